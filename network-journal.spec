@@ -64,9 +64,11 @@ mkdir -p %{buildroot}%{_sysconfdir}/%{name}
 install -m 0600 -p -D %{name}.reference.yml %{buildroot}%{_sysconfdir}/%{name}/%{name}.yml
 mkdir -p %{buildroot}%{_datadir}/%{name}
 install -m 0644 -p -D regexes.yaml %{buildroot}%{_datadir}/%{name}/regexes.yaml
+install -m 0644 -p -D public_suffix_list.dat %{buildroot}%{_datadir}/%{name}/public_suffix_list.dat
 mkdir -p %{buildroot}%{_sysconfdir}/logrotate.d
 install -m 0644 -p -D pkg/%{name}.logrotate %{buildroot}%{_sysconfdir}/logrotate.d/%{name}
 mkdir -p %{buildroot}%{_localstatedir}/log/%{name}
+mkdir -p %{buildroot}%{_sharedstatedir}/%{name}
 install -m 0644 -p -D pkg/%{name}.service %{buildroot}%{_unitdir}/%{name}.service
 
 
@@ -84,13 +86,18 @@ cargo test -r -- --skip reports::tls_cert_validity::tests
 %dir %attr(0755, network-journal, network-journal) %{_sysconfdir}/%{name}
 %config(noreplace) %attr(0600, network-journal, network-journal) %{_sysconfdir}/%{name}/%{name}.yml
 %attr(0644, root, root) %{_datadir}/%{name}/regexes.yaml
+%attr(0644, root, root) %{_datadir}/%{name}/public_suffix_list.dat
 %attr(0644, root, root) %{_unitdir}/%{name}.service
 %dir %attr(0755, network-journal, network-journal) %{_localstatedir}/log/%{name}
 %ghost %attr(0644, network-journal, network-journal) %{_localstatedir}/log/%{name}/%{name}.log
+%dir %attr(0755, network-journal, network-journal) %{_sharedstatedir}/%{name}
 %config %attr(0644, root, root) %{_sysconfdir}/logrotate.d/%{name}
 
 
 %changelog
+* Sun Oct 18 2026 nerou GmbH <info@nerou.de>
+- Add state directory
+
 * Sat Mar 21 2026 nerou GmbH <info@nerou.de>
 - Skip online tests since they might fail due to rate limits etc.
 
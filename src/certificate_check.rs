@@ -0,0 +1,194 @@
+/**
+ * network-journal - collect network reports and print them to file
+ * Copyright (C) 2025 nerou GmbH
+ * 
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{path::PathBuf, sync::{Arc, Mutex, MutexGuard}, thread::{sleep, Builder, JoinHandle}};
+
+use chrono::Utc;
+use log::{error, trace, warn};
+
+use crate::{
+    config::{CertificateCheckConfig, CertificateChecksConfig, NetworkJournalConfig}, 
+    dns::Resolver, 
+    reports::{
+        caa::CAAComplianceReport, 
+        handle_report, 
+        tls_cert_change::CertificateStateStore, 
+        tls_cert_validity::{revocation::RevocationCache, CertificateInfo, TLSCertificateValidityReport}, 
+        tls_configuration::TLSConfigurationReport, 
+        ReportType
+    }, 
+    schedule::Schedule
+};
+
+/// key of the file checks in the persisted last check times
+const FILES_CHECK_KEY: &str = "files";
+
+/**
+ * Spawns a thread per target and one for all certificate files, each running on its own schedule.
+ * Panics on invalid schedules, like on any other invalid configuration.
+ */
+pub fn spawn_certificate_checks(cfg: &NetworkJournalConfig, resolver: &Resolver) -> Vec<JoinHandle<()>> {
+    let checks = Arc::new(cfg.certificate_check.clone());
+    let state_store = Arc::new(Mutex::new(CertificateStateStore::load(&cfg.state_dir).unwrap_or_else(|err| {
+        error!("{}", err);
+        CertificateStateStore::new(&cfg.state_dir)
+    })));
+    let revocation_cache = Arc::new(RevocationCache::new(&cfg.state_dir));
+
+    let mut handles = vec![];
+    for target in &checks.domains {
+        let target = checks.resolve(target);
+        let schedule = match Schedule::new(target.interval, target.schedule.as_deref()) {
+            Ok(schedule) => schedule,
+            Err(err) => panic!("invalid schedule of certificate check {}:{}: {}", target.domain, target.port, err)
+        };
+        let (checks, resolver, state_store, revocation_cache) = (checks.clone(), resolver.clone(), state_store.clone(), revocation_cache.clone());
+        let spawn_res = Builder::new().name(format!("tls_cert_check_{}:{}", target.domain, target.port)).spawn(move || {
+            trace!("TLS certificate check thread for {}:{} started", target.domain, target.port);
+            let key = format!("{}:{}", target.domain, target.port);
+            run_scheduled(&key, &schedule, &state_store, || check_target(&target, &checks, &resolver, &revocation_cache, &state_store));
+        });
+        match spawn_res {
+            Ok(handle) => handles.push(handle),
+            Err(err) => error!("failed to start TLS certificate check thread: {}", err)
+        }
+    }
+
+    let server_cert = if checks.server_certificate { cfg.tls.cert.clone() } else { None };
+    if !checks.files.is_empty() || server_cert.is_some() {
+        let schedule = match Schedule::new(Some(checks.interval), checks.schedule.as_deref()) {
+            Ok(schedule) => schedule,
+            Err(err) => panic!("invalid schedule of certificate checks: {}", err)
+        };
+        let spawn_res = Builder::new().name("tls_cert_check_files".to_string()).spawn(move || {
+            trace!("TLS certificate file check thread started");
+            run_scheduled(FILES_CHECK_KEY, &schedule, &state_store, || check_files(&checks, server_cert.as_ref(), &revocation_cache));
+        });
+        match spawn_res {
+            Ok(handle) => handles.push(handle),
+            Err(err) => error!("failed to start TLS certificate file check thread: {}", err)
+        }
+    }
+    handles
+}
+
+/**
+ * Runs the check whenever it is due according to the schedule and the persisted time of the last check
+ */
+fn run_scheduled<F: FnMut()>(key: &str, schedule: &Schedule, state_store: &Mutex<CertificateStateStore>, mut check: F) {
+    loop {
+        let now = Utc::now();
+        let next_run = schedule.next_run(lock(state_store).last_check(key), now);
+        if let Ok(wait) = (next_run - now).to_std() {
+            trace!("next certificate check of {} at {}", key, next_run);
+            sleep(wait);
+        }
+
+        let started = Utc::now();
+        check();
+
+        let mut store = lock(state_store);
+        store.set_last_check(key, started);
+        if let Err(err) = store.save() {
+            error!("{}", err);
+        }
+    }
+}
+
+/**
+ * A panic in one check thread must not stop the others
+ */
+fn lock(state_store: &Mutex<CertificateStateStore>) -> MutexGuard<'_, CertificateStateStore> {
+    state_store.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn check_target(target: &CertificateCheckConfig, checks: &CertificateChecksConfig, resolver: &Resolver, revocation_cache: &RevocationCache, state_store: &Mutex<CertificateStateStore>) {
+    let mut certificates: Vec<CertificateInfo> = vec![];
+    match TLSCertificateValidityReport::create(target, resolver, revocation_cache) {
+        Ok(results) => {
+            for (address, cert_res) in results {
+                match cert_res {
+                    Ok(cert_opt) => {
+                        match cert_opt {
+                            Some(rpt) => {
+                                if let Err(err) = handle_report(&ReportType::TLSCertificateValidity(&rpt), None, None) {
+                                    error!("{}", err);
+                                }
+                                let changes = lock(state_store).update(&target.domain, target.port, address, &rpt.certificate, checks.renewal_threshold_days);
+                                for change in changes {
+                                    if let Err(err) = handle_report(&ReportType::TLSCertificateChange(&change), None, None) {
+                                        error!("{}", err);
+                                    }
+                                }
+                                if !certificates.iter().any(|cert| cert.fingerprint == rpt.certificate.fingerprint) {
+                                    certificates.push(rpt.certificate);
+                                }
+                            },
+                            None => warn!("no certiticate found for domain {}:{} at {}", target.domain, target.port, address)
+                        }
+                    },
+                    Err(err) => error!("failed to get certificate for domain {}:{} at {}: {}", target.domain, target.port, address, err)
+                }
+            }
+        },
+        Err(err) => error!("failed to resolve domain {}:{}: {}", target.domain, target.port, err)
+    }
+
+    if target.caa {
+        match CAAComplianceReport::create(&target.domain, resolver, &certificates, &checks.caa_issuers, checks.ct_search) {
+            Ok(rpt) => {
+                if let Err(err) = handle_report(&ReportType::CAACompliance(&rpt), None, None) {
+                    error!("{}", err);
+                }
+            },
+            Err(err) => error!("failed to look up CAA records of {}: {}", target.domain, err)
+        }
+    }
+
+    if target.tls_configuration {
+        match TLSConfigurationReport::create(target) {
+            Ok(results) => {
+                for (address, probe_res) in results {
+                    match probe_res {
+                        Ok(rpt) => {
+                            if let Err(err) = handle_report(&ReportType::TLSConfiguration(&rpt), None, None) {
+                                error!("{}", err);
+                            }
+                        },
+                        Err(err) => error!("failed to probe TLS configuration of {}:{} at {}: {}", target.domain, target.port, address, err)
+                    }
+                }
+            },
+            Err(err) => error!("failed to resolve domain {}:{}: {}", target.domain, target.port, err)
+        }
+    }
+}
+
+fn check_files(checks: &CertificateChecksConfig, server_cert: Option<&PathBuf>, revocation_cache: &RevocationCache) {
+    for (path, cert_res) in TLSCertificateValidityReport::create_for_files(checks, server_cert.map(|p| p.as_path()), revocation_cache) {
+        match cert_res {
+            Ok(Some(rpt)) => {
+                if let Err(err) = handle_report(&ReportType::TLSCertificateValidity(&rpt), None, None) {
+                    error!("{}", err);
+                }
+            },
+            Ok(None) => warn!("no certificate found in {}", path.display()),
+            Err(err) => error!("failed to check certificate file {}: {}", path.display(), err)
+        }
+    }
+}
@@ -16,9 +16,10 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::path::PathBuf;
+use std::{collections::HashMap, path::PathBuf};
 
 use config::Config;
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -29,10 +30,38 @@ pub struct NetworkJournalConfig {
     /// defaults to 8080
     pub port: u16,
     pub tls: TlsConfig,
+    /// networks of reverse proxies whose Forwarded, X-Forwarded-For and X-Real-IP headers are trusted, defaults to none
+    pub trusted_proxies: Vec<IpNet>,
+    /// header the trusted proxies write the client address to, others are ignored, defaults to x-forwarded-for
+    pub forwarded_header: ForwardedHeader,
+    /// additional listener expecting the PROXY protocol
+    pub proxy_protocol: ProxyProtocolConfig,
     pub imap: ImapConfig,
     pub filter: FilterConfig,
+    /// group identical browser reports and log one summary per time window
+    pub aggregation: AggregationConfig,
+    /// log only a random share of reports per log type and host
+    pub sampling: SamplingConfig,
+    /// token bucket rate limits of the report endpoints
+    pub rate_limit: RateLimitConfig,
     /// check TLS server certificates for validity
-    pub certificate_check: CertificateChecksConfig
+    pub certificate_check: CertificateChecksConfig,
+    /// check HTTP security and reporting headers of sites
+    pub header_audit: HeaderAuditConfig,
+    /// request URLs regularly and report failures like NEL
+    pub synthetic_probes: SyntheticProbesConfig,
+    /// DNS resolver used for active checks (e.g. DANE)
+    pub dns: DnsConfig,
+    /// reverse DNS lookups of the sources of DMARC and SMTP TLS reports
+    pub reverse_dns: ReverseDnsConfig,
+    /// user agent parsing of incoming reports
+    pub user_agent: UserAgentConfig,
+    /// offline GeoIP and ASN enrichment of IP addresses
+    pub geoip: GeoIpConfig,
+    /// Public Suffix List to derive registrable domains, defaults to /usr/share/network-journal/public_suffix_list.dat
+    pub public_suffix_list: PathBuf,
+    /// directory to persist state in (e.g. last seen certificates), defaults to /var/lib/network-journal
+    pub state_dir: PathBuf
 }
 
 impl NetworkJournalConfig {
@@ -48,6 +77,14 @@ impl NetworkJournalConfig {
             Err(err) => panic!("config file could not be parsed: {}", err)
         }
     }
+
+    /**
+     * Whether any enabled feature queries the `dns` resolver (DANE, CAA or reverse DNS without own resolver)
+     */
+    pub fn needs_resolver(&self) -> bool {
+        self.certificate_check.domains.iter().any(|domain| domain.dane || domain.caa)
+            || (self.reverse_dns.enable && self.reverse_dns.resolver.is_none())
+    }
 }
 
 impl Default for NetworkJournalConfig {
@@ -56,9 +93,29 @@ impl Default for NetworkJournalConfig {
             listen: "127.0.0.1".to_string(),
             port: 8080,
             tls: TlsConfig::default(),
+            trusted_proxies: vec![],
+            forwarded_header: ForwardedHeader::XForwardedFor,
+            proxy_protocol: ProxyProtocolConfig::default(),
             imap: ImapConfig::default(),
             filter: FilterConfig::default(),
-            certificate_check: CertificateChecksConfig::default()
+            aggregation: AggregationConfig::default(),
+            sampling: SamplingConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            certificate_check: CertificateChecksConfig::default(),
+            header_audit: HeaderAuditConfig::default(),
+            synthetic_probes: SyntheticProbesConfig::default(),
+            dns: DnsConfig::default(),
+            reverse_dns: ReverseDnsConfig::default(),
+            user_agent: UserAgentConfig::default(),
+            geoip: GeoIpConfig::default(),
+            #[cfg(debug_assertions)]
+            public_suffix_list: PathBuf::from("./public_suffix_list.dat"),
+            #[cfg(not(debug_assertions))]
+            public_suffix_list: PathBuf::from("/usr/share/network-journal/public_suffix_list.dat"),
+            #[cfg(debug_assertions)]
+            state_dir: PathBuf::from("./state"),
+            #[cfg(not(debug_assertions))]
+            state_dir: PathBuf::from("/var/lib/network-journal")
         }
     }
 }
@@ -83,6 +140,43 @@ impl Default for TlsConfig {
     }
 }
 
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ForwardedHeader {
+    /// `Forwarded` (RFC 7239)
+    Forwarded,
+    #[default]
+    XForwardedFor,
+    XRealIp
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ProxyProtocolConfig {
+    /// defaults to false
+    pub enable: bool,
+    /// listen address, defaults to 127.0.0.1
+    pub listen: String,
+    /// defaults to 8081
+    pub port: u16,
+    /// networks of proxies allowed to connect, defaults to 127.0.0.0/8 and ::1/128
+    pub allowed_sources: Vec<IpNet>,
+    /// seconds to wait for the PROXY header, defaults to 5
+    pub header_timeout: u64
+}
+
+impl Default for ProxyProtocolConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            listen: "127.0.0.1".to_string(),
+            port: 8081,
+            allowed_sources: vec!["127.0.0.0/8".parse().unwrap(), "::1/128".parse().unwrap()],
+            header_timeout: 5
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ImapConfig {
     /// default false
@@ -113,36 +207,139 @@ impl Default for ImapConfig {
 pub struct FilterConfig {
     /// empty list allows all domains
     #[serde(default)]
-    pub domain_whitelist: Vec<DomainConfigType>
+    pub domain_whitelist: Vec<DomainConfigType>,
+    /// domains, wildcards (e.g. `*.example.com`) and IP networks to drop reports of, takes precedence over the whitelist
+    #[serde(default)]
+    pub domain_blacklist: Vec<DomainConfigType>,
+    /// match the registrable domain (eTLD+1) of hosts against the whitelist as well, defaults to false
+    #[serde(default)]
+    pub match_registrable_domain: bool,
+    /// classification of reports sent by bots and crawlers
+    #[serde(default)]
+    pub bots: BotFilterConfig,
+    /// organizations and domains allowed or denied to send DMARC and SMTP TLS reports
+    #[serde(default)]
+    pub reporters: ReporterFilterConfig,
+    /// ordered rules to allow or deny reports, the first matching rule applies
+    #[serde(default)]
+    pub rules: Vec<FilterRuleConfig>,
+    /// apply the shipped rules against browser extension and ad injector noise after the configured ones, defaults to true
+    #[serde(default = "default_true")]
+    pub default_rules: bool,
+    /// seconds between two logs of the rule hit counters (Filter-Statistics), 0 disables them, defaults to 3600
+    #[serde(default = "default_filter_statistics_interval")]
+    pub statistics_interval: u64
 }
 
-impl FilterConfig {
-    pub fn contains_domain(&self, domain: &str) -> bool {
-        for item in self.domain_whitelist.iter() {
-            let contained = match item {
-                DomainConfigType::Simple(d) => domain == d,
-                DomainConfigType::Complex(d) => {
-                    let mut subdomain_pattern = d.domain.clone();
-                    subdomain_pattern.insert_str(0, ".");
-                    d.domain == domain || (d.include_subdomains && domain.ends_with(&subdomain_pattern))
-                }
-            };
-            if contained {
-                return true;
-            }
+impl Default for FilterConfig {
+    fn default() -> Self {
+        Self {
+            domain_whitelist: vec![],
+            domain_blacklist: vec![],
+            match_registrable_domain: false,
+            bots: BotFilterConfig::default(),
+            reporters: ReporterFilterConfig::default(),
+            rules: vec![],
+            default_rules: true,
+            statistics_interval: default_filter_statistics_interval()
         }
-        return false;
     }
 }
 
-impl Default for FilterConfig {
+fn default_true() -> bool {
+    true
+}
+
+fn default_filter_statistics_interval() -> u64 {
+    3600
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FilterRuleConfig {
+    /// name used in the hit counters
+    pub name: String,
+    pub action: FilterRuleAction,
+    /// log types (e.g. CSP, NEL) the rule applies to, defaults to all
+    #[serde(default)]
+    pub report_types: Vec<String>,
+    /// conditions which all have to match, defaults to none i.e. every report of the report types matches
+    #[serde(default)]
+    pub conditions: Vec<FilterConditionConfig>
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterRuleAction {
+    /// log the report without evaluating further rules
+    Allow,
+    /// do not log the report
+    Deny
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct FilterConditionConfig {
+    /// dot separated path of a field in the logged JSON e.g. report.body.blockedUrl or derived.client.family, 
+    /// `*` matches any key or array element
+    pub field: String,
+    /// exactly one of equals, regex or glob
+    pub equals: Option<String>,
+    pub regex: Option<String>,
+    /// `*` matches any characters, `?` matches one character
+    pub glob: Option<String>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct BotFilterConfig {
+    /// regular expressions of user agents to classify as bot in addition to known crawlers and headless browsers
+    pub user_agents: Vec<String>,
+    /// IP ranges (CIDR notation) of reporters to classify as bot e.g. 192.0.2.0/24
+    pub ip_ranges: Vec<IpNet>,
+    /// what to do with reports of bots, defaults to tag
+    pub action: BotAction
+}
+
+impl Default for BotFilterConfig {
     fn default() -> Self {
         Self {
-            domain_whitelist: vec![]
+            user_agents: vec![],
+            ip_ranges: vec![],
+            action: BotAction::Tag
         }
     }
 }
 
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum BotAction {
+    /// log the report with the bot classification in `derived.bot`
+    Tag,
+    /// do not log the report
+    Drop
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct ReporterFilterConfig {
+    /// reporters to accept exclusively, empty lists allow all reporters
+    pub allow: ReporterListConfig,
+    /// reporters to drop reports of, takes precedence over allow
+    pub deny: ReporterListConfig,
+    /// accept DMARC reports only from e-mails with a DKIM, SPF or DMARC pass for the domain of the reporter's address, defaults to false
+    pub require_authentication: bool,
+    /// authserv-ids of the Authentication-Results headers to trust (i.e. those added by your own MTA), required if `require_authentication` is set
+    pub authserv_ids: Vec<String>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct ReporterListConfig {
+    /// organization names (`org_name` of DMARC, `organization-name` of SMTP TLS reports), compared case-insensitively
+    pub organizations: Vec<String>,
+    /// domains of the reporter's contact address (`email` of DMARC, `contact-info` of SMTP TLS reports)
+    pub domains: Vec<DomainConfigType>
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
 #[serde(untagged)]
 pub enum DomainConfigType {
@@ -161,25 +358,400 @@ pub struct DomainConfig {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct CertificateChecksConfig {
     #[serde(default)]
-    pub domains: Vec<CertificateCheckConfig>
+    pub domains: Vec<CertificateCheckConfig>,
+    /// seconds between two checks, defaults to 86400 (daily)
+    #[serde(default = "default_certificate_check_interval")]
+    pub interval: u64,
+    /// cron expression (e.g. `0 6 * * *`) to run the checks at, takes precedence over `interval`
+    #[serde(default)]
+    pub schedule: Option<String>,
+    /// TCP connect timeout in seconds, defaults to 10
+    #[serde(default = "default_certificate_check_timeout")]
+    pub connect_timeout: u64,
+    /// timeout in seconds for reads and writes during STARTTLS and the TLS handshake, defaults to 10
+    #[serde(default = "default_certificate_check_timeout")]
+    pub handshake_timeout: u64,
+    /// report a `warning` severity for certificates expiring within this many days, defaults to 30
+    #[serde(default = "default_warning_days")]
+    pub warning_days: i64,
+    /// report a `critical` severity for certificates expiring within this many days, defaults to 7
+    #[serde(default = "default_critical_days")]
+    pub critical_days: i64,
+    /// report certificates that have not been renewed this many days before expiry, defaults to 14
+    #[serde(default = "default_renewal_threshold_days")]
+    pub renewal_threshold_days: i64,
+    /// additional CAA issuer domain names and the issuer organization names of their certificates, e.g. `ca.example.com: ["Example CA"]`
+    #[serde(default)]
+    pub caa_issuers: HashMap<String, Vec<String>>,
+    /// include unexpired certificates logged in certificate transparency logs (crt.sh) in CAA checks, defaults to false
+    #[serde(default)]
+    pub ct_search: bool,
+    /// PEM or DER encoded certificate files and directories containing such files (*.pem, *.crt, *.cer, *.der)
+    #[serde(default)]
+    pub files: Vec<PathBuf>,
+    /// check the certificate configured in the `tls` section as well, defaults to false
+    #[serde(default)]
+    pub server_certificate: bool
 }
 
 impl Default for CertificateChecksConfig {
     fn default() -> Self {
         Self {
-            domains: vec![]
+            domains: vec![],
+            interval: default_certificate_check_interval(),
+            schedule: None,
+            connect_timeout: default_certificate_check_timeout(),
+            handshake_timeout: default_certificate_check_timeout(),
+            warning_days: default_warning_days(),
+            critical_days: default_critical_days(),
+            renewal_threshold_days: default_renewal_threshold_days(),
+            caa_issuers: HashMap::new(),
+            ct_search: false,
+            files: vec![],
+            server_certificate: false
+        }
+    }
+}
+
+impl CertificateChecksConfig {
+    /**
+     * Returns a copy of the target with all unset settings taken from the global ones
+     */
+    pub fn resolve(&self, target: &CertificateCheckConfig) -> CertificateCheckConfig {
+        let mut resolved = target.clone();
+        // a schedule or interval of the target replaces both global ones
+        if target.schedule.is_none() && target.interval.is_none() {
+            resolved.schedule = self.schedule.clone();
+            resolved.interval = Some(self.interval);
         }
+        resolved.connect_timeout = target.connect_timeout.or(Some(self.connect_timeout));
+        resolved.handshake_timeout = target.handshake_timeout.or(Some(self.handshake_timeout));
+        resolved.warning_days = target.warning_days.or(Some(self.warning_days));
+        resolved.critical_days = target.critical_days.or(Some(self.critical_days));
+        resolved
     }
 }
 
+fn default_renewal_threshold_days() -> i64 {
+    14
+}
+
+pub fn default_certificate_check_interval() -> u64 {
+    86400
+}
+
+pub fn default_certificate_check_timeout() -> u64 {
+    10
+}
+
+pub fn default_warning_days() -> i64 {
+    30
+}
+
+pub fn default_critical_days() -> i64 {
+    7
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct CertificateCheckConfig {
     pub domain: String,
     /// defaults to 443
     #[serde(default = "default_certificate_check_port")]
-    pub port: u16
+    pub port: u16,
+    /// issue SMTP STARTTLS before the TLS handshake (e.g. for MX hosts on port 25), defaults to false
+    #[serde(default)]
+    pub starttls: bool,
+    /// match the certificate chain against the TLSA records of `_<port>._tcp.<domain>`, defaults to false
+    #[serde(default)]
+    pub dane: bool,
+    /// probe supported protocol versions and cipher suites, defaults to false
+    #[serde(default)]
+    pub tls_configuration: bool,
+    /// check certificate issuers against the CAA records of the domain, defaults to false
+    #[serde(default)]
+    pub caa: bool,
+    /// seconds between two checks of this target, defaults to the global `interval`
+    #[serde(default)]
+    pub interval: Option<u64>,
+    /// cron expression to check this target at, defaults to the global `schedule`
+    #[serde(default)]
+    pub schedule: Option<String>,
+    /// defaults to the global `connect_timeout`
+    #[serde(default)]
+    pub connect_timeout: Option<u64>,
+    /// defaults to the global `handshake_timeout`
+    #[serde(default)]
+    pub handshake_timeout: Option<u64>,
+    /// defaults to the global `warning_days`
+    #[serde(default)]
+    pub warning_days: Option<i64>,
+    /// defaults to the global `critical_days`
+    #[serde(default)]
+    pub critical_days: Option<i64>
 }
 
 fn default_certificate_check_port() -> u16 {
     443
 }
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct HeaderAuditConfig {
+    pub urls: Vec<HeaderAuditTargetConfig>,
+    /// base URL of this instance (e.g. https://network-journal.example.com), all reporting endpoints have to start with
+    pub endpoint: Option<String>,
+    /// headers every URL is expected to send, defaults to all
+    pub headers: Vec<AuditedHeader>,
+    /// seconds between two audits, defaults to 86400 (daily)
+    pub interval: u64,
+    /// cron expression (e.g. `0 6 * * *`) to run the audit at, takes precedence over `interval`
+    pub schedule: Option<String>,
+    /// request timeout in seconds, defaults to 10
+    pub timeout: u64
+}
+
+impl Default for HeaderAuditConfig {
+    fn default() -> Self {
+        Self {
+            urls: vec![],
+            endpoint: None,
+            headers: vec![
+                AuditedHeader::ReportingEndpoints,
+                AuditedHeader::Nel,
+                AuditedHeader::Csp,
+                AuditedHeader::Coop,
+                AuditedHeader::Coep,
+                AuditedHeader::IntegrityPolicy
+            ],
+            interval: 86400,
+            schedule: None,
+            timeout: 10
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HeaderAuditTargetConfig {
+    pub url: String,
+    /// headers this URL is expected to send, defaults to the global `headers`
+    #[serde(default)]
+    pub headers: Option<Vec<AuditedHeader>>
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuditedHeader {
+    /// `Reporting-Endpoints` or `Report-To`
+    ReportingEndpoints,
+    Nel,
+    /// `Content-Security-Policy` or `Content-Security-Policy-Report-Only`
+    Csp,
+    /// `Cross-Origin-Opener-Policy` or `Cross-Origin-Opener-Policy-Report-Only`
+    Coop,
+    /// `Cross-Origin-Embedder-Policy` or `Cross-Origin-Embedder-Policy-Report-Only`
+    Coep,
+    /// `Integrity-Policy` or `Integrity-Policy-Report-Only`
+    IntegrityPolicy
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SyntheticProbesConfig {
+    /// HTTP(S) URLs to request
+    pub urls: Vec<String>,
+    /// seconds between two probes, defaults to 300
+    pub interval: u64,
+    /// cron expression (e.g. `*/5 * * * *`) to probe at, takes precedence over `interval`
+    pub schedule: Option<String>,
+    /// timeout in seconds of each phase (connect, TLS handshake, response), defaults to 10
+    pub timeout: u64
+}
+
+impl Default for SyntheticProbesConfig {
+    fn default() -> Self {
+        Self {
+            urls: vec![],
+            interval: 300,
+            schedule: None,
+            timeout: 10
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct DnsConfig {
+    /// address of a DNSSEC validating resolver e.g. 127.0.0.1:53, defaults to the first nameserver in /etc/resolv.conf
+    pub resolver: Option<String>,
+    /// query timeout in seconds, defaults to 5
+    pub timeout: u64
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        Self {
+            resolver: None,
+            timeout: 5
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ReverseDnsConfig {
+    /// defaults to false
+    pub enable: bool,
+    /// address of the resolver to send PTR and A/AAAA queries to, defaults to the resolver of the DNS config
+    pub resolver: Option<String>,
+    /// seconds to cache the result of an address, defaults to 3600
+    pub cache_ttl: u64,
+    /// addresses to cache, defaults to 10000
+    pub cache_size: usize
+}
+
+impl Default for ReverseDnsConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            resolver: None,
+            cache_ttl: 3600,
+            cache_size: 10000
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct AggregationConfig {
+    /// defaults to false
+    pub enable: bool,
+    /// seconds of the window reports are grouped in, defaults to 60
+    pub window: u64,
+    /// fields of the logged JSON (dotted paths like filter rule conditions) that, together with the log type, form the group key
+    pub keys: Vec<String>,
+    /// log types of browser reports to log individually, defaults to none
+    pub pass_through: Vec<String>,
+    /// groups per window, further reports are logged individually, defaults to 10000
+    pub max_groups: usize
+}
+
+impl Default for AggregationConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            window: 60,
+            keys: vec![
+                "derived.url.host".to_string(),
+                "report.*.type".to_string(),
+                "report.*.effectiveDirective".to_string(),
+                "report.*.blockedUrl".to_string(),
+                "report.*.sourceFile".to_string(),
+                "derived.client.family".to_string()
+            ],
+            pass_through: vec![],
+            max_groups: 10000
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct SamplingConfig {
+    /// rates (0 to 1) of reports to log per log type e.g. CSP: 0.1, defaults to none i.e. all reports are logged
+    pub report_types: HashMap<String, f64>,
+    /// rate of NEL reports of successful requests (type ok) overriding the rate of NEL, defaults to none
+    pub nel_success: Option<f64>,
+    /// rates of hosts including their subdomains (the most specific one applies), multiplied with the rate of the log type
+    pub hosts: HashMap<String, f64>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// defaults to false
+    pub enable: bool,
+    /// limit of all requests, defaults to none
+    pub global: Option<RateLimit>,
+    /// limit of requests per client IP address, defaults to 10 per second with a burst of 100
+    pub client: Option<RateLimit>,
+    /// IPv6 clients share the client bucket of their network of this prefix length (a single host usually holds a whole /64), defaults to 64
+    pub client_ipv6_prefix: u8,
+    /// limit of reports per reported host, defaults to 100 per second with a burst of 1000
+    pub host: Option<RateLimit>,
+    /// limits of endpoints (e.g. /csp) with buckets of their own, overriding the limits above
+    pub endpoints: HashMap<String, EndpointRateLimitConfig>,
+    /// seconds between two logs of the dropped report counters (Rate-Limit-Statistics), 0 disables them, defaults to 3600
+    pub statistics_interval: u64
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            global: None,
+            client: Some(RateLimit { rate: 10.0, burst: 100.0 }),
+            client_ipv6_prefix: 64,
+            host: Some(RateLimit { rate: 100.0, burst: 1000.0 }),
+            endpoints: HashMap::new(),
+            statistics_interval: 3600
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct EndpointRateLimitConfig {
+    pub global: Option<RateLimit>,
+    pub client: Option<RateLimit>,
+    pub host: Option<RateLimit>
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub struct RateLimit {
+    /// tokens (requests or reports) added per second
+    pub rate: f64,
+    /// size of the bucket i.e. the number of requests allowed at once
+    pub burst: f64
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct UserAgentConfig {
+    /// uap-core regexes used to parse user agents, defaults to /usr/share/network-journal/regexes.yaml
+    pub regexes: PathBuf,
+    /// seconds between two checks of the regexes for changes, 0 disables the check, defaults to 60
+    pub reload_interval: u64
+}
+
+impl Default for UserAgentConfig {
+    fn default() -> Self {
+        Self {
+            #[cfg(debug_assertions)]
+            regexes: PathBuf::from("./regexes.yaml"),
+            #[cfg(not(debug_assertions))]
+            regexes: PathBuf::from("/usr/share/network-journal/regexes.yaml"),
+            reload_interval: 60
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct GeoIpConfig {
+    /// MaxMind DB (.mmdb) of countries and cities e.g. GeoLite2-City, defaults to none
+    pub location: Option<PathBuf>,
+    /// MaxMind DB (.mmdb) of autonomous systems e.g. GeoLite2-ASN, defaults to none
+    pub asn: Option<PathBuf>,
+    /// seconds between two checks of the databases for changes, 0 disables the check, defaults to 300
+    pub reload_interval: u64
+}
+
+impl Default for GeoIpConfig {
+    fn default() -> Self {
+        Self {
+            location: None,
+            asn: None,
+            reload_interval: 300
+        }
+    }
+}
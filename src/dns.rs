@@ -0,0 +1,224 @@
+/**
+ * network-journal - collect network reports and print them to file
+ * Copyright (C) 2025 nerou GmbH
+ * 
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{fmt::Display, fs, io::{self, Read, Write}, net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket}, time::Duration};
+
+use hickory_proto::{
+    error::ProtoError,
+    op::{Edns, Message, MessageType, OpCode, Query, ResponseCode},
+    rr::{Name, RData, RecordType}
+};
+use log::warn;
+
+use crate::config::DnsConfig;
+
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+const FALLBACK_RESOLVER: &str = "127.0.0.1:53";
+const MAX_UDP_PAYLOAD: u16 = 1232;
+
+/**
+ * Minimal blocking stub resolver that sends all queries to one configured (recursive) resolver.
+ * The DNSSEC status is taken from the AD flag of the response, so the configured resolver has to validate.
+ */
+#[derive(Clone, Debug)]
+pub struct Resolver {
+    server: SocketAddr,
+    timeout: Duration
+}
+
+#[derive(Debug)]
+pub struct Answer {
+    pub records: Vec<RData>,
+    /// AD flag of the response, i.e. the resolver validated the answer using DNSSEC
+    pub authenticated: bool
+}
+
+impl Resolver {
+
+    pub fn new(config: &DnsConfig) -> Result<Self, Error> {
+        let address = match &config.resolver {
+            Some(resolver) => resolver.clone(),
+            None => Self::read_system_resolver().unwrap_or_else(|| FALLBACK_RESOLVER.to_string())
+        };
+        let server = if let Ok(ip) = address.parse() {
+            SocketAddr::new(ip, 53)
+        } else {
+            match address.to_socket_addrs()?.next() {
+                Some(addr) => addr,
+                None => return Err(Error::InvalidResolver(address))
+            }
+        };
+        Ok(Self {
+            server,
+            timeout: Duration::from_secs(config.timeout)
+        })
+    }
+
+    /**
+     * Resolver using `FALLBACK_RESOLVER`, e.g. if the configured one is invalid, but not needed
+     */
+    pub fn fallback(config: &DnsConfig) -> Self {
+        Self {
+            server: FALLBACK_RESOLVER.parse().unwrap(),
+            timeout: Duration::from_secs(config.timeout)
+        }
+    }
+
+    fn read_system_resolver() -> Option<String> {
+        parse_resolv_conf(&fs::read_to_string(RESOLV_CONF).ok()?)
+    }
+
+    /**
+     * An empty answer is returned for NXDOMAIN as well as NOERROR without matching records
+     */
+    pub fn query(&self, name: &str, record_type: RecordType) -> Result<Answer, Error> {
+        let mut request = Message::new();
+        request
+            .set_id(rand_id())
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(true)
+            .set_authentic_data(true)
+            .add_query(Query::query(Name::from_ascii(name)?, record_type));
+        let mut edns = Edns::new();
+        edns.set_max_payload(MAX_UDP_PAYLOAD).set_dnssec_ok(true);
+        request.set_edns(edns);
+        let request_bytes = request.to_vec()?;
+
+        let mut response = self.query_udp(&request_bytes)?;
+        if response.truncated() {
+            response = self.query_tcp(&request_bytes)?;
+        }
+        if response.id() != request.id() {
+            return Err(Error::IdMismatch);
+        }
+
+        match response.response_code() {
+            ResponseCode::NoError | ResponseCode::NXDomain => Ok(Answer {
+                authenticated: response.authentic_data(),
+                records: response.answers().iter()
+                    .filter(|record| record.record_type() == record_type)
+                    .filter_map(|record| record.data().cloned())
+                    .collect()
+            }),
+            code => Err(Error::ResponseCode(code))
+        }
+    }
+
+    fn query_udp(&self, request: &[u8]) -> Result<Message, Error> {
+        let bind_addr = if self.server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = UdpSocket::bind(bind_addr)?;
+        socket.set_read_timeout(Some(self.timeout))?;
+        socket.connect(self.server)?;
+        socket.send(request)?;
+
+        let mut buf = vec![0u8; MAX_UDP_PAYLOAD as usize];
+        let len = socket.recv(&mut buf)?;
+        Ok(Message::from_vec(&buf[..len])?)
+    }
+
+    fn query_tcp(&self, request: &[u8]) -> Result<Message, Error> {
+        let mut stream = TcpStream::connect_timeout(&self.server, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let mut framed = (request.len() as u16).to_be_bytes().to_vec();
+        framed.extend_from_slice(request);
+        stream.write_all(&framed)?;
+
+        let mut len_buf = [0u8; 2];
+        stream.read_exact(&mut len_buf)?;
+        let mut buf = vec![0u8; u16::from_be_bytes(len_buf) as usize];
+        stream.read_exact(&mut buf)?;
+        Ok(Message::from_vec(&buf)?)
+    }
+}
+
+/**
+ * Returns the first nameserver of a resolv.conf, which can be queried directly.
+ * Scoped (link-local) addresses like fe80::1%eth0 are skipped.
+ */
+fn parse_resolv_conf(content: &str) -> Option<String> {
+    content.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            match (fields.next(), fields.next()) {
+                (Some("nameserver"), Some(ns)) => ns.parse::<IpAddr>().ok(),
+                _ => None
+            }
+        })
+        .map(|ip| ip.to_string())
+        .next()
+}
+
+/**
+ * Query IDs do not need to be cryptographically secure here, but should not be predictable either
+ */
+fn rand_id() -> u16 {
+    let mut buf = [0u8; 2];
+    if openssl::rand::rand_bytes(&mut buf).is_err() {
+        warn!("failed to generate random DNS query ID");
+    }
+    u16::from_ne_bytes(buf)
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Proto(ProtoError),
+    ResponseCode(ResponseCode),
+    IdMismatch,
+    InvalidResolver(String)
+}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<ProtoError> for Error {
+    fn from(value: ProtoError) -> Self {
+        Self::Proto(value)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "DNS query failed: {}", e),
+            Self::Proto(e) => write!(f, "invalid DNS message: {}", e),
+            Self::ResponseCode(code) => write!(f, "DNS query failed with response code {}", code),
+            Self::IdMismatch => write!(f, "DNS response ID does not match the query"),
+            Self::InvalidResolver(addr) => write!(f, "invalid DNS resolver address \"{}\"", addr)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn system_resolver() {
+        assert_eq!(parse_resolv_conf("# comment\nsearch example.com\nnameserver 192.0.2.53\nnameserver 192.0.2.54\n"), Some("192.0.2.53".to_string()));
+        assert_eq!(parse_resolv_conf("nameserver fe80::1%eth0\nnameserver\t2001:db8::53\n"), Some("2001:db8::53".to_string()));
+        assert_eq!(parse_resolv_conf("nameservers 192.0.2.1\nnameserver invalid\n"), None);
+        assert_eq!(parse_resolv_conf(""), None);
+    }
+}
@@ -16,25 +16,31 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::{path::PathBuf, thread::{sleep, Builder}, time::Duration};
+use std::{net::{Ipv4Addr, TcpListener}, path::PathBuf, sync::mpsc::{channel, RecvTimeoutError}, thread::{sleep, Builder}, time::Duration};
 
 use actix_cors::Cors;
-use actix_web::{dev::Service, guard::{self, Header}, http::header::{self, HeaderValue}, main, web::{resource, Data, Payload}, App, HttpServer};
+use actix_web::{dev::Service, error::ErrorForbidden, guard::{self, Header}, http::header::{self, HeaderValue}, main, rt::{signal::unix::{signal, SignalKind}, spawn}, web::{resource, Data, Payload}, App, HttpServer};
 use clap::{crate_name, crate_version, Parser};
-use futures_util::future::FutureExt;
-use log::{error, trace, warn, LevelFilter};
-use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
+use futures_util::future::{ready, FutureExt};
+use log::{error, info, trace, warn, LevelFilter};
+use openssl::ssl::{SslAcceptor, SslAcceptorBuilder, SslFiletype, SslMethod};
 use simple_logger::SimpleLogger;
+use url::Url;
 
 use crate::{
-    config::NetworkJournalConfig, processing::filter::Filter, reports::{
-        csp::report_csp, dmarc::IMAPClient, handle_report, reporting_api::reporting_api, smtp_tls::report_smtp_tls, tls_cert_validity::TLSCertificateValidityReport, ReportType
-    }
+    certificate_check::spawn_certificate_checks, config::{DnsConfig, NetworkJournalConfig, TlsConfig}, dns::Resolver, processing::{aggregation::Aggregator, client_ip::TrustedProxies, derivation::UserAgentParser, filter::Filter, geoip::GeoIp, public_suffix::PublicSuffixList, rate_limit::RateLimiter, reverse_dns::ReverseDns, sampling::Sampler, Processor}, proxy_protocol::{ProxiedPeers, ProxyListener}, reports::{
+        csp::report_csp, dmarc::IMAPClient, handle_report, header_audit::HeaderAuditReport, reporting_api::reporting_api, smtp_tls::report_smtp_tls, synthetic_probe::SyntheticProbeReport, ReportType
+    }, schedule::Schedule
 };
 
+mod certificate_check;
 mod config;
+mod dns;
 mod reports;
 mod processing;
+mod proxy_protocol;
+mod schedule;
+mod structured_field;
 
 #[derive(Parser, Debug)]
 #[command(version, author, about, long_about = "Copyright (C) 2026 nerou GmbH This program comes with ABSOLUTELY NO WARRANTY. This is free software, and you are welcome to redistribute it under certain conditions.")]
@@ -45,7 +51,7 @@ struct Args {
 }
 
 struct WebState {
-    filter: Filter
+    processor: Processor
 }
 
 async fn get_body_as_string(body: Payload) -> Result<String, String> {
@@ -72,39 +78,243 @@ async fn main() -> std::io::Result<()> {
 
     let cfg = NetworkJournalConfig::read(args.config.to_str().unwrap());
 
-    let _tls_cert_check_thread_handle = if !cfg.certificate_check.domains.is_empty() {
-        Some(Builder::new().name("tls_cert_check".to_string()).spawn(move || {
-            trace!("TLS certificate check thread started");
+    let resolver = match Resolver::new(&cfg.dns) {
+        Ok(resolver) => resolver,
+        Err(err) if cfg.needs_resolver() => panic!("DNS resolver could not be configured: {}", err),
+        Err(err) => {
+            warn!("DNS resolver could not be configured, but is not used: {}", err);
+            Resolver::fallback(&cfg.dns)
+        }
+    };
+
+    let user_agent_parser = match UserAgentParser::new(&cfg.user_agent.regexes, &cfg.state_dir) {
+        Ok(parser) => parser,
+        Err(err) => panic!("user agent parser could not be configured: {}", err)
+    };
+
+    let public_suffix_list = match PublicSuffixList::load(&cfg.public_suffix_list) {
+        Ok(list) => list,
+        Err(err) => panic!("public suffix list could not be loaded: {}", err)
+    };
+
+    let geoip = match GeoIp::new(&cfg.geoip) {
+        Ok(geoip) => geoip,
+        Err(err) => panic!("GeoIP databases could not be loaded: {}", err)
+    };
+
+    // reload the user agent regexes, the public suffix list and the GeoIP databases on SIGHUP, the regexes also if they changed
+    let (reload_sender, reload_receiver) = channel::<()>();
+    let mut hangup = signal(SignalKind::hangup())?;
+    spawn(async move {
+        while hangup.recv().await.is_some() {
+            if reload_sender.send(()).is_err() {
+                break;
+            }
+        }
+    });
+    let user_agent_parser_reload = user_agent_parser.clone();
+    let public_suffix_list_reload = public_suffix_list.clone();
+    let geoip_reload = geoip.clone();
+    let user_agent_reload_interval = cfg.user_agent.reload_interval;
+    let _reload_thread_handle = Builder::new().name("reload".to_string()).spawn(move || {
+        trace!("reload thread started");
+
+        loop {
+            let hangup = if user_agent_reload_interval == 0 {
+                match reload_receiver.recv() {
+                    Ok(_) => true,
+                    Err(_) => break
+                }
+            } else {
+                match reload_receiver.recv_timeout(Duration::from_secs(user_agent_reload_interval)) {
+                    Ok(_) => true,
+                    Err(RecvTimeoutError::Timeout) => false,
+                    Err(RecvTimeoutError::Disconnected) => break
+                }
+            };
+            let reload_res = if hangup {
+                user_agent_parser_reload.reload().map(|_| true)
+            } else {
+                user_agent_parser_reload.reload_if_changed()
+            };
+            match reload_res {
+                Ok(true) => info!("user agent regexes reloaded"),
+                Ok(false) => {},
+                Err(err) => error!("failed to reload user agent regexes, keeping the previous ones: {}", err)
+            }
+            if hangup {
+                match public_suffix_list_reload.reload() {
+                    Ok(_) => info!("public suffix list reloaded"),
+                    Err(err) => error!("failed to reload public suffix list, keeping the previous one: {}", err)
+                }
+                if geoip_reload.is_enabled() {
+                    match geoip_reload.reload() {
+                        Ok(_) => info!("GeoIP databases reloaded"),
+                        Err(err) => error!("failed to reload GeoIP databases, keeping the previous ones: {}", err)
+                    }
+                }
+            }
+        }
+    });
 
+    let _geoip_reload_thread_handle = if geoip.is_enabled() && cfg.geoip.reload_interval > 0 {
+        let geoip_reload = geoip.clone();
+        let interval = Duration::from_secs(cfg.geoip.reload_interval);
+        Some(Builder::new().name("geoip_reload".to_string()).spawn(move || {
             loop {
-                for domain in &cfg.certificate_check.domains {
-                    let cert_res = TLSCertificateValidityReport::create(domain.domain.as_str(), domain.port);
-                    match cert_res {
-                        Ok(cert_opt) => {
-                            match cert_opt {
-                                Some(rpt) => {
-                                    //println!("{:?}", rpt.certificate);
-                                    if let Err(err) = handle_report(&ReportType::TLSCertificateValidity(&rpt), None, None) {
-                                        error!("{}", err);
-                                    }
-                                },
-                                None => warn!("no certiticate found for domain {}:{}", domain.domain, domain.port)
+                sleep(interval);
+                match geoip_reload.reload_if_changed() {
+                    Ok(true) => info!("GeoIP databases reloaded"),
+                    Ok(false) => {},
+                    Err(err) => error!("failed to reload GeoIP databases, keeping the previous ones: {}", err)
+                }
+            }
+        }))
+    } else {
+        None
+    };
+
+    let filter = match Filter::new(cfg.filter.clone(), public_suffix_list.clone()) {
+        Ok(filter) => filter,
+        Err(err) => panic!("filter could not be configured: {}", err)
+    };
+    if cfg.aggregation.enable && cfg.aggregation.window == 0 {
+        panic!("aggregation window must be at least one second");
+    }
+    let rate_limiter = match RateLimiter::new(cfg.rate_limit.clone()) {
+        Ok(rate_limiter) => rate_limiter,
+        Err(err) => panic!("rate limits could not be configured: {}", err)
+    };
+    let sampler = match Sampler::new(cfg.sampling.clone()) {
+        Ok(sampler) => sampler,
+        Err(err) => panic!("sampling could not be configured: {}", err)
+    };
+    let reverse_dns_resolver = match cfg.reverse_dns.resolver.as_ref() {
+        Some(address) if cfg.reverse_dns.enable => Resolver::new(&DnsConfig { resolver: Some(address.clone()), ..cfg.dns.clone() }),
+        Some(_) => Ok(Resolver::fallback(&cfg.dns)),
+        None => Ok(resolver.clone())
+    };
+    let reverse_dns = match reverse_dns_resolver {
+        Ok(resolver) => ReverseDns::new(&cfg.reverse_dns, resolver),
+        Err(err) => panic!("reverse DNS resolver could not be configured: {}", err)
+    };
+    let processor = Processor {
+        filter,
+        user_agent_parser,
+        public_suffix_list,
+        aggregator: Aggregator::new(cfg.aggregation.clone()),
+        rate_limiter,
+        sampler,
+        geoip,
+        reverse_dns,
+        trusted_proxies: TrustedProxies::new(cfg.trusted_proxies.clone(), cfg.forwarded_header),
+        proxied_peers: ProxiedPeers::default()
+    };
+
+    let _filter_statistics_thread_handle = if cfg.filter.statistics_interval > 0 && processor.filter.has_rules() {
+        let filter_statistics = processor.filter.clone();
+        let interval = Duration::from_secs(cfg.filter.statistics_interval);
+        Some(Builder::new().name("filter_statistics".to_string()).spawn(move || {
+            loop {
+                sleep(interval);
+                match serde_json::to_string_pretty(&filter_statistics.rule_statistics()) {
+                    Ok(statistics) => info!("Filter-Statistics {}", statistics),
+                    Err(err) => error!("failed to serialize filter statistics: {}", err)
+                }
+            }
+        }))
+    } else {
+        None
+    };
+
+    let _rate_limit_statistics_thread_handle = if cfg.rate_limit.statistics_interval > 0 && processor.rate_limiter.is_enabled() {
+        let rate_limiter = processor.rate_limiter.clone();
+        let interval = Duration::from_secs(cfg.rate_limit.statistics_interval);
+        Some(Builder::new().name("rate_limit_statistics".to_string()).spawn(move || {
+            loop {
+                sleep(interval);
+                match serde_json::to_string_pretty(&rate_limiter.statistics()) {
+                    Ok(statistics) => info!("Rate-Limit-Statistics {}", statistics),
+                    Err(err) => error!("failed to serialize rate limit statistics: {}", err)
+                }
+            }
+        }))
+    } else {
+        None
+    };
+
+    let _aggregation_thread_handle = if cfg.aggregation.enable {
+        let aggregator = processor.aggregator.clone();
+        let window = Duration::from_secs(cfg.aggregation.window);
+        Some(Builder::new().name("aggregation".to_string()).spawn(move || {
+            loop {
+                sleep(window);
+                log_aggregated_reports(&aggregator);
+            }
+        }))
+    } else {
+        None
+    };
+
+    let _tls_cert_check_thread_handles = spawn_certificate_checks(&cfg, &resolver);
+
+    let _header_audit_thread_handle = if !cfg.header_audit.urls.is_empty() {
+        let schedule = match Schedule::new(Some(cfg.header_audit.interval), cfg.header_audit.schedule.as_deref()) {
+            Ok(schedule) => schedule,
+            Err(err) => panic!("invalid schedule of header audit: {}", err)
+        };
+        match cfg.header_audit.endpoint.as_deref().map(Url::parse) {
+            Some(Ok(_)) => {},
+            Some(Err(err)) => panic!("invalid header audit endpoint: {}", err),
+            None => warn!("no header audit endpoint configured, reporting endpoints are not checked to point to this instance")
+        }
+        Some(Builder::new().name("header_audit".to_string()).spawn(move || {
+            trace!("header audit thread started");
+
+            schedule.run(|| {
+                for target in &cfg.header_audit.urls {
+                    match HeaderAuditReport::create(target, &cfg.header_audit) {
+                        Ok(rpt) => {
+                            if let Err(err) = handle_report(&ReportType::HeaderAudit(&rpt), None, None) {
+                                error!("{}", err);
                             }
                         },
-                        Err(err) => error!("failed to get certificate for domain {}:{}: {}", domain.domain, domain.port, err)
+                        Err(err) => error!("failed to audit headers of {}: {}", target.url, err)
                     }
                 }
+            });
+        }))
+    } else {
+        None
+    };
 
-                sleep(Duration::from_secs(86400));
-            }
+    let _synthetic_probe_thread_handle = if !cfg.synthetic_probes.urls.is_empty() {
+        let schedule = match Schedule::new(Some(cfg.synthetic_probes.interval), cfg.synthetic_probes.schedule.as_deref()) {
+            Ok(schedule) => schedule,
+            Err(err) => panic!("invalid schedule of synthetic probes: {}", err)
+        };
+        Some(Builder::new().name("synthetic_probes".to_string()).spawn(move || {
+            trace!("synthetic probe thread started");
+
+            schedule.run(|| {
+                for url in &cfg.synthetic_probes.urls {
+                    match SyntheticProbeReport::create(url, Duration::from_secs(cfg.synthetic_probes.timeout)) {
+                        Ok(rpts) => for rpt in rpts {
+                            if let Err(err) = handle_report(&ReportType::SyntheticProbe(&rpt), None, None) {
+                                error!("{}", err);
+                            }
+                        },
+                        Err(err) => error!("failed to probe {}: {}", url, err)
+                    }
+                }
+            });
         }))
     } else {
         None
     };
 
-    let filter = Filter::new(cfg.filter);
     let _imap_thread_handle = if cfg.imap.enable {
-        let filter_imap = filter.clone();
+        let processor_imap = processor.clone();
         Some(Builder::new().name("imap".to_string()).spawn(move || {
             trace!("IMAP thread started");
 
@@ -122,7 +332,7 @@ async fn main() -> std::io::Result<()> {
                         match imap_client.read("UNANSWERED UNSEEN UNDELETED UNDRAFT SUBJECT \"Report Domain:\"") {
                             Ok(reports) => {
                                 for report in reports {
-                                    if let Err(err) = handle_report(&ReportType::DMARC(&report), None, Some(&filter_imap)) {
+                                    if let Err(err) = handle_report(&ReportType::DMARC(&report), None, Some(&processor_imap)) {
                                         error!("{}", err);
                                     }
                                 }
@@ -147,7 +357,18 @@ async fn main() -> std::io::Result<()> {
     };
 
     let server_string: &'static str = format!("{}/{}", crate_name!(), crate_version!()).leak();
+    let aggregator = processor.aggregator.clone();
+    let proxied_peers = processor.proxied_peers.clone();
+    // the PROXY protocol listener relays connections (including TLS) to an additional local listener of the server
+    let backend = if cfg.proxy_protocol.enable {
+        Some(TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?)
+    } else {
+        None
+    };
+    let backend_addr = backend.as_ref().map(|backend| backend.local_addr()).transpose()?;
+    let relayed_peers = proxied_peers.clone();
     let server = HttpServer::new(move || {
+        let relayed_peers = relayed_peers.clone();
         let cors = Cors::default()
             .allow_any_origin()
             .allowed_methods(vec!["POST", "OPTIONS"])
@@ -155,9 +376,16 @@ async fn main() -> std::io::Result<()> {
         
         App::new()
             .app_data(Data::new(WebState { 
-                filter: filter.clone()
+                processor: processor.clone()
             }))
             .wrap(cors)
+            .wrap_fn(move |req, srv| {
+                if relayed_peers.is_bypassing(backend_addr, req.app_config().local_addr(), req.peer_addr()) {
+                    warn!("request of {:?} to the PROXY protocol backend refused, connection not relayed", req.peer_addr());
+                    return ready(Err(ErrorForbidden("connection not relayed by the PROXY protocol listener"))).boxed_local();
+                }
+                srv.call(req).boxed_local()
+            })
             .wrap_fn(|req, srv| {
                 srv.call(req).map(|res| {
                     if let Ok(mut resp) = res {
@@ -197,16 +425,41 @@ async fn main() -> std::io::Result<()> {
                 .guard(guard::Any(Header("content-type", "application/tlsrpt+gzip")).or(Header("content-type", "application/tlsrpt+json")))
                 .post(report_smtp_tls))
     });
-    let bound_server = if cfg.tls.enable && cfg.tls.key.is_some() && cfg.tls.cert.is_some() {
-        let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
-        builder
-            .set_private_key_file(cfg.tls.key.unwrap(), SslFiletype::PEM)
-            .unwrap();
-        builder.set_certificate_chain_file(cfg.tls.cert.unwrap()).unwrap();
-
-        server.bind_openssl(format!("{}:{}", cfg.listen, cfg.port), builder)?
-    } else {
-        server.bind((cfg.listen, cfg.port))?
+    let mut bound_server = match ssl_acceptor(&cfg.tls) {
+        Some(builder) => server.bind_openssl(format!("{}:{}", cfg.listen, cfg.port), builder)?,
+        None => server.bind((cfg.listen.as_str(), cfg.port))?
+    };
+    if let (Some(backend), Some(backend_addr)) = (backend, backend_addr) {
+        bound_server = match ssl_acceptor(&cfg.tls) {
+            Some(builder) => bound_server.listen_openssl(backend, builder)?,
+            None => bound_server.listen(backend)?
+        };
+        let proxy_listener = ProxyListener::bind(&cfg.proxy_protocol, backend_addr, proxied_peers).await?;
+        info!("listening for PROXY protocol connections on {}", proxy_listener.local_addr()?);
+        spawn(proxy_listener.run());
+    }
+    let res = bound_server.run().await;
+    log_aggregated_reports(&aggregator);
+    res
+}
+
+fn ssl_acceptor(tls: &TlsConfig) -> Option<SslAcceptorBuilder> {
+    let (true, Some(key), Some(cert)) = (tls.enable, &tls.key, &tls.cert) else {
+        return None;
     };
-    bound_server.run().await
+    let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+    builder
+        .set_private_key_file(key, SslFiletype::PEM)
+        .unwrap();
+    builder.set_certificate_chain_file(cert).unwrap();
+    Some(builder)
+}
+
+fn log_aggregated_reports(aggregator: &Aggregator) {
+    for aggregated_report in aggregator.flush() {
+        match serde_json::to_string_pretty(&aggregated_report) {
+            Ok(serialized) => info!("Aggregate {}", serialized),
+            Err(err) => error!("failed to serialize aggregated reports: {}", err)
+        }
+    }
 }
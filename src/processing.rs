@@ -16,5 +16,63 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use std::net::IpAddr;
+
+use actix_web::{http::header, HttpRequest};
+
+use crate::{processing::{aggregation::Aggregator, client_ip::TrustedProxies, derivation::{ClientHints, UserAgentParser}, filter::Filter, geoip::GeoIp, public_suffix::PublicSuffixList, rate_limit::RateLimiter, reverse_dns::ReverseDns, sampling::Sampler}, proxy_protocol::ProxiedPeers};
+
+pub mod aggregation;
+pub mod client_ip;
 pub mod filter;
+pub mod geoip;
 pub mod derivation;
+pub mod domain_list;
+pub mod public_suffix;
+pub mod rate_limit;
+pub mod reverse_dns;
+pub mod rules;
+pub mod sampling;
+
+/**
+ * Shared state needed to filter and enrich incoming reports.
+ */
+#[derive(Clone)]
+pub struct Processor {
+    pub filter: Filter,
+    pub user_agent_parser: UserAgentParser,
+    pub public_suffix_list: PublicSuffixList,
+    pub aggregator: Aggregator,
+    pub rate_limiter: RateLimiter,
+    pub sampler: Sampler,
+    pub geoip: GeoIp,
+    pub reverse_dns: ReverseDns,
+    pub trusted_proxies: TrustedProxies,
+    pub proxied_peers: ProxiedPeers
+}
+
+/**
+ * Properties of the HTTP request a report was received with
+ */
+#[derive(Default, Debug)]
+pub struct RequestInfo {
+    /// path of the endpoint e.g. /csp
+    pub endpoint: String,
+    pub user_agent: Option<String>,
+    pub client_hints: ClientHints,
+    /// peer address or the address forwarded by a trusted proxy
+    pub client_ip: Option<IpAddr>
+}
+
+impl RequestInfo {
+    pub fn from_request(req: &HttpRequest, processor: &Processor) -> Self {
+        // connections received via the PROXY protocol are treated like ones of the original client
+        let peer = req.peer_addr().map(|addr| processor.proxied_peers.get(addr).unwrap_or(addr));
+        RequestInfo {
+            endpoint: req.path().to_string(),
+            user_agent: req.headers().get(header::USER_AGENT).and_then(|h| h.to_str().ok()).map(|ua| ua.to_string()),
+            client_hints: ClientHints::from_headers(req.headers()),
+            client_ip: processor.trusted_proxies.client_ip(peer.map(|addr| addr.ip()), req.headers())
+        }
+    }
+}
@@ -0,0 +1,154 @@
+/**
+ * network-journal - collect network reports and print them to file
+ * Copyright (C) 2025 nerou GmbH
+ * 
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{collections::HashMap, sync::{Arc, Mutex}};
+
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::{config::AggregationConfig, processing::rules::select};
+
+/// log types of reports sent by browsers, which are aggregated
+const BROWSER_REPORT_TYPES: [&str; 10] = [
+    "COEP", "COOP", "Crash", "CSP", "CSP-Hash", "Decprecation", "IntegrityViolation", "Intervention", "NEL", "PermissionsPolicyViolation"
+];
+
+/**
+ * Summary of identical reports within a window
+ */
+#[derive(Serialize, Debug)]
+pub struct AggregatedReport {
+    pub report_type: String,
+    pub count: u64,
+    /// sum of the sampling weights of the reports i.e. the estimated count without sampling
+    pub weighted_count: f64,
+    pub first_seen: String,
+    pub last_seen: String,
+    /// seconds
+    pub window: u64,
+    /// values of the configured key fields
+    pub key: Map<String, Value>,
+    /// first report of the group
+    pub sample: Value
+}
+
+#[derive(Clone)]
+pub struct Aggregator {
+    config: AggregationConfig,
+    paths: Arc<Vec<Vec<String>>>,
+    groups: Arc<Mutex<HashMap<(String, String), AggregatedReport>>>
+}
+
+impl Aggregator {
+    pub fn new(config: AggregationConfig) -> Self {
+        Self {
+            paths: Arc::new(config.keys.iter().map(|key| key.split('.').map(|s| s.to_string()).collect()).collect()),
+            config,
+            groups: Arc::new(Mutex::new(HashMap::new()))
+        }
+    }
+
+    pub fn is_aggregated(&self, report_type: &str) -> bool {
+        self.config.enable && BROWSER_REPORT_TYPES.contains(&report_type)
+            && !self.config.pass_through.iter().any(|t| t == report_type)
+    }
+
+    /**
+     * Adds the report (as logged) to its group of the current window, false if the report has to be logged individually
+     */
+    pub fn add(&self, report_type: &str, report: Value) -> bool {
+        let key: Map<String, Value> = self.config.keys.iter().zip(self.paths.iter())
+            .map(|(name, path)| (name.clone(), select(&report, path).into_iter().next().cloned().unwrap_or(Value::Null)))
+            .collect();
+        let group_key = (report_type.to_string(), Value::Object(key.clone()).to_string());
+        let weight = report.pointer("/derived/sampling/weight").and_then(Value::as_f64).unwrap_or(1.0);
+        let now = Utc::now().to_rfc3339();
+        let mut groups = self.groups.lock().unwrap();
+        if let Some(group) = groups.get_mut(&group_key) {
+            group.count += 1;
+            group.weighted_count += weight;
+            group.last_seen = now;
+            return true;
+        }
+        if groups.len() >= self.config.max_groups {
+            return false;
+        }
+        groups.insert(group_key, AggregatedReport {
+            report_type: report_type.to_string(),
+            count: 1,
+            weighted_count: weight,
+            first_seen: now.clone(),
+            last_seen: now,
+            window: self.config.window,
+            key,
+            sample: report
+        });
+        true
+    }
+
+    /**
+     * Ends the current window and returns its groups
+     */
+    pub fn flush(&self) -> Vec<AggregatedReport> {
+        let mut groups: Vec<AggregatedReport> = self.groups.lock().unwrap().drain().map(|(_, group)| group).collect();
+        groups.sort_by(|a, b| a.first_seen.cmp(&b.first_seen));
+        groups
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn aggregate_reports() {
+        let aggregator = Aggregator::new(AggregationConfig {
+            enable: true,
+            pass_through: vec!["NEL".to_string()],
+            max_groups: 2,
+            ..AggregationConfig::default()
+        });
+        assert!(aggregator.is_aggregated("CSP"));
+        assert!(!aggregator.is_aggregated("NEL"));
+        assert!(!aggregator.is_aggregated("DMARC"));
+
+        let csp = |blocked_url: &str, line: u32| json!({
+            "report": { "csp-report": { "effectiveDirective": "script-src", "blockedUrl": blocked_url, "lineNumber": line } },
+            "derived": { "url": { "host": "example.com" }, "client": { "family": "Firefox" } }
+        });
+        let mut sampled = csp("https://a.example/x.js", 2);
+        sampled["derived"]["sampling"] = json!({ "rate": 0.25, "weight": 4.0 });
+        assert!(aggregator.add("CSP", csp("https://a.example/x.js", 1)));
+        assert!(aggregator.add("CSP", sampled));
+        assert!(aggregator.add("CSP", csp("inline", 3)));
+        assert!(!aggregator.add("CSP", csp("eval", 4)));
+
+        let groups = aggregator.flush();
+        assert_eq!(groups.len(), 2);
+        let group = groups.iter().find(|g| g.key["report.*.blockedUrl"] == "https://a.example/x.js").unwrap();
+        assert_eq!(group.count, 2);
+        assert_eq!(group.weighted_count, 5.0);
+        assert_eq!(group.key["derived.url.host"], "example.com");
+        assert_eq!(group.key["report.*.sourceFile"], Value::Null);
+        assert_eq!(group.sample["report"]["csp-report"]["lineNumber"], 1);
+        assert!(aggregator.flush().is_empty());
+    }
+}
@@ -0,0 +1,168 @@
+/**
+ * network-journal - collect network reports and print them to file
+ * Copyright (C) 2025 nerou GmbH
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{net::{IpAddr, SocketAddr}, sync::Arc};
+
+use actix_web::http::header::{self, HeaderMap, HeaderName};
+use ipnet::IpNet;
+
+use crate::{config::ForwardedHeader, structured_field::{split_top_level, unquote}};
+
+const X_REAL_IP: HeaderName = HeaderName::from_static("x-real-ip");
+
+/**
+ * Determines the address of the client that submitted a report.
+ * The forwarding header written by the proxies is only honoured if sent by a trusted proxy.
+ */
+#[derive(Clone, Default, Debug)]
+pub struct TrustedProxies {
+    networks: Arc<Vec<IpNet>>,
+    header: ForwardedHeader
+}
+
+impl TrustedProxies {
+    pub fn new(networks: Vec<IpNet>, header: ForwardedHeader) -> Self {
+        Self {
+            networks: Arc::new(networks),
+            header
+        }
+    }
+
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(&ip))
+    }
+
+    /**
+     * Starting at the peer, the forwarded addresses are walked from the nearest to the farthest hop
+     * until an address not belonging to a trusted proxy is reached.
+     * Only the configured header is read, as clients may send any of the others through the proxy.
+     * If an address is obfuscated or invalid, the last valid one is returned.
+     */
+    pub fn client_ip(&self, peer: Option<IpAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        let mut client = peer?.to_canonical();
+        if !self.is_trusted(client) {
+            return Some(client);
+        }
+        for hop in forwarded_addresses(headers, self.header).into_iter().rev() {
+            match hop {
+                Some(ip) => client = ip,
+                None => break
+            }
+            if !self.is_trusted(client) {
+                break;
+            }
+        }
+        Some(client)
+    }
+}
+
+/**
+ * Addresses of all hops from the client to the nearest proxy, None for obfuscated or invalid ones
+ */
+fn forwarded_addresses(headers: &HeaderMap, forwarded_header: ForwardedHeader) -> Vec<Option<IpAddr>> {
+    match forwarded_header {
+        ForwardedHeader::Forwarded => header_values(headers, &header::FORWARDED)
+            .flat_map(|value| split_top_level(value, ','))
+            .map(|element| split_top_level(element, ';').into_iter()
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                .and_then(|(_, node)| parse_node(&unquote(node.trim()))))
+            .collect(),
+        ForwardedHeader::XForwardedFor => header_values(headers, &header::X_FORWARDED_FOR)
+            .flat_map(|value| value.split(','))
+            .map(parse_node)
+            .collect(),
+        ForwardedHeader::XRealIp => header_values(headers, &X_REAL_IP)
+            .map(parse_node)
+            .collect()
+    }
+}
+
+fn header_values<'a>(headers: &'a HeaderMap, name: &HeaderName) -> impl Iterator<Item = &'a str> {
+    headers.get_all(name).filter_map(|value| value.to_str().ok())
+}
+
+/**
+ * Parses an address optionally followed by a port e.g. 192.0.2.1:4711 or [2001:db8::1]:4711
+ */
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim();
+    node.parse::<IpAddr>().ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| node.strip_prefix('[').and_then(|n| n.strip_suffix(']')).and_then(|ip| ip.parse().ok()))
+        .map(|ip: IpAddr| ip.to_canonical())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::HeaderValue;
+
+    use super::*;
+
+    fn headers(entries: &[(HeaderName, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in entries {
+            headers.append(name.clone(), HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn resolve_client_ip() {
+        let networks: Vec<IpNet> = vec!["127.0.0.0/8".parse().unwrap(), "10.0.0.0/8".parse().unwrap()];
+        let proxies = TrustedProxies::new(networks.clone(), ForwardedHeader::XForwardedFor);
+        let proxy = "127.0.0.1".parse().ok();
+        let ip = |ip: &str| ip.parse::<IpAddr>().ok();
+
+        // untrusted peers cannot forge their address
+        let forged = headers(&[(header::X_FORWARDED_FOR, "192.0.2.1")]);
+        assert_eq!(proxies.client_ip(ip("198.51.100.7"), &forged), ip("198.51.100.7"));
+        assert_eq!(proxies.client_ip(None, &forged), None);
+        assert_eq!(proxies.client_ip(proxy, &HeaderMap::new()), proxy);
+        assert_eq!(proxies.client_ip(ip("::ffff:127.0.0.1"), &forged), ip("192.0.2.1"));
+
+        // the address prepended by the client itself is skipped
+        let chain = headers(&[(header::X_FORWARDED_FOR, "203.0.113.9, 192.0.2.1"), (header::X_FORWARDED_FOR, "10.1.2.3")]);
+        assert_eq!(proxies.client_ip(proxy, &chain), ip("192.0.2.1"));
+        assert_eq!(TrustedProxies::default().client_ip(proxy, &chain), proxy);
+
+        let forwarded_proxies = TrustedProxies::new(networks.clone(), ForwardedHeader::Forwarded);
+        let forwarded = headers(&[
+            (header::FORWARDED, r#"for=192.0.2.60;proto=http;by=203.0.113.43, For="[2001:db8:cafe::17]:4711""#),
+            (header::X_FORWARDED_FOR, "198.51.100.1")
+        ]);
+        assert_eq!(forwarded_proxies.client_ip(proxy, &forwarded), ip("2001:db8:cafe::17"));
+        assert_eq!(proxies.client_ip(proxy, &forwarded), ip("198.51.100.1"));
+        let obfuscated = headers(&[(header::FORWARDED, "for=192.0.2.60, for=_hidden, for=10.0.0.1:80")]);
+        assert_eq!(forwarded_proxies.client_ip(proxy, &obfuscated), ip("10.0.0.1"));
+
+        let real_ip_proxies = TrustedProxies::new(networks, ForwardedHeader::XRealIp);
+        let real_ip = headers(&[(X_REAL_IP, "192.0.2.44")]);
+        assert_eq!(real_ip_proxies.client_ip(proxy, &real_ip), ip("192.0.2.44"));
+        assert_eq!(real_ip_proxies.client_ip(proxy, &headers(&[(X_REAL_IP, "invalid")])), proxy);
+
+        // headers forged by the client next to the one set by the proxy are ignored
+        let forged = headers(&[
+            (header::X_FORWARDED_FOR, "1.2.3.4"),
+            (header::FORWARDED, "for=1.2.3.4"),
+            (X_REAL_IP, "192.0.2.44")
+        ]);
+        assert_eq!(real_ip_proxies.client_ip(proxy, &forged), ip("192.0.2.44"));
+        assert_eq!(real_ip_proxies.client_ip(proxy, &headers(&[(header::X_FORWARDED_FOR, "1.2.3.4")])), proxy);
+    }
+}
@@ -16,12 +16,34 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::path::Path;
+use std::{fmt::Display, fs, num::NonZeroUsize, path::{Path, PathBuf}, sync::{Arc, Mutex, RwLock}, time::SystemTime};
+#[cfg(feature = "embedded-regexes")]
+use log::warn;
+use lru::LruCache;
+use actix_web::http::header::HeaderMap;
 use serde::{Deserialize, Serialize};
 use uaparser_rs::UAParser;
 use url::ParseError;
 
-#[derive(Serialize, Deserialize, PartialEq, Default, Debug)]
+use crate::{processing::public_suffix::PublicSuffixList, structured_field::{parse_boolean, parse_list, unquote}};
+
+#[cfg(feature = "embedded-regexes")]
+const EMBEDDED_REGEXES: &str = include_str!("../../regexes.yaml");
+#[cfg(feature = "embedded-regexes")]
+const EMBEDDED_REGEXES_FILE: &str = "regexes.yaml";
+
+/// Number of distinct user agent strings whose parse results are kept
+const USER_AGENT_CACHE_SIZE: usize = 10_000;
+
+/// Where derived client information came from
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Source {
+    UserAgent,
+    ClientHints
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Default, Clone, Debug)]
 pub struct Client {
     pub family: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -31,7 +53,9 @@ pub struct Client {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub patch: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub patch_minor: Option<String>
+    pub patch_minor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<Source>
 }
 
 impl Client {
@@ -41,7 +65,8 @@ impl Client {
             major: ua.major,
             minor: ua.minor,
             patch: ua.patch,
-            patch_minor: ua.patch_minor
+            patch_minor: ua.patch_minor,
+            source: Some(Source::UserAgent)
         }
     }
 
@@ -51,18 +76,23 @@ impl Client {
             major: os.major,
             minor: os.minor,
             patch: os.patch,
-            patch_minor: os.patch_minor
+            patch_minor: os.patch_minor,
+            source: Some(Source::UserAgent)
         }
     }
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Default, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Default, Clone, Debug)]
 pub struct Device {
     pub family: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub brand: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub model: Option<String>
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mobile: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<Source>
 }
 
 impl Device {
@@ -70,38 +100,406 @@ impl Device {
         Device {
             family: dev.family,
             brand: dev.brand,
-            model: dev.model
+            model: dev.model,
+            mobile: None,
+            source: Some(Source::UserAgent)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    MissingRegexes(PathBuf),
+    InvalidRegexes(PathBuf, String),
+    IO(std::io::Error)
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::MissingRegexes(path) => write!(f, "user agent regexes {} not found", path.display()),
+            Error::InvalidRegexes(path, err) => write!(f, "failed to load user agent regexes {}: {}", path.display(), err),
+            Error::IO(err) => write!(f, "failed to write embedded user agent regexes: {}", err)
         }
     }
 }
 
-pub fn analyze_user_agent(user_agent: &str) -> (Client, Client, Device) {
-    #[cfg(debug_assertions)]
-    let path = "./regexes.yaml";
-    #[cfg(not(debug_assertions))]
-    let path = "/usr/share/network-journal/regexes.yaml";
-    if Path::new(path).exists() {
-        let uap = UAParser::from_yaml(path).unwrap();
-        let client_info = uap.parse(user_agent);
-        (Client::from_user_agent(client_info.user_agent), Client::from_os(client_info.os), Device::from_device(client_info.device))
-    } else {
-        (Client::default(), Client::default(), Device::default())
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Error::IO(value)
+    }
+}
+
+/// Client, operating system and device derived from a user agent string
+pub type UserAgentInfo = (Client, Client, Device);
+
+struct LoadedRegexes {
+    parser: UAParser,
+    /// modification time of the configured regexes, None if the embedded copy is in use
+    modified: Option<SystemTime>
+}
+
+/**
+ * User agent parser whose regexes are compiled once and shared between all workers.
+ * Parse results of recently seen user agent strings are kept in a bounded LRU cache.
+ * The regexes can be reloaded at runtime, which clears the cache.
+ */
+#[derive(Clone)]
+pub struct UserAgentParser {
+    path: PathBuf,
+    regexes: Arc<RwLock<LoadedRegexes>>,
+    cache: Arc<Mutex<LruCache<String, UserAgentInfo>>>
+}
+
+impl UserAgentParser {
+    /**
+     * Load the regexes at `path`. If they are missing and the binary was built with the 
+     * `embedded-regexes` feature, the embedded copy is written to `state_dir` and used instead.
+     */
+    pub fn new(path: &Path, state_dir: &Path) -> Result<Self, Error> {
+        let regexes = if path.exists() {
+            LoadedRegexes {
+                parser: load_regexes(path)?,
+                modified: modification_time(path)
+            }
+        } else {
+            LoadedRegexes {
+                parser: load_embedded_regexes(path, state_dir)?,
+                modified: None
+            }
+        };
+        Ok(UserAgentParser {
+            path: path.to_path_buf(),
+            regexes: Arc::new(RwLock::new(regexes)),
+            cache: Arc::new(Mutex::new(LruCache::new(NonZeroUsize::new(USER_AGENT_CACHE_SIZE).unwrap())))
+        })
+    }
+
+    /**
+     * Reload the configured regexes. On failure the previously loaded regexes stay in use.
+     */
+    pub fn reload(&self) -> Result<(), Error> {
+        if !self.path.exists() {
+            return Err(Error::MissingRegexes(self.path.clone()));
+        }
+        let parser = load_regexes(&self.path)?;
+        *self.regexes.write().unwrap() = LoadedRegexes {
+            parser,
+            modified: modification_time(&self.path)
+        };
+        self.cache.lock().unwrap().clear();
+        Ok(())
+    }
+
+    /**
+     * Reload the configured regexes if they have been modified since they were loaded.
+     * Returns whether the regexes have been reloaded.
+     */
+    pub fn reload_if_changed(&self) -> Result<bool, Error> {
+        let modified = modification_time(&self.path);
+        if modified.is_none() || modified == self.regexes.read().unwrap().modified {
+            return Ok(false);
+        }
+        if let Err(err) = self.reload() {
+            // do not retry until the regexes are modified again
+            self.regexes.write().unwrap().modified = modified;
+            return Err(err);
+        }
+        Ok(true)
+    }
+
+    /**
+     * Derive client, operating system and device from a user agent string.
+     */
+    pub fn analyze(&self, user_agent: &str) -> UserAgentInfo {
+        if let Some(cached) = self.cache.lock().unwrap().get(user_agent) {
+            return cached.clone();
+        }
+        let client_info = self.regexes.read().unwrap().parser.parse(user_agent);
+        let derived = (Client::from_user_agent(client_info.user_agent), Client::from_os(client_info.os), Device::from_device(client_info.device));
+        self.cache.lock().unwrap().put(user_agent.to_string(), derived.clone());
+        derived
+    }
+}
+
+fn load_regexes(path: &Path) -> Result<UAParser, Error> {
+    UAParser::from_yaml(&path.to_string_lossy()).map_err(|err| Error::InvalidRegexes(path.to_path_buf(), format!("{:?}", err)))
+}
+
+pub fn modification_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(feature = "embedded-regexes")]
+fn load_embedded_regexes(path: &Path, state_dir: &Path) -> Result<UAParser, Error> {
+    // the parser only reads regexes from a file
+    let embedded_path = state_dir.join(EMBEDDED_REGEXES_FILE);
+    fs::create_dir_all(state_dir)?;
+    fs::write(&embedded_path, EMBEDDED_REGEXES)?;
+    warn!("user agent regexes {} not found, using embedded copy", path.display());
+    load_regexes(&embedded_path)
+}
+
+#[cfg(not(feature = "embedded-regexes"))]
+fn load_embedded_regexes(path: &Path, _state_dir: &Path) -> Result<UAParser, Error> {
+    Err(Error::MissingRegexes(path.to_path_buf()))
+}
+
+/**
+ * User-Agent Client Hints (`Sec-CH-UA*` request headers)
+ */
+#[derive(PartialEq, Default, Clone, Debug)]
+pub struct ClientHints {
+    /// brand and major version of `Sec-CH-UA`
+    pub brands: Vec<(String, String)>,
+    pub platform: Option<String>,
+    pub platform_version: Option<String>,
+    pub mobile: Option<bool>,
+    pub model: Option<String>
+}
+
+impl ClientHints {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+        let sf_string = |name: &str| header(name).map(unquote).filter(|v| !v.is_empty());
+        ClientHints {
+            brands: header("sec-ch-ua").map(|value| parse_list(value).into_iter()
+                .map(|(brand, params)| {
+                    let version = params.iter().find(|(key, _)| key == "v").map(|(_, v)| unquote(v)).unwrap_or_default();
+                    (unquote(&brand), version)
+                })
+                .collect()
+            ).unwrap_or_default(),
+            platform: sf_string("sec-ch-ua-platform").filter(|p| p != "Unknown"),
+            platform_version: sf_string("sec-ch-ua-platform-version"),
+            mobile: header("sec-ch-ua-mobile").and_then(parse_boolean),
+            model: sf_string("sec-ch-ua-model")
+        }
+    }
+
+    /**
+     * Override or refine the values derived from the (possibly frozen) user agent string.
+     */
+    pub fn refine(&self, client: &mut Client, os: &mut Client, device: &mut Device) {
+        if let Some((family, major)) = self.brand() {
+            if client.family != family || client.major.as_deref() != Some(major.as_str()) {
+                // minor and patch versions of the user agent string are unreliable once the major version differs
+                *client = Client {
+                    family,
+                    major: Some(major).filter(|v| !v.is_empty()),
+                    ..Client::default()
+                };
+            }
+            client.source = Some(Source::ClientHints);
+        }
+
+        if let Some(platform) = &self.platform {
+            let family = match platform.as_str() {
+                "macOS" => "Mac OS X".to_string(),
+                platform => platform.to_string()
+            };
+            if os.family != family {
+                *os = Client {
+                    family,
+                    ..Client::default()
+                };
+            }
+            if let Some(version) = &self.platform_version {
+                let (major, minor, patch) = if platform == "Windows" {
+                    windows_version(version)
+                } else {
+                    let mut parts = version.split('.').map(|v| v.to_string());
+                    (parts.next(), parts.next(), parts.next())
+                };
+                (os.major, os.minor, os.patch, os.patch_minor) = (major, minor, patch, None);
+            }
+            os.source = Some(Source::ClientHints);
+        }
+
+        if let Some(model) = &self.model {
+            device.family = model.clone();
+            device.model = Some(model.clone());
+            device.source = Some(Source::ClientHints);
+        }
+        if self.mobile.is_some() {
+            device.mobile = self.mobile;
+        }
+    }
+
+    /**
+     * The most specific brand, i.e. neither Chromium nor a GREASE brand like "Not-A.Brand"
+     */
+    fn brand(&self) -> Option<(String, String)> {
+        let brands = self.brands.iter()
+            .filter(|(brand, _)| !(brand.contains("Not") && brand.contains("Brand")))
+            .collect::<Vec<_>>();
+        brands.iter()
+            .find(|(brand, _)| brand != "Chromium")
+            .or(brands.first())
+            .map(|(brand, version)| {
+                let family = brand.strip_prefix("Google ").or(brand.strip_prefix("Microsoft ")).unwrap_or(brand);
+                (family.to_string(), version.clone())
+            })
     }
 }
 
+/**
+ * Maps `Sec-CH-UA-Platform-Version` of Windows to the marketing version, 
+ * see <https://learn.microsoft.com/en-us/microsoft-edge/web-platform/how-to-detect-win11>
+ */
+fn windows_version(platform_version: &str) -> (Option<String>, Option<String>, Option<String>) {
+    let mut parts = platform_version.split('.').map(|v| v.parse::<u32>().unwrap_or_default());
+    let (major, minor) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+    let (major, minor) = match (major, minor) {
+        (13.., _) => ("11", None),
+        (1.., _) => ("10", None),
+        (0, 1) => ("7", None),
+        (0, 2) => ("8", None),
+        (0, 3) => ("8", Some("1")),
+        _ => return (None, None, None)
+    };
+    (Some(major.to_string()), minor.map(|m| m.to_string()), None)
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Default, Debug)]
 pub struct Url {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scheme: Option<String>,
     pub host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// eTLD+1 according to the Public Suffix List
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registrable_domain: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subdomain: Option<String>,
     pub path: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub query: Option<String>
+    pub query: Option<String>,
+    /// serialized origin, "null" for opaque origins (e.g. data: URLs)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>
+}
+
+impl Url {
+    /**
+     * Derive registrable domain and subdomain of the host
+     */
+    pub fn split_host(&mut self, public_suffix_list: &PublicSuffixList) {
+        if let Some((registrable_domain, subdomain)) = self.host.as_deref().and_then(|host| public_suffix_list.split(host)) {
+            self.registrable_domain = Some(registrable_domain);
+            self.subdomain = subdomain;
+        }
+    }
 }
 
 pub fn analyze_url(url: &str) -> Result<Url, ParseError> {
     let parsed_url = url::Url::parse(url)?;
     Ok(Url {
+        scheme: Some(parsed_url.scheme().to_owned()),
         host: parsed_url.host_str().map(|s| s.to_owned()),
+        port: parsed_url.port_or_known_default(),
         path: parsed_url.path().to_owned(),
-        query: parsed_url.query().map(|s| s.to_owned())
+        query: parsed_url.query().map(|s| s.to_owned()),
+        origin: Some(parsed_url.origin().ascii_serialization()),
+        ..Url::default()
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(not(feature = "embedded-regexes"))]
+    fn missing_regexes() {
+        assert!(matches!(UserAgentParser::new(Path::new("./does-not-exist.yaml"), &std::env::temp_dir()), Err(Error::MissingRegexes(_))));
+    }
+
+    #[test]
+    fn cached_user_agent() {
+        let parser = UserAgentParser::new(Path::new("./regexes.yaml"), &std::env::temp_dir()).unwrap();
+        let ua = "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0";
+        let (client, os, _) = parser.analyze(ua);
+        assert_eq!(client.family, "Firefox");
+        assert_eq!(client.major.as_deref(), Some("128"));
+        assert_eq!(os.family, "Linux");
+        assert_eq!(parser.cache.lock().unwrap().len(), 1);
+        assert_eq!(parser.analyze(ua).0, client);
+        assert_eq!(parser.cache.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn reload_changed_regexes() {
+        let dir = std::env::temp_dir().join("network-journal-test-regexes");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("regexes.yaml");
+        fs::copy("./regexes.yaml", &path).unwrap();
+        let parser = UserAgentParser::new(&path, &dir).unwrap();
+        parser.analyze("curl/8.0.1");
+        assert!(!parser.reload_if_changed().unwrap());
+        assert_eq!(parser.cache.lock().unwrap().len(), 1);
+
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + std::time::Duration::from_secs(60)).unwrap();
+        assert!(parser.reload_if_changed().unwrap());
+        assert_eq!(parser.cache.lock().unwrap().len(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn client_hints() {
+        let mut headers = HeaderMap::new();
+        for (name, value) in [
+            ("sec-ch-ua", r#""Chromium";v="124", "Google Chrome";v="124", "Not-A.Brand";v="99""#),
+            ("sec-ch-ua-platform", r#""Windows""#),
+            ("sec-ch-ua-platform-version", r#""15.0.0""#),
+            ("sec-ch-ua-mobile", "?0"),
+            ("sec-ch-ua-model", r#""""#)
+        ] {
+            headers.insert(name.parse().unwrap(), value.parse().unwrap());
+        }
+        let hints = ClientHints::from_headers(&headers);
+        assert_eq!(hints.brands.len(), 3);
+        assert_eq!(hints.mobile, Some(false));
+        assert_eq!(hints.model, None);
+
+        let parser = UserAgentParser::new(Path::new("./regexes.yaml"), &std::env::temp_dir()).unwrap();
+        let (mut client, mut os, mut device) = parser.analyze("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36");
+        assert_eq!((os.family.as_str(), os.major.as_deref()), ("Windows", Some("10")));
+        hints.refine(&mut client, &mut os, &mut device);
+        assert_eq!((client.family.as_str(), client.major.as_deref(), client.minor.as_deref()), ("Chrome", Some("124"), Some("0")));
+        assert_eq!(client.source, Some(Source::ClientHints));
+        assert_eq!((os.family.as_str(), os.major.as_deref()), ("Windows", Some("11")));
+        assert_eq!(os.source, Some(Source::ClientHints));
+        assert_eq!(device.source, Some(Source::UserAgent));
+        assert_eq!(device.mobile, Some(false));
+    }
+
+    #[test]
+    fn client_hints_override() {
+        let hints = ClientHints {
+            brands: vec![("Not)A;Brand".to_string(), "8".to_string()), ("Chromium".to_string(), "138".to_string()), ("Microsoft Edge".to_string(), "138".to_string())],
+            platform: Some("Android".to_string()),
+            platform_version: Some("14.0.0".to_string()),
+            mobile: Some(true),
+            model: Some("Pixel 8".to_string())
+        };
+        let (mut client, mut os, mut device) = (Client::default(), Client::default(), Device::default());
+        hints.refine(&mut client, &mut os, &mut device);
+        assert_eq!((client.family.as_str(), client.major.as_deref()), ("Edge", Some("138")));
+        assert_eq!((os.family.as_str(), os.major.as_deref(), os.minor.as_deref()), ("Android", Some("14"), Some("0")));
+        assert_eq!((device.family.as_str(), device.model.as_deref(), device.mobile), ("Pixel 8", Some("Pixel 8"), Some(true)));
+        assert_eq!(windows_version("0.3.0"), (Some("8".to_string()), Some("1".to_string()), None));
+    }
+
+    #[test]
+    fn analyze_urls() {
+        let url = analyze_url("https://www.example.co.uk:8443/csp?x=1").unwrap();
+        assert_eq!(url.scheme.as_deref(), Some("https"));
+        assert_eq!(url.port, Some(8443));
+        assert_eq!(url.origin.as_deref(), Some("https://www.example.co.uk:8443"));
+        assert_eq!(analyze_url("http://example.com/").unwrap().port, Some(80));
+        assert_eq!(analyze_url("data:text/plain,x").unwrap().origin.as_deref(), Some("null"));
+    }
+}
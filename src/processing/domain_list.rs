@@ -0,0 +1,181 @@
+/**
+ * network-journal - collect network reports and print them to file
+ * Copyright (C) 2025 nerou GmbH
+ * 
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{fmt::Display, net::IpAddr};
+
+use ipnet::IpNet;
+
+use crate::config::DomainConfigType;
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidDomain(String)
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidDomain(domain) => write!(f, "invalid domain in filter: {}", domain)
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+enum DomainPattern {
+    Exact(String),
+    /// the domain itself and all of its subdomains
+    WithSubdomains(String),
+    /// `*.example.com` i.e. all subdomains, but not the domain itself
+    Wildcard(String),
+    Network(IpNet)
+}
+
+impl DomainPattern {
+    fn parse(entry: &str, include_subdomains: bool) -> Result<Self, Error> {
+        let entry = entry.trim();
+        if let Ok(network) = entry.parse::<IpNet>() {
+            return Ok(DomainPattern::Network(network));
+        }
+        if let Ok(ip) = entry.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+            return Ok(DomainPattern::Network(IpNet::from(ip)));
+        }
+        let invalid = || Error::InvalidDomain(entry.to_string());
+        if let Some(parent) = entry.strip_prefix("*.") {
+            Ok(DomainPattern::Wildcard(normalize_domain(parent).ok_or_else(invalid)?))
+        } else if include_subdomains {
+            Ok(DomainPattern::WithSubdomains(normalize_domain(entry).ok_or_else(invalid)?))
+        } else {
+            Ok(DomainPattern::Exact(normalize_domain(entry).ok_or_else(invalid)?))
+        }
+    }
+
+    fn matches(&self, host: &Host) -> bool {
+        match (self, host) {
+            (DomainPattern::Exact(domain), Host::Domain(host)) => host == domain,
+            (DomainPattern::WithSubdomains(domain), Host::Domain(host)) => host == domain || is_subdomain(host, domain),
+            (DomainPattern::Wildcard(domain), Host::Domain(host)) => is_subdomain(host, domain),
+            (DomainPattern::Network(network), Host::Ip(ip)) => network.contains(ip),
+            _ => false
+        }
+    }
+}
+
+/// normalized host of a report
+#[derive(PartialEq, Debug)]
+pub enum Host {
+    Domain(String),
+    Ip(IpAddr)
+}
+
+impl Host {
+    /**
+     * Normalizes a host: lowercase, IDNA (punycode), without trailing dot, IPv6 without brackets
+     */
+    pub fn parse(host: &str) -> Option<Self> {
+        let host = host.trim();
+        if let Ok(ip) = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+            return Some(Host::Ip(ip));
+        }
+        normalize_domain(host).map(Host::Domain)
+    }
+}
+
+/**
+ * Domains, wildcards and IP networks of the domain white- or blacklist
+ */
+#[derive(Clone, Debug)]
+pub struct DomainList {
+    patterns: Vec<DomainPattern>
+}
+
+impl DomainList {
+    pub fn new(entries: &[DomainConfigType]) -> Result<Self, Error> {
+        Ok(DomainList {
+            patterns: entries.iter()
+                .map(|entry| match entry {
+                    DomainConfigType::Simple(domain) => DomainPattern::parse(domain, false),
+                    DomainConfigType::Complex(d) => DomainPattern::parse(&d.domain, d.include_subdomains)
+                })
+                .collect::<Result<_, _>>()?
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    pub fn contains(&self, host: &Host) -> bool {
+        self.patterns.iter().any(|pattern| pattern.matches(host))
+    }
+}
+
+fn normalize_domain(domain: &str) -> Option<String> {
+    let domain = domain.strip_suffix('.').unwrap_or(domain);
+    match idna::domain_to_ascii(domain) {
+        Ok(ascii) if !ascii.is_empty() && ascii.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')) => Some(ascii),
+        _ => None
+    }
+}
+
+fn is_subdomain(host: &str, domain: &str) -> bool {
+    host.len() > domain.len() + 1 && host.ends_with(domain) && host[..host.len() - domain.len()].ends_with('.')
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::DomainConfig;
+
+    use super::*;
+
+    fn contains(list: &DomainList, host: &str) -> bool {
+        list.contains(&Host::parse(host).unwrap())
+    }
+
+    #[test]
+    fn normalize_hosts() {
+        assert_eq!(Host::parse("Example.COM."), Some(Host::Domain("example.com".to_string())));
+        assert_eq!(Host::parse("bücher.example"), Some(Host::Domain("xn--bcher-kva.example".to_string())));
+        assert_eq!(Host::parse("[2001:DB8::1]"), Some(Host::Ip("2001:db8::1".parse().unwrap())));
+        assert_eq!(Host::parse(""), None);
+    }
+
+    #[test]
+    fn match_domains() {
+        let list = DomainList::new(&[
+            DomainConfigType::Simple("Bücher.example".to_string()),
+            DomainConfigType::Simple("*.cdn.example.com".to_string()),
+            DomainConfigType::Complex(DomainConfig { domain: "example.org.".to_string(), include_subdomains: true }),
+            DomainConfigType::Simple("192.0.2.0/24".to_string()),
+            DomainConfigType::Simple("2001:db8::1".to_string())
+        ]).unwrap();
+        assert!(contains(&list, "xn--bcher-kva.example"));
+        assert!(contains(&list, "BÜCHER.example."));
+        assert!(!contains(&list, "www.bücher.example"));
+        assert!(contains(&list, "a.b.cdn.example.com"));
+        assert!(!contains(&list, "cdn.example.com"));
+        assert!(!contains(&list, "xcdn.example.com"));
+        assert!(contains(&list, "example.org"));
+        assert!(contains(&list, "WWW.Example.org"));
+        assert!(!contains(&list, "notexample.org"));
+        assert!(contains(&list, "192.0.2.17"));
+        assert!(!contains(&list, "198.51.100.1"));
+        assert!(contains(&list, "[2001:db8::1]"));
+        assert!(!contains(&list, "2001:db8::2"));
+        assert!(DomainList::new(&[DomainConfigType::Simple("*.".to_string())]).is_err());
+    }
+}
@@ -16,21 +16,100 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use std::{fmt::Display, net::IpAddr};
+
 use log::debug;
+use regex::RegexSet;
+use serde::Serialize;
+use serde_json::Value;
 use url::Url;
 
-use crate::config::FilterConfig;
+use crate::{config::{BotAction, FilterConfig, FilterRuleAction, ReporterListConfig}, processing::{derivation::Device, domain_list::{self, DomainList, Host}, public_suffix::PublicSuffixList, rules::{self, RuleSet, RuleStatistics}}, reports::dmarc::MessageAuthentication};
+
+/// user agent substrings of headless browsers and browser automation
+const HEADLESS_SIGNATURES: [&str; 8] = ["HeadlessChrome", "PhantomJS", "SlimerJS", "Puppeteer", "Playwright", "Selenium", "HtmlUnit", "jsdom"];
+
+/// Why a report is considered to be sent by a bot
+#[derive(Serialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum BotReason {
+    /// known crawler according to the user agent regexes
+    Spider,
+    /// headless browser or browser automation
+    Headless,
+    /// user agent matches a configured pattern
+    UserAgent,
+    /// reporter address is within a configured IP range
+    IpRange
+}
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidBotPattern(regex::Error),
+    InvalidRule(rules::Error),
+    DomainList(domain_list::Error),
+    MissingAuthservIds
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidBotPattern(err) => write!(f, "invalid bot user agent pattern: {}", err),
+            Error::InvalidRule(err) => write!(f, "{}", err),
+            Error::DomainList(err) => write!(f, "{}", err),
+            Error::MissingAuthservIds => write!(f, "authserv_ids of your own MTA are required to require authentication of reporters")
+        }
+    }
+}
+
+impl From<regex::Error> for Error {
+    fn from(value: regex::Error) -> Self {
+        Error::InvalidBotPattern(value)
+    }
+}
+
+impl From<rules::Error> for Error {
+    fn from(value: rules::Error) -> Self {
+        Error::InvalidRule(value)
+    }
+}
+
+impl From<domain_list::Error> for Error {
+    fn from(value: domain_list::Error) -> Self {
+        Error::DomainList(value)
+    }
+}
 
 #[derive(Clone)]
 pub struct Filter {
     config: FilterConfig,
+    bot_user_agents: RegexSet,
+    public_suffix_list: PublicSuffixList,
+    rules: RuleSet,
+    whitelist: DomainList,
+    blacklist: DomainList,
+    allowed_reporters: ReporterList,
+    denied_reporters: ReporterList
 }
 
 impl Filter {
-    pub fn new<'a>(config: FilterConfig) -> Self {
-        Self { 
-            config
+    /**
+     * Authentication of reporters requires the authserv-ids to trust, as the sender of an e-mail may add Authentication-Results headers as well
+     */
+    pub fn new(config: FilterConfig, public_suffix_list: PublicSuffixList) -> Result<Self, Error> {
+        if config.reporters.require_authentication && config.reporters.authserv_ids.is_empty() {
+            return Err(Error::MissingAuthservIds);
         }
+        Ok(Self { 
+            bot_user_agents: RegexSet::new(&config.bots.user_agents)?,
+            rules: RuleSet::new(&config.rules, config.default_rules)?,
+            whitelist: DomainList::new(&config.domain_whitelist)?,
+            blacklist: DomainList::new(&config.domain_blacklist)?,
+            allowed_reporters: ReporterList::new(&config.reporters.allow)?,
+            denied_reporters: ReporterList::new(&config.reporters.deny)?,
+            config,
+            public_suffix_list
+        })
     }
 
     /**
@@ -45,12 +124,264 @@ impl Filter {
         return false;
     }
 
+    /**
+     * Hosts are normalized (case, IDNA, trailing dot), the blacklist takes precedence over the whitelist
+     */
     pub fn is_domain_allowed(&self, host: &str) -> bool {
-        if self.config.domain_whitelist.is_empty() || self.config.contains_domain(host) {
+        let Some(normalized) = Host::parse(host) else {
+            debug!("got report for invalid domain \"{}\" -> drop", host);
+            return false;
+        };
+        let registrable_domain = match &normalized {
+            Host::Domain(domain) if self.config.match_registrable_domain => self.public_suffix_list.registrable_domain(domain).map(Host::Domain),
+            _ => None
+        };
+        let contains = |list: &DomainList| list.contains(&normalized) || registrable_domain.as_ref().is_some_and(|d| list.contains(d));
+        if contains(&self.blacklist) {
+            debug!("got report for domain \"{}\", which is blacklisted -> drop", host);
+            return false;
+        }
+        if self.whitelist.is_empty() || contains(&self.whitelist) {
             return true;
         } else {
             debug!("got report for domain \"{}\", which is not whitelisted -> drop", host);
             return false;
         }
     }
+
+    /**
+     * Checks the sender of DMARC and SMTP TLS reports, authentication results are available for reports received by e-mail only
+     */
+    pub fn is_reporter_allowed(&self, organization: &str, contact: &str, authentication: Option<&[MessageAuthentication]>) -> bool {
+        let domain = contact_domain(contact);
+        if self.denied_reporters.contains(organization, domain.as_ref()) {
+            debug!("got report of reporter \"{}\" ({}), which is denied -> drop", organization, contact);
+            return false;
+        }
+        if !self.allowed_reporters.is_empty() && !self.allowed_reporters.contains(organization, domain.as_ref()) {
+            debug!("got report of reporter \"{}\" ({}), which is not allowed -> drop", organization, contact);
+            return false;
+        }
+        if let Some(results) = authentication.filter(|_| self.config.reporters.require_authentication) {
+            if !self.is_reporter_authenticated(domain.as_ref(), results) {
+                debug!("got report of reporter \"{}\" ({}), which is not authenticated -> drop", organization, contact);
+                return false;
+            }
+        }
+        true
+    }
+
+    /**
+     * Requires a DKIM, SPF or DMARC pass of the (registrable) domain of the reporter's address in an Authentication-Results header of a trusted authserv-id
+     */
+    fn is_reporter_authenticated(&self, domain: Option<&Host>, results: &[MessageAuthentication]) -> bool {
+        let Some(Host::Domain(domain)) = domain else {
+            return false;
+        };
+        let organizational_domain = |d: &str| self.public_suffix_list.registrable_domain(d).unwrap_or_else(|| d.to_string());
+        let reporter_domain = organizational_domain(domain);
+        let authserv_ids = &self.config.reporters.authserv_ids;
+        results.iter()
+            .filter(|r| authserv_ids.iter().any(|id| id.eq_ignore_ascii_case(&r.authserv_id)))
+            .filter(|r| r.result == "pass" && matches!(r.method.as_str(), "dkim" | "spf" | "dmarc"))
+            .filter_map(|r| r.domain.as_deref().and_then(Host::parse))
+            .any(|host| matches!(host, Host::Domain(d) if organizational_domain(&d) == reporter_domain))
+    }
+
+    /**
+     * Classifies the reporter as bot by its user agent (derived device) or IP address
+     */
+    pub fn classify_bot(&self, user_agent: Option<&str>, device: &Device, client_ip: Option<IpAddr>) -> Option<BotReason> {
+        if device.family == "Spider" {
+            return Some(BotReason::Spider);
+        }
+        if let Some(ua) = user_agent {
+            if HEADLESS_SIGNATURES.iter().any(|signature| ua.contains(signature)) {
+                return Some(BotReason::Headless);
+            }
+            if self.bot_user_agents.is_match(ua) {
+                return Some(BotReason::UserAgent);
+            }
+        }
+        if let Some(ip) = client_ip {
+            if self.config.bots.ip_ranges.iter().any(|range| range.contains(&ip)) {
+                return Some(BotReason::IpRange);
+            }
+        }
+        None
+    }
+
+    pub fn is_bot_allowed(&self, reason: BotReason) -> bool {
+        if self.config.bots.action == BotAction::Drop {
+            debug!("got report from bot ({:?}) -> drop", reason);
+            false
+        } else {
+            true
+        }
+    }
+
+    pub fn has_rules(&self) -> bool {
+        !self.rules.is_empty()
+    }
+
+    /**
+     * Applies the filter rules to the report as logged, reports not matching any rule are allowed
+     */
+    pub fn is_report_allowed(&self, report_type: &str, report: &Value) -> bool {
+        self.rules.evaluate(report_type, report) != Some(FilterRuleAction::Deny)
+    }
+
+    pub fn rule_statistics(&self) -> Vec<RuleStatistics> {
+        self.rules.statistics()
+    }
+}
+
+/**
+ * Organizations and contact domains of reporters
+ */
+#[derive(Clone)]
+struct ReporterList {
+    organizations: Vec<String>,
+    domains: DomainList
+}
+
+impl ReporterList {
+    fn new(config: &ReporterListConfig) -> Result<Self, Error> {
+        Ok(Self {
+            organizations: config.organizations.iter().map(|o| o.trim().to_lowercase()).collect(),
+            domains: DomainList::new(&config.domains)?
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.organizations.is_empty() && self.domains.is_empty()
+    }
+
+    fn contains(&self, organization: &str, domain: Option<&Host>) -> bool {
+        let organization = organization.trim().to_lowercase();
+        self.organizations.contains(&organization) || domain.is_some_and(|d| self.domains.contains(d))
+    }
+}
+
+/**
+ * Domain of an e-mail address (optionally as mailto: URI) or of a HTTPS URL
+ */
+fn contact_domain(contact: &str) -> Option<Host> {
+    let contact = contact.trim();
+    if let Some(host) = Url::parse(contact).ok().and_then(|url| url.host_str().and_then(Host::parse)) {
+        return Some(host);
+    }
+    contact.rsplit_once('@').and_then(|(_, domain)| Host::parse(domain))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{BotFilterConfig, DomainConfigType, ReporterFilterConfig};
+
+    use std::path::Path;
+
+    use super::*;
+
+    fn public_suffix_list() -> PublicSuffixList {
+        PublicSuffixList::load(Path::new("./public_suffix_list.dat")).unwrap()
+    }
+
+    #[test]
+    fn registrable_domain_whitelist() {
+        let mut config = FilterConfig {
+            domain_whitelist: vec![DomainConfigType::Simple("example.co.uk".to_string())],
+            ..FilterConfig::default()
+        };
+        assert!(!Filter::new(config.clone(), public_suffix_list()).unwrap().is_domain_allowed("www.example.co.uk"));
+        config.match_registrable_domain = true;
+        let filter = Filter::new(config, public_suffix_list()).unwrap();
+        assert!(filter.is_domain_allowed("www.example.co.uk"));
+        assert!(filter.is_domain_of_url_allowed("https://a.b.example.co.uk/"));
+        assert!(!filter.is_domain_allowed("example.org.uk"));
+    }
+
+    #[test]
+    fn domain_blacklist() {
+        let filter = Filter::new(FilterConfig {
+            domain_whitelist: vec![DomainConfigType::Simple("*.example.com".to_string()), DomainConfigType::Simple("2001:db8::/32".to_string())],
+            domain_blacklist: vec![DomainConfigType::Simple("*.cdn.example.com".to_string())],
+            ..FilterConfig::default()
+        }, public_suffix_list()).unwrap();
+        assert!(filter.is_domain_allowed("WWW.Example.com."));
+        assert!(!filter.is_domain_allowed("example.com"));
+        assert!(!filter.is_domain_allowed("img.cdn.example.com"));
+        assert!(filter.is_domain_of_url_allowed("https://[2001:db8::1]:8443/"));
+        assert!(!filter.is_domain_of_url_allowed("https://192.0.2.1/"));
+        assert!(Filter::new(FilterConfig {
+            domain_blacklist: vec![DomainConfigType::Simple("exa mple.com".to_string())],
+            ..FilterConfig::default()
+        }, public_suffix_list()).is_err());
+    }
+
+    #[test]
+    fn reporters() {
+        let filter = Filter::new(FilterConfig {
+            reporters: ReporterFilterConfig {
+                allow: ReporterListConfig {
+                    organizations: vec!["Google Inc.".to_string()],
+                    domains: vec![DomainConfigType::Simple("*.example.net".to_string())]
+                },
+                deny: ReporterListConfig {
+                    organizations: vec![],
+                    domains: vec![DomainConfigType::Simple("spam.example.net".to_string())]
+                },
+                require_authentication: true,
+                authserv_ids: vec!["mx.example.com".to_string()]
+            },
+            ..FilterConfig::default()
+        }, public_suffix_list()).unwrap();
+        assert!(filter.is_reporter_allowed("google inc.", "smtp-tls-reporting@google.com", None));
+        assert!(filter.is_reporter_allowed("Other", "mailto:tls@mail.example.net", None));
+        assert!(filter.is_reporter_allowed("Other", "https://reports.example.net/tls", None));
+        assert!(!filter.is_reporter_allowed("Other", "dmarc@spam.example.net", None));
+        assert!(!filter.is_reporter_allowed("Other", "dmarc@example.org", None));
+
+        let dkim = |authserv_id: &str, domain: &str| MessageAuthentication {
+            authserv_id: authserv_id.to_string(),
+            method: "dkim".to_string(),
+            result: "pass".to_string(),
+            domain: Some(domain.to_string())
+        };
+        assert!(filter.is_reporter_allowed("Google Inc.", "noreply-dmarc-support@google.com", Some(&[dkim("mx.example.com", "google.com")])));
+        assert!(filter.is_reporter_allowed("Other", "dmarc@mail.example.net", Some(&[dkim("mx.example.com", "example.net")])));
+        assert!(!filter.is_reporter_allowed("Google Inc.", "noreply-dmarc-support@google.com", Some(&[dkim("mx.example.com", "example.org")])));
+        assert!(!filter.is_reporter_allowed("Google Inc.", "noreply-dmarc-support@google.com", Some(&[dkim("forged.example.org", "google.com")])));
+        assert!(!filter.is_reporter_allowed("Google Inc.", "noreply-dmarc-support@google.com", Some(&[])));
+
+        // without authserv-ids headers added by the sender would be trusted
+        assert!(Filter::new(FilterConfig {
+            reporters: ReporterFilterConfig {
+                require_authentication: true,
+                ..ReporterFilterConfig::default()
+            },
+            ..FilterConfig::default()
+        }, public_suffix_list()).is_err());
+    }
+
+    #[test]
+    fn classify_bots() {
+        let filter = Filter::new(FilterConfig {
+            bots: BotFilterConfig {
+                user_agents: vec!["(?i)security-?scanner".to_string()],
+                ip_ranges: vec!["192.0.2.0/24".parse().unwrap(), "2001:db8::/32".parse().unwrap()],
+                action: BotAction::Drop
+            },
+            ..FilterConfig::default()
+        }, public_suffix_list()).unwrap();
+        let browser = Device { family: "Other".to_string(), ..Device::default() };
+        let chrome = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36";
+        let spider = Device { family: "Spider".to_string(), ..Device::default() };
+        assert_eq!(filter.classify_bot(Some(chrome), &spider, None), Some(BotReason::Spider));
+        assert_eq!(filter.classify_bot(Some(&chrome.replace("Chrome", "HeadlessChrome")), &browser, None), Some(BotReason::Headless));
+        assert_eq!(filter.classify_bot(Some("Security-Scanner/1.0"), &browser, None), Some(BotReason::UserAgent));
+        assert_eq!(filter.classify_bot(Some(chrome), &browser, Some("2001:db8::1".parse().unwrap())), Some(BotReason::IpRange));
+        assert_eq!(filter.classify_bot(Some(chrome), &browser, Some("198.51.100.1".parse().unwrap())), None);
+        assert!(!filter.is_bot_allowed(BotReason::Spider));
+        assert!(Filter::new(FilterConfig::default(), public_suffix_list()).unwrap().is_bot_allowed(BotReason::Spider));
+    }
 }
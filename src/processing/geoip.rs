@@ -0,0 +1,259 @@
+/**
+ * network-journal - collect network reports and print them to file
+ * Copyright (C) 2025 nerou GmbH
+ * 
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{fmt::Display, net::IpAddr, path::{Path, PathBuf}, sync::{Arc, RwLock}, time::SystemTime};
+
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+use serde::Serialize;
+
+use crate::{config::GeoIpConfig, processing::derivation::modification_time};
+
+#[derive(Debug)]
+pub enum Error {
+    Database(PathBuf, MaxMindDBError)
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Database(path, err) => write!(f, "failed to load MaxMind DB {}: {}", path.display(), err)
+        }
+    }
+}
+
+/**
+ * Location, autonomous system and reverse DNS name of an IP address of a report
+ */
+#[derive(Serialize, Default, PartialEq, Clone, Debug)]
+pub struct IpInfo {
+    /// field of the report (e.g. server_ip) or client_ip for the submitting client
+    pub field: String,
+    /// omitted for the submitting client
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    /// ISO 3166-1 alpha-2 code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    /// english name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asn: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub as_organization: Option<String>,
+    /// PTR name of the sources of DMARC and SMTP TLS reports
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// whether the hostname resolves back to the address (forward-confirmed reverse DNS)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fcrdns: Option<bool>
+}
+
+struct Database {
+    path: PathBuf,
+    reader: RwLock<(Reader<Vec<u8>>, Option<SystemTime>)>
+}
+
+impl Database {
+    fn open(path: &Path) -> Result<Self, Error> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            reader: RwLock::new(open_reader(path)?)
+        })
+    }
+
+    fn reload(&self) -> Result<(), Error> {
+        let reader = open_reader(&self.path)?;
+        *self.reader.write().unwrap() = reader;
+        Ok(())
+    }
+
+    fn reload_if_changed(&self) -> Result<bool, Error> {
+        let modified = modification_time(&self.path);
+        if modified.is_none() || modified == self.reader.read().unwrap().1 {
+            return Ok(false);
+        }
+        if let Err(err) = self.reload() {
+            // do not retry until the database is modified again
+            self.reader.write().unwrap().1 = modified;
+            return Err(err);
+        }
+        Ok(true)
+    }
+}
+
+/**
+ * Offline lookup of IP addresses in MaxMind DBs (GeoLite2/GeoIP2 or compatible), which are reloaded on change
+ */
+#[derive(Clone)]
+pub struct GeoIp {
+    location: Option<Arc<Database>>,
+    asn: Option<Arc<Database>>
+}
+
+impl GeoIp {
+    pub fn new(config: &GeoIpConfig) -> Result<Self, Error> {
+        Ok(Self {
+            location: config.location.as_deref().map(Database::open).transpose()?.map(Arc::new),
+            asn: config.asn.as_deref().map(Database::open).transpose()?.map(Arc::new)
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.location.is_some() || self.asn.is_some()
+    }
+
+    pub fn reload(&self) -> Result<(), Error> {
+        for database in self.databases() {
+            database.reload()?;
+        }
+        Ok(())
+    }
+
+    /**
+     * Reloads the databases modified since they have been loaded. 
+     * Returns whether a database has been reloaded.
+     */
+    pub fn reload_if_changed(&self) -> Result<bool, Error> {
+        let mut reloaded = false;
+        for database in self.databases() {
+            reloaded |= database.reload_if_changed()?;
+        }
+        Ok(reloaded)
+    }
+
+    /**
+     * None if the address is in none of the databases
+     */
+    pub fn lookup(&self, field: &str, ip: IpAddr) -> Option<IpInfo> {
+        let mut info = IpInfo {
+            field: field.to_string(),
+            ip: Some(ip.to_string()),
+            ..IpInfo::default()
+        };
+        if let Some(database) = &self.location {
+            let reader = database.reader.read().unwrap();
+            if let Ok(city) = reader.0.lookup::<geoip2::City>(ip) {
+                info.country = city.country.and_then(|c| c.iso_code).map(|c| c.to_string());
+                info.city = city.city.and_then(|c| c.names).and_then(|n| n.get("en").map(|c| c.to_string()));
+            }
+        }
+        if let Some(database) = &self.asn {
+            let reader = database.reader.read().unwrap();
+            if let Ok(asn) = reader.0.lookup::<geoip2::Asn>(ip) {
+                info.asn = asn.autonomous_system_number;
+                info.as_organization = asn.autonomous_system_organization.map(|o| o.to_string());
+            }
+        }
+        if info.country.is_none() && info.city.is_none() && info.asn.is_none() {
+            return None;
+        }
+        Some(info)
+    }
+
+    fn databases(&self) -> impl Iterator<Item = &Arc<Database>> {
+        self.location.iter().chain(self.asn.iter())
+    }
+}
+
+fn open_reader(path: &Path) -> Result<(Reader<Vec<u8>>, Option<SystemTime>), Error> {
+    let modified = modification_time(path);
+    let reader = Reader::open_readfile(path).map_err(|err| Error::Database(path.to_path_buf(), err))?;
+    Ok((reader, modified))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::*;
+
+    /// MaxMind DB format: string and map of the data section
+    fn string(s: &str) -> Vec<u8> {
+        let size = match s.len() {
+            len if len < 29 => vec![0x40 | len as u8],
+            len => vec![0x40 | 29, (len - 29) as u8]
+        };
+        [size, s.as_bytes().to_vec()].concat()
+    }
+
+    fn map(entries: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut res = vec![0xe0 | entries.len() as u8];
+        for (key, value) in entries {
+            res.extend(string(key));
+            res.extend(value);
+        }
+        res
+    }
+
+    fn uint(type_byte: u8, value: u32) -> Vec<u8> {
+        [vec![type_byte | 4], value.to_be_bytes().to_vec()].concat()
+    }
+
+    /**
+     * Database of a single record all addresses resolve to
+     */
+    fn database(record: Vec<u8>) -> Vec<u8> {
+        let metadata = map(&[
+            ("binary_format_major_version", [vec![0xa0 | 2], 2u16.to_be_bytes().to_vec()].concat()),
+            ("binary_format_minor_version", vec![0xa0]),
+            ("build_epoch", vec![0x01, 0x02, 0x00]),
+            ("database_type", string("Test")),
+            ("description", map(&[])),
+            ("ip_version", [vec![0xa0 | 2], 6u16.to_be_bytes().to_vec()].concat()),
+            ("languages", vec![0x00, 0x04]),
+            ("node_count", uint(0xc0, 1)),
+            ("record_size", [vec![0xa0 | 2], 24u16.to_be_bytes().to_vec()].concat())
+        ]);
+        // one node, both records point to the data at offset 0 (node count + 16)
+        [vec![0, 0, 17, 0, 0, 17], vec![0; 16], record, b"\xab\xcd\xefMaxMind.com".to_vec(), metadata].concat()
+    }
+
+    #[test]
+    fn lookup_addresses() {
+        let dir = env::temp_dir().join(format!("network-journal-geoip-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (location, asn) = (dir.join("city.mmdb"), dir.join("asn.mmdb"));
+        fs::write(&location, database(map(&[
+            ("city", map(&[("names", map(&[("en", string("Berlin"))]))])),
+            ("country", map(&[("iso_code", string("DE"))]))
+        ]))).unwrap();
+        fs::write(&asn, database(map(&[
+            ("autonomous_system_number", uint(0xc0, 64496)),
+            ("autonomous_system_organization", string("Example AS"))
+        ]))).unwrap();
+
+        let geoip = GeoIp::new(&GeoIpConfig { location: Some(location.clone()), asn: Some(asn.clone()), reload_interval: 0 }).unwrap();
+        assert!(geoip.is_enabled());
+        assert_eq!(geoip.lookup("server_ip", "192.0.2.1".parse().unwrap()), Some(IpInfo {
+            field: "server_ip".to_string(),
+            ip: Some("192.0.2.1".to_string()),
+            country: Some("DE".to_string()),
+            city: Some("Berlin".to_string()),
+            asn: Some(64496),
+            as_organization: Some("Example AS".to_string()),
+            ..IpInfo::default()
+        }));
+
+        fs::write(&location, b"no database").unwrap();
+        assert!(geoip.reload().is_err());
+        assert!(!GeoIp::new(&GeoIpConfig::default()).unwrap().is_enabled());
+        assert!(GeoIp::new(&GeoIpConfig { location: Some(dir.join("missing.mmdb")), ..GeoIpConfig::default() }).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,180 @@
+/**
+ * network-journal - collect network reports and print them to file
+ * Copyright (C) 2025 nerou GmbH
+ * 
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{collections::HashSet, fmt::Display, fs, net::IpAddr, path::{Path, PathBuf}, sync::{Arc, RwLock}};
+
+#[derive(Debug)]
+pub enum Error {
+    IO(PathBuf, std::io::Error)
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::IO(path, err) => write!(f, "failed to read public suffix list {}: {}", path.display(), err)
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+struct Rules {
+    suffixes: HashSet<String>,
+    /// parents of wildcard rules e.g. `ck` of `*.ck`
+    wildcards: HashSet<String>,
+    /// exception rules without the leading `!`
+    exceptions: HashSet<String>
+}
+
+impl Rules {
+    fn parse(list: &str) -> Self {
+        let mut rules = Rules::default();
+        for line in list.lines() {
+            let rule = match line.split_whitespace().next() {
+                Some(rule) if !rule.starts_with("//") => rule,
+                _ => continue
+            };
+            let (rule, set) = if let Some(exception) = rule.strip_prefix('!') {
+                (exception, &mut rules.exceptions)
+            } else if let Some(parent) = rule.strip_prefix("*.") {
+                (parent, &mut rules.wildcards)
+            } else {
+                (rule, &mut rules.suffixes)
+            };
+            // hosts of parsed URLs are punycode encoded
+            if let Ok(ascii) = idna::domain_to_ascii(rule) {
+                set.insert(ascii);
+            }
+        }
+        rules
+    }
+
+    /**
+     * Number of labels of the public suffix of the host according to the algorithm at <https://publicsuffix.org/list/>
+     */
+    fn suffix_len(&self, labels: &[&str]) -> usize {
+        for i in 0..labels.len() {
+            let candidate = labels[i..].join(".");
+            if self.exceptions.contains(&candidate) {
+                return labels.len() - i - 1;
+            }
+            if self.suffixes.contains(&candidate) || (i + 1 < labels.len() && self.wildcards.contains(&labels[i + 1..].join("."))) {
+                return labels.len() - i;
+            }
+        }
+        // implicit rule "*"
+        1
+    }
+}
+
+/**
+ * Public Suffix List to determine registrable domains (eTLD+1), which can be reloaded at runtime
+ */
+#[derive(Clone)]
+pub struct PublicSuffixList {
+    path: PathBuf,
+    rules: Arc<RwLock<Rules>>
+}
+
+impl PublicSuffixList {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Ok(PublicSuffixList {
+            path: path.to_path_buf(),
+            rules: Arc::new(RwLock::new(read_rules(path)?))
+        })
+    }
+
+    /**
+     * Reload the list. On failure the previously loaded rules stay in use.
+     */
+    pub fn reload(&self) -> Result<(), Error> {
+        let rules = read_rules(&self.path)?;
+        *self.rules.write().unwrap() = rules;
+        Ok(())
+    }
+
+    /**
+     * Splits a host into its registrable domain and subdomain (if any).
+     * Returns None for IP addresses and hosts which are public suffixes themselves.
+     */
+    pub fn split(&self, host: &str) -> Option<(String, Option<String>)> {
+        let host = host.trim_end_matches('.').to_lowercase();
+        if host.parse::<IpAddr>().is_ok() || host.starts_with('[') {
+            return None;
+        }
+        let labels = host.split('.').collect::<Vec<_>>();
+        if labels.iter().any(|label| label.is_empty()) {
+            return None;
+        }
+        let suffix_len = self.rules.read().unwrap().suffix_len(&labels);
+        if labels.len() <= suffix_len {
+            return None;
+        }
+        let split_at = labels.len() - suffix_len - 1;
+        let subdomain = if split_at > 0 {
+            Some(labels[..split_at].join("."))
+        } else {
+            None
+        };
+        Some((labels[split_at..].join("."), subdomain))
+    }
+
+    pub fn registrable_domain(&self, host: &str) -> Option<String> {
+        self.split(host).map(|(registrable_domain, _)| registrable_domain)
+    }
+}
+
+fn read_rules(path: &Path) -> Result<Rules, Error> {
+    fs::read_to_string(path)
+        .map(|list| Rules::parse(&list))
+        .map_err(|err| Error::IO(path.to_path_buf(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(rules: &str) -> PublicSuffixList {
+        PublicSuffixList {
+            path: PathBuf::new(),
+            rules: Arc::new(RwLock::new(Rules::parse(rules)))
+        }
+    }
+
+    #[test]
+    fn split_hosts() {
+        let psl = list("// comment\ncom\nuk\nco.uk\n*.ck\n!www.ck\n公司.cn\ncn\ngithub.io\n");
+        assert_eq!(psl.split("www.Example.COM."), Some(("example.com".to_string(), Some("www".to_string()))));
+        assert_eq!(psl.split("example.com"), Some(("example.com".to_string(), None)));
+        assert_eq!(psl.split("a.b.example.co.uk"), Some(("example.co.uk".to_string(), Some("a.b".to_string()))));
+        assert_eq!(psl.split("co.uk"), None);
+        assert_eq!(psl.split("a.foo.ck"), Some(("a.foo.ck".to_string(), None)));
+        assert_eq!(psl.split("www.ck"), Some(("www.ck".to_string(), None)));
+        assert_eq!(psl.registrable_domain("shop.xn--55qx5d.cn").as_deref(), Some("shop.xn--55qx5d.cn"));
+        assert_eq!(psl.registrable_domain("user.github.io").as_deref(), Some("user.github.io"));
+        assert_eq!(psl.registrable_domain("example.unknown").as_deref(), Some("example.unknown"));
+        assert_eq!(psl.split("192.0.2.1"), None);
+        assert_eq!(psl.split("[2001:db8::1]"), None);
+    }
+
+    #[test]
+    fn bundled_list() {
+        let psl = PublicSuffixList::load(Path::new("./public_suffix_list.dat")).unwrap();
+        assert_eq!(psl.registrable_domain("www.example.co.uk").as_deref(), Some("example.co.uk"));
+        assert_eq!(psl.registrable_domain("foo.blogspot.com").as_deref(), Some("foo.blogspot.com"));
+    }
+}
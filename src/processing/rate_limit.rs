@@ -0,0 +1,316 @@
+/**
+ * network-journal - collect network reports and print them to file
+ * Copyright (C) 2025 nerou GmbH
+ * 
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{collections::HashMap, fmt::Display, net::IpAddr, num::NonZeroUsize, sync::{Arc, Mutex}, time::Instant};
+
+use ipnet::Ipv6Net;
+use log::debug;
+use lru::LruCache;
+use serde::Serialize;
+
+use crate::{config::{RateLimit, RateLimitConfig}, processing::RequestInfo};
+
+/// buckets of clients and hosts kept, the least recently used ones start over with a full bucket
+const BUCKET_CACHE_SIZE: usize = 100_000;
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidLimit(String),
+    InvalidPrefixLength(u8)
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidLimit(limit) => write!(f, "invalid rate limit {}, rate and burst have to be positive", limit),
+            Error::InvalidPrefixLength(prefix) => write!(f, "invalid IPv6 prefix length {} of client rate limits, it has to be at most 128", prefix)
+        }
+    }
+}
+
+#[derive(Serialize, PartialEq, Eq, Hash, Clone, Copy, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scope {
+    Global,
+    Client,
+    Host
+}
+
+#[derive(Serialize, Debug)]
+pub struct RateLimitStatistics {
+    pub endpoint: String,
+    pub scope: Scope,
+    pub dropped: u64
+}
+
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant
+}
+
+impl TokenBucket {
+    fn new(limit: &RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst,
+            updated: now
+        }
+    }
+
+    /**
+     * Takes a token, otherwise returns the seconds until the next token is available
+     */
+    fn take(&mut self, limit: &RateLimit, now: Instant) -> Result<(), u64> {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate).min(limit.burst);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - self.tokens) / limit.rate).ceil().max(1.0) as u64)
+        }
+    }
+
+    /**
+     * Puts back a token taken for a request, which has been dropped nonetheless
+     */
+    fn refund(&mut self, limit: &RateLimit) {
+        self.tokens = (self.tokens + 1.0).min(limit.burst);
+    }
+}
+
+struct State {
+    /// keyed by scope, endpoint ("*" for the limits shared by all endpoints) and client IP (IPv6 network) or host
+    buckets: LruCache<(Scope, String, String), TokenBucket>,
+    dropped: HashMap<(String, Scope), u64>
+}
+
+/**
+ * Token bucket rate limits per client IP, per reported host and of all requests
+ */
+#[derive(Clone)]
+pub struct RateLimiter {
+    config: Arc<RateLimitConfig>,
+    state: Arc<Mutex<State>>
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Result<Self, Error> {
+        let limits = [("global", &config.global), ("client", &config.client), ("host", &config.host)].into_iter()
+            .chain(config.endpoints.iter().flat_map(|(endpoint, e)| [(endpoint.as_str(), &e.global), (endpoint.as_str(), &e.client), (endpoint.as_str(), &e.host)]));
+        for (name, limit) in limits {
+            if let Some(limit) = limit.filter(|l| !(l.rate > 0.0 && l.burst >= 1.0)) {
+                return Err(Error::InvalidLimit(format!("{} ({}/s, burst {})", name, limit.rate, limit.burst)));
+            }
+        }
+        if config.client_ipv6_prefix > 128 {
+            return Err(Error::InvalidPrefixLength(config.client_ipv6_prefix));
+        }
+        Ok(Self {
+            config: Arc::new(config),
+            state: Arc::new(Mutex::new(State {
+                buckets: LruCache::new(NonZeroUsize::new(BUCKET_CACHE_SIZE).unwrap()),
+                dropped: HashMap::new()
+            }))
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.enable
+    }
+
+    /**
+     * Checks the limits per client and of all requests to an endpoint, returns the seconds to retry after if exceeded
+     */
+    pub fn check_request(&self, request: &RequestInfo) -> Result<(), u64> {
+        if !self.config.enable {
+            return Ok(());
+        }
+        let client_key = request.client_ip.map(|ip| self.client_key(ip));
+        if let Some(key) = &client_key {
+            self.take(&request.endpoint, Scope::Client, key)?;
+        }
+        let res = self.take(&request.endpoint, Scope::Global, "");
+        if let (Err(_), Some(key)) = (res, &client_key) {
+            // the client is not charged for requests dropped due to the global limit
+            self.refund(&request.endpoint, Scope::Client, key);
+        }
+        res
+    }
+
+    /**
+     * Checks the limit per reported host, returns the seconds to retry after if exceeded
+     */
+    pub fn check_host(&self, endpoint: &str, host: &str) -> Result<(), u64> {
+        if !self.config.enable {
+            return Ok(());
+        }
+        self.take(endpoint, Scope::Host, &host.to_lowercase())
+    }
+
+    pub fn statistics(&self) -> Vec<RateLimitStatistics> {
+        let mut statistics: Vec<RateLimitStatistics> = self.state.lock().unwrap().dropped.iter()
+            .map(|((endpoint, scope), dropped)| RateLimitStatistics {
+                endpoint: endpoint.clone(),
+                scope: *scope,
+                dropped: *dropped
+            })
+            .collect();
+        statistics.sort_by(|a, b| (&a.endpoint, a.scope as u8).cmp(&(&b.endpoint, b.scope as u8)));
+        statistics
+    }
+
+    /**
+     * IPv6 clients can pick any address of their network, so they are limited per network
+     */
+    fn client_key(&self, ip: IpAddr) -> String {
+        match ip {
+            IpAddr::V6(ip) => Ipv6Net::new(ip, self.config.client_ipv6_prefix).map_or_else(|_| ip.to_string(), |net| net.trunc().to_string()),
+            IpAddr::V4(ip) => ip.to_string()
+        }
+    }
+
+    /**
+     * Limit of the scope and the endpoint its bucket belongs to
+     */
+    fn limit<'a>(&'a self, endpoint: &'a str, scope: Scope) -> Option<(RateLimit, &'a str)> {
+        let select = |global: &Option<RateLimit>, client: &Option<RateLimit>, host: &Option<RateLimit>| match scope {
+            Scope::Global => *global,
+            Scope::Client => *client,
+            Scope::Host => *host
+        };
+        if let Some(limit) = self.config.endpoints.get(endpoint).and_then(|e| select(&e.global, &e.client, &e.host)) {
+            return Some((limit, endpoint));
+        }
+        select(&self.config.global, &self.config.client, &self.config.host).map(|limit| (limit, "*"))
+    }
+
+    fn take(&self, endpoint: &str, scope: Scope, key: &str) -> Result<(), u64> {
+        let Some((limit, bucket_endpoint)) = self.limit(endpoint, scope) else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let res = state.buckets
+            .get_or_insert_mut((scope, bucket_endpoint.to_string(), key.to_string()), || TokenBucket::new(&limit, now))
+            .take(&limit, now);
+        if res.is_err() {
+            debug!("rate limit of {:?} {} exceeded at {} -> drop", scope, key, endpoint);
+            *state.dropped.entry((endpoint.to_string(), scope)).or_default() += 1;
+        }
+        res
+    }
+
+    fn refund(&self, endpoint: &str, scope: Scope, key: &str) {
+        let Some((limit, bucket_endpoint)) = self.limit(endpoint, scope) else {
+            return;
+        };
+        if let Some(bucket) = self.state.lock().unwrap().buckets.get_mut(&(scope, bucket_endpoint.to_string(), key.to_string())) {
+            bucket.refund(&limit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use crate::config::EndpointRateLimitConfig;
+
+    use super::*;
+
+    #[test]
+    fn token_bucket() {
+        let limit = RateLimit { rate: 2.0, burst: 3.0 };
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(&limit, start);
+        assert!((0..3).all(|_| bucket.take(&limit, start).is_ok()));
+        assert_eq!(bucket.take(&limit, start), Err(1));
+        assert_eq!(bucket.take(&limit, start + Duration::from_millis(500)), Ok(()));
+        assert_eq!(bucket.take(&limit, start + Duration::from_millis(500)), Err(1));
+        assert_eq!(bucket.take(&limit, start + Duration::from_secs(60)), Ok(()));
+    }
+
+    #[test]
+    fn rate_limits() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            enable: true,
+            global: None,
+            client: Some(RateLimit { rate: 0.1, burst: 2.0 }),
+            client_ipv6_prefix: 64,
+            host: Some(RateLimit { rate: 0.1, burst: 1.0 }),
+            endpoints: HashMap::from([("/nel".to_string(), EndpointRateLimitConfig {
+                client: Some(RateLimit { rate: 0.1, burst: 1.0 }),
+                ..EndpointRateLimitConfig::default()
+            })]),
+            statistics_interval: 0
+        }).unwrap();
+        let request = |endpoint: &str, ip: &str| RequestInfo {
+            endpoint: endpoint.to_string(),
+            client_ip: Some(ip.parse().unwrap()),
+            ..RequestInfo::default()
+        };
+        assert!(limiter.check_request(&request("/csp", "192.0.2.1")).is_ok());
+        assert!(limiter.check_request(&request("/crash", "192.0.2.1")).is_ok());
+        assert_eq!(limiter.check_request(&request("/csp", "192.0.2.1")), Err(10));
+        assert!(limiter.check_request(&request("/csp", "192.0.2.2")).is_ok());
+        assert!(limiter.check_request(&request("/nel", "192.0.2.1")).is_ok());
+        assert!(limiter.check_request(&request("/nel", "192.0.2.1")).is_err());
+
+        // addresses of the same /64 share their bucket
+        assert!(limiter.check_request(&request("/nel", "2001:db8:0:1::1")).is_ok());
+        assert!(limiter.check_request(&request("/nel", "2001:db8:0:1:ffff::2")).is_err());
+        assert!(limiter.check_request(&request("/nel", "2001:db8:0:2::1")).is_ok());
+
+        assert!(limiter.check_host("/csp", "example.com").is_ok());
+        assert!(limiter.check_host("/nel", "Example.com").is_err());
+        assert!(limiter.check_host("/csp", "example.org").is_ok());
+
+        let statistics = limiter.statistics();
+        assert_eq!(statistics.iter().map(|s| (s.endpoint.as_str(), s.scope, s.dropped)).collect::<Vec<_>>(), vec![
+            ("/csp", Scope::Client, 1),
+            ("/nel", Scope::Client, 2),
+            ("/nel", Scope::Host, 1)
+        ]);
+
+        assert!(RateLimiter::new(RateLimitConfig { host: Some(RateLimit { rate: 0.0, burst: 1.0 }), ..RateLimitConfig::default() }).is_err());
+        assert!(RateLimiter::new(RateLimitConfig { client_ipv6_prefix: 129, ..RateLimitConfig::default() }).is_err());
+    }
+
+    #[test]
+    fn global_limit_does_not_charge_clients() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            enable: true,
+            global: Some(RateLimit { rate: 0.1, burst: 1.0 }),
+            client: Some(RateLimit { rate: 0.1, burst: 2.0 }),
+            ..RateLimitConfig::default()
+        }).unwrap();
+        let request = RequestInfo {
+            endpoint: "/csp".to_string(),
+            client_ip: Some("192.0.2.1".parse().unwrap()),
+            ..RequestInfo::default()
+        };
+        assert!(limiter.check_request(&request).is_ok());
+        for _ in 0..3 {
+            assert!(limiter.check_request(&request).is_err());
+        }
+        // the client still has its second token
+        assert!(limiter.take("/csp", Scope::Client, "192.0.2.1").is_ok());
+        assert!(limiter.take("/csp", Scope::Client, "192.0.2.1").is_err());
+    }
+}
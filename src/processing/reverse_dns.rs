@@ -0,0 +1,242 @@
+/**
+ * network-journal - collect network reports and print them to file
+ * Copyright (C) 2025 nerou GmbH
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{collections::HashMap, net::IpAddr, num::NonZeroUsize, sync::{Arc, Mutex}, thread, time::{Duration, Instant}};
+
+use hickory_proto::rr::{Name, RData, RecordType};
+use log::debug;
+use lru::LruCache;
+
+use crate::{config::ReverseDnsConfig, dns::{self, Resolver}};
+
+/// lookups running at once
+const MAX_PARALLEL_LOOKUPS: usize = 16;
+/// PTR names of one address to forward-confirm
+const MAX_PTR_NAMES: usize = 4;
+/// failed lookups (e.g. timed out) are not repeated for each report of a burst
+const FAILED_LOOKUP_TTL: Duration = Duration::from_secs(60);
+
+/**
+ * Result of the reverse lookup of an IP address
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub struct ReverseName {
+    /// first forward-confirmed PTR name or the first PTR name, none without PTR records
+    pub hostname: Option<String>,
+    /// the A/AAAA records of the hostname contain the address (FCrDNS)
+    pub forward_confirmed: bool
+}
+
+/// names of the addresses and their expiry, none for failed lookups
+type Cache = LruCache<IpAddr, (Option<ReverseName>, Instant)>;
+
+/**
+ * Cached reverse DNS lookups with forward-confirmation of the PTR names
+ */
+#[derive(Clone)]
+pub struct ReverseDns {
+    resolver: Option<Resolver>,
+    cache_ttl: Duration,
+    cache: Arc<Mutex<Cache>>
+}
+
+impl ReverseDns {
+    /**
+     * The resolver is only used if reverse lookups are enabled
+     */
+    pub fn new(config: &ReverseDnsConfig, resolver: Resolver) -> Self {
+        Self {
+            resolver: config.enable.then_some(resolver),
+            cache_ttl: Duration::from_secs(config.cache_ttl),
+            cache: Arc::new(Mutex::new(LruCache::new(NonZeroUsize::new(config.cache_size.max(1)).unwrap())))
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.resolver.is_some()
+    }
+
+    /**
+     * Looks up all addresses not cached yet in parallel.
+     * Addresses whose PTR lookup failed (e.g. timed out) are missing in the result and only cached for `FAILED_LOOKUP_TTL`.
+     */
+    pub fn lookup_all(&self, ips: &[IpAddr]) -> HashMap<IpAddr, ReverseName> {
+        let Some(resolver) = &self.resolver else {
+            return HashMap::new();
+        };
+        let mut names = HashMap::new();
+        let mut missing = vec![];
+        {
+            let mut cache = self.cache.lock().unwrap();
+            for ip in ips {
+                match cache.get(ip) {
+                    Some((name, expires)) if *expires > Instant::now() => if let Some(name) = name {
+                        names.insert(*ip, name.clone());
+                    },
+                    _ => if !missing.contains(ip) {
+                        missing.push(*ip);
+                    }
+                }
+            }
+        }
+        for chunk in missing.chunks(MAX_PARALLEL_LOOKUPS) {
+            let results: Vec<_> = thread::scope(|scope| {
+                let handles: Vec<_> = chunk.iter()
+                    .map(|ip| (*ip, scope.spawn(|| lookup(resolver, *ip))))
+                    .collect();
+                handles.into_iter()
+                    .map(|(ip, handle)| (ip, handle.join().expect("reverse DNS lookup panicked")))
+                    .collect()
+            });
+            let mut cache = self.cache.lock().unwrap();
+            for (ip, result) in results {
+                match result {
+                    Ok(name) => {
+                        cache.put(ip, (Some(name.clone()), Instant::now() + self.cache_ttl));
+                        names.insert(ip, name);
+                    },
+                    Err(err) => {
+                        debug!("reverse DNS lookup of {} failed: {}", ip, err);
+                        cache.put(ip, (None, Instant::now() + FAILED_LOOKUP_TTL.min(self.cache_ttl)));
+                    }
+                }
+            }
+        }
+        names
+    }
+}
+
+/**
+ * Queries the PTR records of the address and the A/AAAA records of the PTR names.
+ * A failed forward lookup just leaves the PTR name unconfirmed.
+ */
+fn lookup(resolver: &Resolver, ip: IpAddr) -> Result<ReverseName, dns::Error> {
+    let ptr_names: Vec<String> = resolver.query(&Name::from(ip).to_ascii(), RecordType::PTR)?.records.into_iter()
+        .filter_map(|record| match record {
+            RData::PTR(ptr) => Some(ptr.0.to_ascii().trim_end_matches('.').to_ascii_lowercase()),
+            _ => None
+        })
+        .filter(|name| !name.is_empty())
+        .take(MAX_PTR_NAMES)
+        .collect();
+    let record_type = if ip.is_ipv4() { RecordType::A } else { RecordType::AAAA };
+    for name in &ptr_names {
+        let confirmed = match resolver.query(name, record_type) {
+            Ok(answer) => answer.records.iter().any(|record| match record {
+                RData::A(a) => IpAddr::V4(a.0) == ip,
+                RData::AAAA(aaaa) => IpAddr::V6(aaaa.0) == ip,
+                _ => false
+            }),
+            Err(err) => {
+                debug!("forward lookup of {} ({}) failed: {}", name, ip, err);
+                false
+            }
+        };
+        if confirmed {
+            return Ok(ReverseName {
+                hostname: Some(name.clone()),
+                forward_confirmed: true
+            });
+        }
+    }
+    Ok(ReverseName {
+        hostname: ptr_names.into_iter().next(),
+        forward_confirmed: false
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::UdpSocket, sync::atomic::{AtomicUsize, Ordering}};
+
+    use hickory_proto::{op::{Message, MessageType}, rr::{rdata::{A, PTR}, Record}};
+
+    use crate::config::DnsConfig;
+
+    use super::*;
+
+    /**
+     * Answers PTR and A queries from the given records until the test ends, counting the queries.
+     * Queries of names starting with "unresponsive." are not answered.
+     */
+    fn spawn_dns_server(records: Vec<(&'static str, RData)>) -> (String, Arc<AtomicUsize>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap().to_string();
+        let queries = Arc::new(AtomicUsize::new(0));
+        let counter = queries.clone();
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf) {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let request = Message::from_vec(&buf[..len]).unwrap();
+                let query = request.queries()[0].clone();
+                if query.name().to_ascii().starts_with("unresponsive.") {
+                    continue;
+                }
+                let mut response = Message::new();
+                response.set_id(request.id()).set_message_type(MessageType::Response).add_query(query.clone());
+                for (name, rdata) in &records {
+                    if query.name().to_ascii().eq_ignore_ascii_case(name) && rdata.record_type() == query.query_type() {
+                        response.add_answer(Record::from_rdata(query.name().clone(), 60, rdata.clone()));
+                    }
+                }
+                socket.send_to(&response.to_vec().unwrap(), peer).unwrap();
+            }
+        });
+        (address, queries)
+    }
+
+    #[test]
+    fn forward_confirmed_lookups() {
+        let (address, queries) = spawn_dns_server(vec![
+            ("1.2.0.192.in-addr.arpa.", RData::PTR(PTR(Name::from_ascii("mail.example.com.").unwrap()))),
+            ("mail.example.com.", RData::A(A("192.0.2.1".parse().unwrap()))),
+            ("2.2.0.192.in-addr.arpa.", RData::PTR(PTR(Name::from_ascii("Spoofed.Example.com.").unwrap()))),
+            ("spoofed.example.com.", RData::A(A("198.51.100.1".parse().unwrap()))),
+            ("4.2.0.192.in-addr.arpa.", RData::PTR(PTR(Name::from_ascii("unresponsive.example.com.").unwrap())))
+        ]);
+        let resolver = Resolver::new(&DnsConfig { resolver: Some(address), timeout: 1 }).unwrap();
+        let reverse_dns = ReverseDns::new(&ReverseDnsConfig { enable: true, ..ReverseDnsConfig::default() }, resolver);
+        let ips: Vec<IpAddr> = ["192.0.2.1", "192.0.2.2", "192.0.2.3", "192.0.2.1", "192.0.2.4"].iter().map(|ip| ip.parse().unwrap()).collect();
+
+        let names = reverse_dns.lookup_all(&ips);
+        assert_eq!(names.len(), 4);
+        assert_eq!(names[&ips[0]], ReverseName { hostname: Some("mail.example.com".to_string()), forward_confirmed: true });
+        assert_eq!(names[&ips[1]], ReverseName { hostname: Some("spoofed.example.com".to_string()), forward_confirmed: false });
+        assert_eq!(names[&ips[2]], ReverseName { hostname: None, forward_confirmed: false });
+        // the forward lookup timed out
+        assert_eq!(names[&ips[4]], ReverseName { hostname: Some("unresponsive.example.com".to_string()), forward_confirmed: false });
+        let sent = queries.load(Ordering::SeqCst);
+
+        // cached
+        assert_eq!(reverse_dns.lookup_all(&ips[..2]).len(), 2);
+        assert_eq!(queries.load(Ordering::SeqCst), sent);
+
+        // failed lookups are cached briefly
+        let unresponsive: IpAddr = "192.0.2.5".parse().unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let resolver = Resolver::new(&DnsConfig { resolver: Some(socket.local_addr().unwrap().to_string()), timeout: 1 }).unwrap();
+        let silent = ReverseDns::new(&ReverseDnsConfig { enable: true, ..ReverseDnsConfig::default() }, resolver);
+        assert!(silent.lookup_all(&[unresponsive]).is_empty());
+        assert!(silent.cache.lock().unwrap().get(&unresponsive).is_some_and(|(name, _)| name.is_none()));
+
+        let disabled = ReverseDns::new(&ReverseDnsConfig::default(), Resolver::new(&DnsConfig::default()).unwrap());
+        assert!(!disabled.is_enabled());
+        assert!(disabled.lookup_all(&ips).is_empty());
+    }
+}
@@ -0,0 +1,285 @@
+/**
+ * network-journal - collect network reports and print them to file
+ * Copyright (C) 2025 nerou GmbH
+ * 
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{fmt::Display, sync::{atomic::{AtomicU64, Ordering}, Arc}};
+
+use log::debug;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::config::{FilterConditionConfig, FilterRuleAction, FilterRuleConfig};
+
+/// URL schemes of browser internals and extensions, whose resources are blocked by CSP without any relation to the site
+const BROWSER_EXTENSION_URLS: &str = r"^(chrome|chrome-extension|moz-extension|safari-extension|safari-web-extension|ms-browser-extension|edge)://";
+
+/// hosts of ad injectors, toolbars and security software injecting resources into sites
+const INJECTOR_URLS: &str = r"^https?://([^/]+\.)?(metrext\.com|tlscdn\.com|superfish\.com|image2play\.com|amung\.us|kaspersky-labs\.com|zilionfast\.in|searchfun\.in|datafastguru\.info|pulseadnetwork\.com|linkluster\.com|rafomedia\.com|icontent\.us|amiok\.org|siteheart\.net|injections\.adguard\.com|(resultshub|savingsslider|cdncache|middlerush)-a\.akamaihd\.net)(:\d+)?(/|$)";
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidPattern(String, regex::Error),
+    InvalidCondition(String, String)
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidPattern(rule, err) => write!(f, "invalid pattern in filter rule {}: {}", rule, err),
+            Error::InvalidCondition(rule, field) => write!(f, "condition on {} of filter rule {} needs exactly one of equals, regex or glob", field, rule)
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct RuleStatistics {
+    pub name: String,
+    pub action: FilterRuleAction,
+    pub hits: u64
+}
+
+struct Condition {
+    path: Vec<String>,
+    pattern: Regex
+}
+
+impl Condition {
+    fn new(rule: &str, config: &FilterConditionConfig) -> Result<Self, Error> {
+        let pattern = match (&config.equals, &config.regex, &config.glob) {
+            (Some(equals), None, None) => format!("^{}$", regex::escape(equals)),
+            (None, Some(regex), None) => regex.clone(),
+            (None, None, Some(glob)) => glob_to_regex(glob),
+            _ => return Err(Error::InvalidCondition(rule.to_string(), config.field.clone()))
+        };
+        Ok(Condition {
+            path: config.field.split('.').map(|s| s.to_string()).collect(),
+            pattern: Regex::new(&pattern).map_err(|err| Error::InvalidPattern(rule.to_string(), err))?
+        })
+    }
+
+    /**
+     * Matches if any value at the path matches, values of arrays are matched individually
+     */
+    fn matches(&self, report: &Value) -> bool {
+        select(report, &self.path).into_iter()
+            .flat_map(|value| match value {
+                Value::Array(items) => items.iter().collect(),
+                value => vec![value]
+            })
+            .any(|value| match value {
+                Value::String(s) => self.pattern.is_match(s),
+                Value::Number(_) | Value::Bool(_) => self.pattern.is_match(&value.to_string()),
+                _ => false
+            })
+    }
+}
+
+struct Rule {
+    name: String,
+    action: FilterRuleAction,
+    report_types: Vec<String>,
+    conditions: Vec<Condition>,
+    hits: AtomicU64
+}
+
+impl Rule {
+    fn new(config: &FilterRuleConfig) -> Result<Self, Error> {
+        Ok(Rule {
+            name: config.name.clone(),
+            action: config.action,
+            report_types: config.report_types.clone(),
+            conditions: config.conditions.iter()
+                .map(|condition| Condition::new(&config.name, condition))
+                .collect::<Result<_, _>>()?,
+            hits: AtomicU64::new(0)
+        })
+    }
+
+    fn matches(&self, report_type: &str, report: &Value) -> bool {
+        (self.report_types.is_empty() || self.report_types.iter().any(|t| t == report_type))
+            && self.conditions.iter().all(|condition| condition.matches(report))
+    }
+}
+
+/**
+ * Ordered allow and deny rules, whose hit counters are shared between all clones
+ */
+#[derive(Clone)]
+pub struct RuleSet {
+    rules: Arc<Vec<Rule>>
+}
+
+impl RuleSet {
+    pub fn new(configs: &[FilterRuleConfig], include_default_rules: bool) -> Result<Self, Error> {
+        let defaults = if include_default_rules {
+            default_rules()
+        } else {
+            vec![]
+        };
+        Ok(RuleSet {
+            rules: Arc::new(configs.iter().chain(defaults.iter())
+                .map(Rule::new)
+                .collect::<Result<_, _>>()?)
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /**
+     * Action of the first rule matching the report (as logged), None if no rule matches
+     */
+    pub fn evaluate(&self, report_type: &str, report: &Value) -> Option<FilterRuleAction> {
+        let rule = self.rules.iter().find(|rule| rule.matches(report_type, report))?;
+        rule.hits.fetch_add(1, Ordering::Relaxed);
+        debug!("{} report matches filter rule {} -> {:?}", report_type, rule.name, rule.action);
+        Some(rule.action)
+    }
+
+    pub fn statistics(&self) -> Vec<RuleStatistics> {
+        self.rules.iter()
+            .map(|rule| RuleStatistics {
+                name: rule.name.clone(),
+                action: rule.action,
+                hits: rule.hits.load(Ordering::Relaxed)
+            })
+            .collect()
+    }
+}
+
+/**
+ * Values at a dotted path of a report (as logged), `*` matches any key of objects and any item of arrays
+ */
+pub fn select<'a>(report: &'a Value, path: &[String]) -> Vec<&'a Value> {
+    let mut values = vec![report];
+    for segment in path {
+        values = values.into_iter()
+            .flat_map(|value| match (value, segment.as_str()) {
+                (Value::Object(map), "*") => map.values().collect(),
+                (Value::Array(items), "*") => items.iter().collect(),
+                (Value::Object(map), key) => map.get(key).into_iter().collect(),
+                (Value::Array(items), index) => index.parse::<usize>().ok().and_then(|i| items.get(i)).into_iter().collect(),
+                _ => vec![]
+            })
+            .collect();
+    }
+    values
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let pattern = glob.chars()
+        .map(|c| match c {
+            '*' => ".*".to_string(),
+            '?' => ".".to_string(),
+            c => regex::escape(&c.to_string())
+        })
+        .collect::<String>();
+    format!("^{}$", pattern)
+}
+
+/**
+ * Shipped rules against common noise of browser extensions and ad injectors in CSP reports
+ */
+fn default_rules() -> Vec<FilterRuleConfig> {
+    let deny_csp = |name: &str, field: &str, regex: &str| FilterRuleConfig {
+        name: name.to_string(),
+        action: FilterRuleAction::Deny,
+        report_types: vec!["CSP".to_string()],
+        conditions: vec![FilterConditionConfig {
+            field: field.to_string(),
+            regex: Some(regex.to_string()),
+            ..FilterConditionConfig::default()
+        }]
+    };
+    vec![
+        deny_csp("default:browser-extension-blocked-url", "report.*.blockedUrl", BROWSER_EXTENSION_URLS),
+        deny_csp("default:browser-extension-source-file", "report.*.sourceFile", BROWSER_EXTENSION_URLS),
+        deny_csp("default:injector-blocked-url", "report.*.blockedUrl", INJECTOR_URLS)
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn condition(field: &str, equals: Option<&str>, regex: Option<&str>, glob: Option<&str>) -> FilterConditionConfig {
+        FilterConditionConfig {
+            field: field.to_string(),
+            equals: equals.map(|s| s.to_string()),
+            regex: regex.map(|s| s.to_string()),
+            glob: glob.map(|s| s.to_string())
+        }
+    }
+
+    #[test]
+    fn evaluate_rules() {
+        let rules = RuleSet::new(&[
+            FilterRuleConfig {
+                name: "own-extension".to_string(),
+                action: FilterRuleAction::Allow,
+                report_types: vec![],
+                conditions: vec![condition("report.body.blockedUrl", Some("chrome-extension://abc/script.js"), None, None)]
+            },
+            FilterRuleConfig {
+                name: "dns-errors".to_string(),
+                action: FilterRuleAction::Deny,
+                report_types: vec!["NEL".to_string()],
+                conditions: vec![
+                    condition("report.body.type", None, Some("^dns\\."), None),
+                    condition("derived.client.family", None, None, Some("Chrom*"))
+                ]
+            }
+        ], true).unwrap();
+
+        let csp3 = |blocked_url: &str| json!({"report": {"type": "csp-violation", "body": {"blockedUrl": blocked_url}}});
+        assert_eq!(rules.evaluate("CSP", &csp3("chrome-extension://abc/script.js")), Some(FilterRuleAction::Allow));
+        assert_eq!(rules.evaluate("CSP", &csp3("moz-extension://def/script.js")), Some(FilterRuleAction::Deny));
+        assert_eq!(rules.evaluate("CSP", &csp3("https://gc.kis.v2.scr.kaspersky-labs.com/x.js")), Some(FilterRuleAction::Deny));
+        assert_eq!(rules.evaluate("CSP", &csp3("https://kaspersky-labs.com.example.com/x.js")), None);
+        assert_eq!(rules.evaluate("CSP", &csp3("inline")), None);
+        let csp2 = json!({"report": {"csp-report": {"blockedUrl": "https://x.tlscdn.com/a.js"}}});
+        assert_eq!(rules.evaluate("CSP", &csp2), Some(FilterRuleAction::Deny));
+
+        let nel = |r#type: &str, family: &str| json!({"report": {"body": {"type": r#type}}, "derived": {"client": {"family": family}}});
+        assert_eq!(rules.evaluate("NEL", &nel("dns.name_not_resolved", "Chrome")), Some(FilterRuleAction::Deny));
+        assert_eq!(rules.evaluate("NEL", &nel("dns.name_not_resolved", "Firefox")), None);
+        assert_eq!(rules.evaluate("CSP", &nel("dns.name_not_resolved", "Chrome")), None);
+
+        let hits = rules.statistics().into_iter().map(|s| (s.name, s.hits)).collect::<Vec<_>>();
+        assert_eq!(hits[0], ("own-extension".to_string(), 1));
+        assert_eq!(hits[1], ("dns-errors".to_string(), 1));
+        assert_eq!(hits[2], ("default:browser-extension-blocked-url".to_string(), 1));
+        assert_eq!(hits[4], ("default:injector-blocked-url".to_string(), 2));
+    }
+
+    #[test]
+    fn invalid_conditions() {
+        let rule = |condition| FilterRuleConfig {
+            name: "invalid".to_string(),
+            action: FilterRuleAction::Deny,
+            report_types: vec![],
+            conditions: vec![condition]
+        };
+        assert!(matches!(RuleSet::new(&[rule(condition("a", Some("x"), Some("y"), None))], false), Err(Error::InvalidCondition(_, _))));
+        assert!(matches!(RuleSet::new(&[rule(condition("a", None, Some("("), None))], false), Err(Error::InvalidPattern(_, _))));
+        assert!(RuleSet::new(&[], false).unwrap().is_empty());
+    }
+}
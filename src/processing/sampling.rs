@@ -0,0 +1,160 @@
+/**
+ * network-journal - collect network reports and print them to file
+ * Copyright (C) 2025 nerou GmbH
+ * 
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{fmt::Display, sync::Arc};
+
+use serde::Serialize;
+
+use crate::{config::SamplingConfig, reports::nel::NetworkError};
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidRate(String, f64)
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidRate(name, rate) => write!(f, "invalid sampling rate {} of {}, has to be between 0 and 1", rate, name)
+        }
+    }
+}
+
+/**
+ * Sampling applied to a logged report, dashboards scale counts back up by the weight
+ */
+#[derive(Serialize, PartialEq, Clone, Copy, Debug)]
+pub struct Sampling {
+    /// rate of the configured sampling
+    pub rate: f64,
+    /// reciprocal of the effective rate including the sampling of the browser (NEL sampling_fraction)
+    pub weight: f64
+}
+
+impl Sampling {
+    /**
+     * None if neither network-journal nor the browser sampled.
+     * The sampling_fraction is sent by the client, values outside of (0, 1] are ignored.
+     */
+    pub fn new(rate: f64, sampling_fraction: f64) -> Option<Self> {
+        let sampling_fraction = if sampling_fraction > 0.0 && sampling_fraction <= 1.0 { sampling_fraction } else { 1.0 };
+        if rate >= 1.0 && sampling_fraction >= 1.0 {
+            return None;
+        }
+        Some(Self {
+            rate,
+            weight: 1.0 / (rate * sampling_fraction)
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct Sampler {
+    config: Arc<SamplingConfig>
+}
+
+impl Sampler {
+    pub fn new(config: SamplingConfig) -> Result<Self, Error> {
+        let rates = config.report_types.iter().chain(config.hosts.iter())
+            .map(|(name, rate)| (name.as_str(), *rate))
+            .chain(config.nel_success.map(|rate| ("nel_success", rate)));
+        for (name, rate) in rates {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(Error::InvalidRate(name.to_string(), rate));
+            }
+        }
+        Ok(Self {
+            config: Arc::new(config)
+        })
+    }
+
+    /**
+     * Configured rate of a report, i.e. the rate of its log type multiplied with the rate of its host
+     */
+    pub fn rate(&self, report_type: &str, nel: Option<&NetworkError>, host: Option<&str>) -> f64 {
+        let type_rate = match nel {
+            Some(nel) if nel.get_type() == "ok" && self.config.nel_success.is_some() => self.config.nel_success,
+            _ => self.config.report_types.get(report_type).copied()
+        };
+        type_rate.unwrap_or(1.0) * host.and_then(|h| self.host_rate(h)).unwrap_or(1.0)
+    }
+
+    /**
+     * Randomly decides whether to log a report sampled at the rate
+     */
+    pub fn is_sampled(&self, rate: f64) -> bool {
+        rate >= 1.0 || rand::random::<f64>() < rate
+    }
+
+    /**
+     * Rate of the host or its closest parent domain
+     */
+    fn host_rate(&self, host: &str) -> Option<f64> {
+        if self.config.hosts.is_empty() {
+            return None;
+        }
+        let host = host.trim_end_matches('.').to_lowercase();
+        let mut domain = host.as_str();
+        loop {
+            if let Some(rate) = self.config.hosts.get(domain) {
+                return Some(*rate);
+            }
+            domain = domain.split_once('.')?.1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::reports::nel::Phase;
+
+    use super::*;
+
+    #[test]
+    fn sampling_rates() {
+        let sampler = Sampler::new(SamplingConfig {
+            report_types: HashMap::from([("NEL".to_string(), 0.5), ("Decprecation".to_string(), 0.1)]),
+            nel_success: Some(0.01),
+            hosts: HashMap::from([("example.com".to_string(), 0.5), ("shop.example.com".to_string(), 1.0)])
+        }).unwrap();
+        let nel = |r#type: &str| NetworkError::synthetic(Phase::Application, r#type, "h2", "192.0.2.1".to_string(), 200, 10);
+        assert_eq!(sampler.rate("NEL", Some(&nel("http.error")), None), 0.5);
+        assert_eq!(sampler.rate("NEL", Some(&nel("ok")), None), 0.01);
+        assert_eq!(sampler.rate("Decprecation", None, Some("www.Example.com.")), 0.05);
+        assert_eq!(sampler.rate("Decprecation", None, Some("cdn.shop.example.com")), 0.1);
+        assert_eq!(sampler.rate("CSP", None, Some("example.org")), 1.0);
+        assert!(sampler.is_sampled(1.0));
+        assert!(!sampler.is_sampled(0.0));
+
+        assert_eq!(Sampling::new(1.0, 1.0), None);
+        assert_eq!(Sampling::new(0.5, 0.25), Some(Sampling { rate: 0.5, weight: 8.0 }));
+    }
+
+    #[test]
+    fn invalid_sampling_fraction() {
+        assert_eq!(Sampling::new(1.0, 0.0), None);
+        assert_eq!(Sampling::new(1.0, -0.5), None);
+        assert_eq!(Sampling::new(1.0, 2.0), None);
+        assert_eq!(Sampling::new(1.0, f64::NAN), None);
+        assert_eq!(Sampling::new(0.5, 0.0), Some(Sampling { rate: 0.5, weight: 2.0 }));
+        assert_eq!(Sampling::new(0.5, f64::INFINITY), Some(Sampling { rate: 0.5, weight: 2.0 }));
+        assert!(Sampler::new(SamplingConfig { nel_success: Some(2.0), ..SamplingConfig::default() }).is_err());
+    }
+}
@@ -0,0 +1,308 @@
+/**
+ * network-journal - collect network reports and print them to file
+ * Copyright (C) 2025 nerou GmbH
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{collections::HashMap, fmt::Display, io, net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr}, sync::{Arc, Mutex}, time::Duration};
+
+use actix_web::rt::spawn;
+use ipnet::IpNet;
+use log::{debug, warn};
+use tokio::{io::{copy_bidirectional, AsyncRead, AsyncReadExt}, net::{TcpListener, TcpStream}, time::timeout};
+
+use crate::config::ProxyProtocolConfig;
+
+/// first 12 bytes of a v2 header
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+/// including CRLF
+const V1_MAX_LENGTH: usize = 107;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    InvalidHeader(String),
+    Timeout
+}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "PROXY protocol connection failed: {}", e),
+            Self::InvalidHeader(reason) => write!(f, "invalid PROXY protocol header: {}", reason),
+            Self::Timeout => write!(f, "no PROXY protocol header received in time")
+        }
+    }
+}
+
+/**
+ * Original client addresses of the connections relayed to the HTTP server, by the local address of the relayed connection
+ */
+#[derive(Clone, Default, Debug)]
+pub struct ProxiedPeers {
+    peers: Arc<Mutex<HashMap<SocketAddr, SocketAddr>>>
+}
+
+impl ProxiedPeers {
+    /**
+     * None if the peer is no connection relayed by the PROXY protocol listener
+     */
+    pub fn get(&self, peer: SocketAddr) -> Option<SocketAddr> {
+        self.peers.lock().unwrap().get(&peer).copied()
+    }
+
+    /**
+     * Whether a connection to the backend listener has not been relayed, i.e. it has been made by another local process
+     * bypassing the `allowed_sources` (and possibly forging the forwarding header of trusted proxies)
+     */
+    pub fn is_bypassing(&self, backend: Option<SocketAddr>, local: SocketAddr, peer: Option<SocketAddr>) -> bool {
+        backend == Some(local) && peer.and_then(|peer| self.get(peer)).is_none()
+    }
+}
+
+/**
+ * Listener expecting a PROXY protocol (v1 or v2) header in front of every connection.
+ * The remaining connection is relayed to the HTTP server (including TLS) listening on the backend address.
+ */
+pub struct ProxyListener {
+    listener: TcpListener,
+    backend: SocketAddr,
+    allowed_sources: Arc<Vec<IpNet>>,
+    header_timeout: Duration,
+    peers: ProxiedPeers
+}
+
+impl ProxyListener {
+    pub async fn bind(config: &ProxyProtocolConfig, backend: SocketAddr, peers: ProxiedPeers) -> io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind((config.listen.as_str(), config.port)).await?,
+            backend,
+            allowed_sources: Arc::new(config.allowed_sources.clone()),
+            header_timeout: Duration::from_secs(config.header_timeout),
+            peers
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub async fn run(self) {
+        loop {
+            let (stream, source) = match self.listener.accept().await {
+                Ok(connection) => connection,
+                Err(err) => {
+                    warn!("failed to accept PROXY protocol connection: {}", err);
+                    continue;
+                }
+            };
+            if !self.allowed_sources.iter().any(|network| network.contains(&source.ip().to_canonical())) {
+                warn!("PROXY protocol connection of {} refused, source not allowed", source);
+                continue;
+            }
+            let backend = self.backend;
+            let header_timeout = self.header_timeout;
+            let peers = self.peers.clone();
+            spawn(async move {
+                if let Err(err) = relay(stream, source, backend, header_timeout, &peers).await {
+                    debug!("{} (source {})", err, source);
+                }
+            });
+        }
+    }
+}
+
+async fn relay(mut stream: TcpStream, source: SocketAddr, backend: SocketAddr, header_timeout: Duration, peers: &ProxiedPeers) -> Result<(), Error> {
+    let client = timeout(header_timeout, read_header(&mut stream)).await.map_err(|_| Error::Timeout)??;
+    let mut upstream = TcpStream::connect(backend).await?;
+    let relayed = upstream.local_addr()?;
+    // LOCAL connections (e.g. health checks) are made by the proxy itself
+    peers.peers.lock().unwrap().insert(relayed, client.unwrap_or(source));
+    let res = copy_bidirectional(&mut stream, &mut upstream).await;
+    peers.peers.lock().unwrap().remove(&relayed);
+    res?;
+    Ok(())
+}
+
+/**
+ * Reads exactly the PROXY header off the stream.
+ * Returns the source address of the client, None for LOCAL or UNKNOWN connections.
+ */
+pub async fn read_header<R: AsyncRead + Unpin>(stream: &mut R) -> Result<Option<SocketAddr>, Error> {
+    // the shortest v1 header ("PROXY UNKNOWN\r\n") is longer than the signature, so no payload is consumed
+    let mut start = [0u8; 12];
+    stream.read_exact(&mut start).await?;
+    if &start == V2_SIGNATURE {
+        let mut header = [0u8; 4];
+        stream.read_exact(&mut header).await?;
+        let mut addresses = vec![0u8; u16::from_be_bytes([header[2], header[3]]) as usize];
+        stream.read_exact(&mut addresses).await?;
+        parse_v2(header[0], header[1], &addresses)
+    } else if start.starts_with(b"PROXY ") {
+        let mut line = start.to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= V1_MAX_LENGTH {
+                return Err(Error::InvalidHeader("v1 header too long".to_string()));
+            }
+            line.push(stream.read_u8().await?);
+        }
+        parse_v1(&line)
+    } else {
+        Err(Error::InvalidHeader("missing signature".to_string()))
+    }
+}
+
+/**
+ * e.g. PROXY TCP4 192.0.2.1 198.51.100.1 56324 443
+ */
+fn parse_v1(line: &[u8]) -> Result<Option<SocketAddr>, Error> {
+    let line = std::str::from_utf8(line).map_err(|_| Error::InvalidHeader("v1 header is not ASCII".to_string()))?;
+    let fields: Vec<&str> = line.trim_end_matches("\r\n").split(' ').collect();
+    match fields.get(1) {
+        Some(&"UNKNOWN") => Ok(None),
+        Some(protocol @ (&"TCP4" | &"TCP6")) if fields.len() == 6 => {
+            let ip: IpAddr = fields[2].parse().map_err(|_| Error::InvalidHeader(format!("invalid source address {}", fields[2])))?;
+            let port: u16 = fields[4].parse().map_err(|_| Error::InvalidHeader(format!("invalid source port {}", fields[4])))?;
+            if ip.is_ipv4() != (*protocol == "TCP4") {
+                return Err(Error::InvalidHeader(format!("{} address of {}", ip, protocol)));
+            }
+            Ok(Some(SocketAddr::new(ip, port)))
+        },
+        _ => Err(Error::InvalidHeader(format!("unsupported v1 header {}", line.trim_end())))
+    }
+}
+
+/**
+ * Version and command, address family and protocol as well as the address block (followed by TLVs, which are ignored)
+ */
+fn parse_v2(version_command: u8, family_protocol: u8, addresses: &[u8]) -> Result<Option<SocketAddr>, Error> {
+    if version_command >> 4 != 2 {
+        return Err(Error::InvalidHeader(format!("unsupported version {}", version_command >> 4)));
+    }
+    match version_command & 0x0f {
+        0 => return Ok(None),
+        1 => {},
+        command => return Err(Error::InvalidHeader(format!("unsupported command {}", command)))
+    }
+    let port = |offset: usize| u16::from_be_bytes([addresses[offset], addresses[offset + 1]]);
+    match family_protocol >> 4 {
+        // AF_INET: source and destination address, source and destination port
+        1 if addresses.len() >= 12 => {
+            let ip: [u8; 4] = addresses[0..4].try_into().unwrap();
+            Ok(Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::from(ip)), port(8))))
+        },
+        2 if addresses.len() >= 36 => {
+            let ip: [u8; 16] = addresses[0..16].try_into().unwrap();
+            Ok(Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(ip)), port(32))))
+        },
+        // AF_UNSPEC and AF_UNIX
+        0 | 3 => Ok(None),
+        family => Err(Error::InvalidHeader(format!("unsupported address family {} or address block too short", family)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+
+    async fn read(header: &[u8]) -> (Result<Option<SocketAddr>, Error>, Vec<u8>) {
+        let stream = [header, b"GET / HTTP/1.1\r\n"].concat();
+        let mut reader = &stream[..];
+        let res = read_header(&mut reader).await;
+        (res, reader.to_vec())
+    }
+
+    #[actix_web::test]
+    async fn parse_headers() {
+        let (res, rest) = read(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n").await;
+        assert_eq!(res.unwrap(), Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n");
+        let (res, rest) = read(b"PROXY UNKNOWN\r\n").await;
+        assert_eq!(res.unwrap(), None);
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n");
+        assert_eq!(read(b"PROXY TCP6 2001:db8::1 2001:db8::2 4711 443\r\n").await.0.unwrap(), Some("[2001:db8::1]:4711".parse().unwrap()));
+        assert!(read(b"PROXY TCP6 192.0.2.1 198.51.100.1 56324 443\r\n").await.0.is_err());
+        assert!(read(b"GET / HTTP/1.1\r\n").await.0.is_err());
+
+        // PROXY command, TCP over IPv4, 12 bytes of addresses followed by a TLV
+        let v2 = [&V2_SIGNATURE[..], &[0x21, 0x11, 0, 15, 192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0x01, 0xbb, 0x04, 0, 0]].concat();
+        let (res, rest) = read(&v2).await;
+        assert_eq!(res.unwrap(), Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n");
+        let mut v6 = [&V2_SIGNATURE[..], &[0x21, 0x21, 0, 36]].concat();
+        v6.extend("2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        v6.extend([0; 16]);
+        v6.extend([0x12, 0x67, 0x01, 0xbb]);
+        assert_eq!(read(&v6).await.0.unwrap(), Some("[2001:db8::1]:4711".parse().unwrap()));
+        // LOCAL command
+        assert_eq!(read(&[&V2_SIGNATURE[..], &[0x20, 0x00, 0, 0]].concat()).await.0.unwrap(), None);
+        assert!(read(&[&V2_SIGNATURE[..], &[0x11, 0x11, 0, 0]].concat()).await.0.is_err());
+        assert!(read(&[&V2_SIGNATURE[..], &[0x21, 0x11, 0, 4, 192, 0, 2, 1]].concat()).await.0.is_err());
+    }
+
+    #[actix_web::test]
+    async fn relay_connections() {
+        let backend = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = ProxyProtocolConfig { port: 0, ..ProxyProtocolConfig::default() };
+        let peers = ProxiedPeers::default();
+        let listener = ProxyListener::bind(&config, backend.local_addr().unwrap(), peers.clone()).await.unwrap();
+        let address = listener.local_addr().unwrap();
+        spawn(listener.run());
+
+        let mut client = TcpStream::connect(address).await.unwrap();
+        client.write_all(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nping").await.unwrap();
+        let (mut relayed, relayed_addr) = backend.accept().await.unwrap();
+        let mut buf = [0u8; 4];
+        relayed.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        assert_eq!(peers.get(relayed_addr), Some("192.0.2.1:56324".parse().unwrap()));
+        let backend_addr = backend.local_addr().ok();
+        assert!(!peers.is_bypassing(backend_addr, backend.local_addr().unwrap(), Some(relayed_addr)));
+        assert!(peers.is_bypassing(backend_addr, backend.local_addr().unwrap(), Some("127.0.0.1:4711".parse().unwrap())));
+        assert!(peers.is_bypassing(backend_addr, backend.local_addr().unwrap(), None));
+        // connections to the regular listeners are not affected
+        assert!(!peers.is_bypassing(backend_addr, "127.0.0.1:8080".parse().unwrap(), Some("127.0.0.1:4711".parse().unwrap())));
+        assert!(!peers.is_bypassing(None, backend.local_addr().unwrap(), None));
+
+        relayed.write_all(b"pong").await.unwrap();
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"pong");
+
+        drop(client);
+        drop(relayed);
+        for _ in 0..100 {
+            if peers.get(relayed_addr).is_none() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(peers.get(relayed_addr), None);
+
+        // sources not allowed are disconnected without relaying
+        let refused = ProxyListener::bind(&ProxyProtocolConfig { port: 0, allowed_sources: vec![], ..config }, backend.local_addr().unwrap(), peers).await.unwrap();
+        let address = refused.local_addr().unwrap();
+        spawn(refused.run());
+        let mut client = TcpStream::connect(address).await.unwrap();
+        assert_eq!(client.read(&mut buf).await.unwrap(), 0);
+    }
+}
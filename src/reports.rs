@@ -16,29 +16,36 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::fmt::Display;
+use std::{fmt::Display, net::IpAddr};
 
+use actix_web::{http::header, HttpResponse, HttpResponseBuilder};
+use itertools::Itertools;
 use log::info;
 use serde::Serialize;
 
 use crate::{
-    processing::{derivation::{analyze_url, analyze_user_agent, Client, Device, Url}, filter::Filter}, 
-    reports::{csp::CSPReport, dmarc::DMARCReport, smtp_tls::SMTPTLSReport, tls_cert_validity::TLSCertificateValidityReport}
+    processing::{derivation::{analyze_url, Client, Device, Url}, filter::BotReason, geoip::IpInfo, reverse_dns::ReverseDns, sampling::Sampling, Processor, RequestInfo}, 
+    reports::{caa::CAAComplianceReport, csp::CSPReport, dmarc::DMARCReport, header_audit::HeaderAuditReport, smtp_tls::SMTPTLSReport, synthetic_probe::SyntheticProbeReport, tls_cert_change::TLSCertificateChangeReport, tls_cert_validity::TLSCertificateValidityReport, tls_configuration::TLSConfigurationReport}
 };
 
+pub mod caa;
 pub mod coep;
 pub mod coop;
 pub mod crash;
 pub mod csp;
 pub mod deprecation;
 pub mod dmarc;
+pub mod header_audit;
 pub mod integrity;
 pub mod intervention;
 pub mod nel;
 pub mod permissions;
 pub mod reporting_api;
 pub mod smtp_tls;
+pub mod synthetic_probe;
+pub mod tls_cert_change;
 pub mod tls_cert_validity;
+pub mod tls_configuration;
 
 #[derive(Serialize, Debug)]
 #[serde(untagged)]
@@ -47,7 +54,12 @@ pub enum ReportType<'a> {
     CSPLvl2(&'a CSPReport),
     SMTPTLSRPT(&'a SMTPTLSReport),
     DMARC(&'a DMARCReport),
-    TLSCertificateValidity(&'a TLSCertificateValidityReport)
+    TLSCertificateValidity(&'a TLSCertificateValidityReport),
+    TLSCertificateChange(&'a TLSCertificateChangeReport),
+    TLSConfiguration(&'a TLSConfigurationReport),
+    CAACompliance(&'a CAAComplianceReport),
+    HeaderAudit(&'a HeaderAuditReport),
+    SyntheticProbe(&'a SyntheticProbeReport)
 }
 
 #[derive(Serialize, Default, Debug)]
@@ -55,7 +67,16 @@ struct Derived {
     pub client: Client,
     pub os: Client,
     pub device: Device,
-    pub url: Url
+    pub url: Url,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bot: Option<BotReason>,
+    /// address of the client that submitted the report
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_ip: Option<IpAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sampling: Option<Sampling>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ips: Vec<IpInfo>
 }
 
 #[derive(Serialize, Debug)]
@@ -67,25 +88,86 @@ struct DecoratedReport<'a> {
 #[derive(Debug)]
 pub enum Error {
     Parse(serde_json::Error),
-    Serialize(serde_json::Error)
+    Serialize(serde_json::Error),
+    /// seconds to retry after
+    RateLimited(u64)
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Error::Parse(err) => write!(f, "failed to parse report: {}", err),
-            Error::Serialize(err) => write!(f, "failed to serialize report: {}", err)
+            Error::Serialize(err) => write!(f, "failed to serialize report: {}", err),
+            Error::RateLimited(retry_after) => write!(f, "rate limit exceeded, retry after {} seconds", retry_after)
         }
     }
 }
 
-pub fn handle_report(report: &ReportType<'_>, user_agent: Option<&str>, filter: Option<&Filter>) -> Result<(), Error> {
+pub fn too_many_requests(retry_after: u64) -> HttpResponseBuilder {
+    HttpResponse::TooManyRequests()
+        .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+        .take()
+}
+
+/// fields of the IP addresses looked up in reverse DNS
+const REVERSE_DNS_FIELDS: [&str; 2] = ["source_ip", "sending-mta-ip"];
+
+/**
+ * IP addresses of a report with the name of their field
+ */
+fn ip_addresses<'a>(report: &ReportType<'a>) -> Vec<(&'static str, &'a str)> {
+    match report {
+        ReportType::ReportingAPI(rpt) => match &rpt.rpt {
+            reporting_api::ReportType::NetworkError(nel) => vec![("server_ip", nel.get_server_ip().as_str())],
+            _ => vec![]
+        },
+        ReportType::SMTPTLSRPT(rpt) => rpt.get_ip_addresses(),
+        ReportType::DMARC(rpt) => rpt.get_source_ips().into_iter().map(|ip| ("source_ip", ip)).collect(),
+        _ => vec![]
+    }
+}
+
+/**
+ * Adds the reverse DNS names of the sources of DMARC and SMTP TLS reports to the IP infos
+ */
+fn add_reverse_names(ips: &mut Vec<IpInfo>, report: &ReportType<'_>, reverse_dns: &ReverseDns) {
+    let addresses: Vec<(&str, IpAddr)> = ip_addresses(report).into_iter()
+        .filter(|(field, _)| REVERSE_DNS_FIELDS.contains(field))
+        .filter_map(|(field, ip)| ip.parse().ok().map(|ip| (field, ip)))
+        .unique()
+        .collect();
+    if addresses.is_empty() {
+        return;
+    }
+    let names = reverse_dns.lookup_all(&addresses.iter().map(|(_, ip)| *ip).collect::<Vec<_>>());
+    for (field, ip) in addresses {
+        let Some(name) = names.get(&ip) else {
+            continue;
+        };
+        let ip = ip.to_string();
+        let index = match ips.iter().position(|info| info.field == field && info.ip.as_ref() == Some(&ip)) {
+            Some(index) => index,
+            None => {
+                ips.push(IpInfo { field: field.to_string(), ip: Some(ip), ..IpInfo::default() });
+                ips.len() - 1
+            }
+        };
+        ips[index].hostname = name.hostname.clone();
+        ips[index].fcrdns = Some(name.forward_confirmed);
+    }
+}
+
+pub fn handle_report(report: &ReportType<'_>, request: Option<&RequestInfo>, processor: Option<&Processor>) -> Result<(), Error> {
+    let filter = processor.map(|p| &p.filter);
     let mut decorated = DecoratedReport {
         report,
-        derived: Derived::default()
+        derived: Derived {
+            client_ip: request.and_then(|r| r.client_ip),
+            ..Derived::default()
+        }
     };
-    if let Some(ua) = user_agent {
-        (decorated.derived.client, decorated.derived.os, decorated.derived.device) = analyze_user_agent(ua);
+    if let (Some(ua), Some(p)) = (request.and_then(|r| r.user_agent.as_ref()), processor) {
+        (decorated.derived.client, decorated.derived.os, decorated.derived.device) = p.user_agent_parser.analyze(ua);
     }
     
     let rpt_type_str: &str;
@@ -95,8 +177,11 @@ pub fn handle_report(report: &ReportType<'_>, user_agent: Option<&str>, filter:
                 if let Ok(parsed_url) = analyze_url(&rpt.url) {
                     decorated.derived.url = parsed_url;
                 }
-                if let Some(user_agent) = &rpt.user_agent {
-                    (decorated.derived.client, decorated.derived.os, decorated.derived.device) = analyze_user_agent(&user_agent);
+                if let (Some(ua), Some(p)) = (&rpt.user_agent, processor) {
+                    (decorated.derived.client, decorated.derived.os, decorated.derived.device) = p.user_agent_parser.analyze(ua);
+                }
+                if let Some(request) = request {
+                    request.client_hints.refine(&mut decorated.derived.client, &mut decorated.derived.os, &mut decorated.derived.device);
                 }
 
                 rpt_type_str = match rpt.rpt {
@@ -120,12 +205,18 @@ pub fn handle_report(report: &ReportType<'_>, user_agent: Option<&str>, filter:
                 if let Ok(parsed_url) = analyze_url(&rpt.csp_report.document_url) {
                     decorated.derived.url = parsed_url;
                 }
+                if let Some(request) = request {
+                    request.client_hints.refine(&mut decorated.derived.client, &mut decorated.derived.os, &mut decorated.derived.device);
+                }
                 rpt_type_str = "CSP";
             } else {
                 return Ok(());
             }
         },
         ReportType::SMTPTLSRPT(rpt) => {
+            if !filter.map_or(true, |f| f.is_reporter_allowed(rpt.get_organization_name(), rpt.get_contact_info(), None)) {
+                return Ok(());
+            }
             decorated.derived.url.host = rpt.get_policy_domains().get(0).map(|s| s.to_string());
             if let Some(host) = &decorated.derived.url.host {
                 if !filter.map_or(true, |f| f.is_domain_allowed(host.as_str())) {
@@ -135,6 +226,9 @@ pub fn handle_report(report: &ReportType<'_>, user_agent: Option<&str>, filter:
             rpt_type_str = "SMTP-TLS-RPT";
         },
         ReportType::DMARC(rpt) => {
+            if !filter.map_or(true, |f| f.is_reporter_allowed(rpt.get_sender_organisation(), rpt.get_sender_email(), Some(rpt.get_authentication_results()))) {
+                return Ok(());
+            }
             decorated.derived.url.host = Some(rpt.get_published_policys_domain().to_string());
             if let Some(host) = &decorated.derived.url.host {
                 if !filter.map_or(true, |f| f.is_domain_allowed(host.as_str())) {
@@ -145,8 +239,89 @@ pub fn handle_report(report: &ReportType<'_>, user_agent: Option<&str>, filter:
             rpt_type_str = "DMARC";
         },
         ReportType::TLSCertificateValidity(rpt) => {
-            decorated.derived.url.host = Some(rpt.certificate.subject.common_name.clone());
+            decorated.derived.url.host = Some(rpt.certificate.get_name().to_string());
             rpt_type_str = "TLS-Certificate-Validity";
+        },
+        ReportType::TLSCertificateChange(rpt) => {
+            decorated.derived.url.host = Some(rpt.domain.clone());
+            rpt_type_str = "TLS-Certificate-Change";
+        },
+        ReportType::TLSConfiguration(rpt) => {
+            decorated.derived.url.host = Some(rpt.domain.clone());
+            rpt_type_str = "TLS-Configuration";
+        },
+        ReportType::CAACompliance(rpt) => {
+            decorated.derived.url.host = Some(rpt.domain.clone());
+            rpt_type_str = "CAA-Compliance";
+        },
+        ReportType::HeaderAudit(rpt) => {
+            if let Ok(parsed_url) = analyze_url(&rpt.url) {
+                decorated.derived.url = parsed_url;
+            }
+            rpt_type_str = "Header-Audit";
+        },
+        ReportType::SyntheticProbe(rpt) => {
+            if let Ok(parsed_url) = analyze_url(&rpt.url) {
+                decorated.derived.url = parsed_url;
+            }
+            rpt_type_str = "Synthetic-Probe";
+        }
+    }
+    if let (Some(request), Some(p), Some(host)) = (request, processor, &decorated.derived.url.host) {
+        p.rate_limiter.check_host(&request.endpoint, host).map_err(Error::RateLimited)?;
+    }
+    if let Some(p) = processor {
+        decorated.derived.url.split_host(&p.public_suffix_list);
+    }
+    if let Some(p) = processor.filter(|p| p.geoip.is_enabled()) {
+        let client = request.and_then(|r| r.client_ip)
+            .and_then(|ip| p.geoip.lookup("client_ip", ip))
+            .map(|info| IpInfo { ip: None, ..info });
+        decorated.derived.ips = ip_addresses(report).into_iter()
+            .unique()
+            .filter_map(|(field, ip)| ip.parse::<IpAddr>().ok().and_then(|ip| p.geoip.lookup(field, ip)))
+            .chain(client)
+            .collect();
+    }
+    if let (Some(request), Some(p)) = (request, processor) {
+        let user_agent = match report {
+            ReportType::ReportingAPI(rpt) => rpt.user_agent.as_deref(),
+            _ => None
+        }.or(request.user_agent.as_deref());
+        decorated.derived.bot = p.filter.classify_bot(user_agent, &decorated.derived.device, request.client_ip);
+        if let Some(reason) = decorated.derived.bot {
+            if !p.filter.is_bot_allowed(reason) {
+                return Ok(());
+            }
+        }
+    }
+    if let Some(p) = processor {
+        if p.filter.has_rules() {
+            let value = serde_json::to_value(&decorated).map_err(Error::Serialize)?;
+            if !p.filter.is_report_allowed(rpt_type_str, &value) {
+                return Ok(());
+            }
+        }
+        let nel = match report {
+            ReportType::ReportingAPI(rpt) => match &rpt.rpt {
+                reporting_api::ReportType::NetworkError(nel) => Some(nel),
+                _ => None
+            },
+            _ => None
+        };
+        let rate = p.sampler.rate(rpt_type_str, nel, decorated.derived.url.host.as_deref());
+        if !p.sampler.is_sampled(rate) {
+            return Ok(());
+        }
+        decorated.derived.sampling = Sampling::new(rate, nel.map_or(1.0, |nel| nel.get_sampling_fraction()));
+        if p.reverse_dns.is_enabled() {
+            add_reverse_names(&mut decorated.derived.ips, report, &p.reverse_dns);
+        }
+        if p.aggregator.is_aggregated(rpt_type_str) {
+            let value = serde_json::to_value(&decorated).map_err(Error::Serialize)?;
+            if p.aggregator.add(rpt_type_str, value) {
+                return Ok(());
+            }
         }
     }
     match serde_json::to_string_pretty(&decorated) {
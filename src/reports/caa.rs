@@ -0,0 +1,432 @@
+/**
+ * network-journal - collect network reports and print them to file
+ * Copyright (C) 2025 nerou GmbH
+ * 
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::collections::{BTreeSet, HashMap};
+
+use hickory_proto::rr::{rdata::{caa::{Property, Value}, CAA}, RData, RecordType};
+use log::{error, trace};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{dns::{self, Resolver}, reports::tls_cert_validity::CertificateInfo};
+
+/// CAA issuer domain names of well-known CAs and the organization names used in the issuer of their certificates
+const DEFAULT_CAA_ISSUERS: &[(&str, &[&str])] = &[
+    ("letsencrypt.org", &["Let's Encrypt"]),
+    ("pki.goog", &["Google Trust Services", "Google Trust Services LLC"]),
+    ("digicert.com", &["DigiCert Inc", "DigiCert, Inc."]),
+    ("sectigo.com", &["Sectigo Limited", "COMODO CA Limited"]),
+    ("comodoca.com", &["Sectigo Limited", "COMODO CA Limited"]),
+    ("globalsign.com", &["GlobalSign nv-sa"]),
+    ("amazon.com", &["Amazon"]),
+    ("amazontrust.com", &["Amazon"]),
+    ("awstrust.com", &["Amazon"]),
+    ("amazonaws.com", &["Amazon"]),
+    ("zerossl.com", &["ZeroSSL"]),
+    ("buypass.com", &["Buypass AS-983163327"]),
+    ("ssl.com", &["SSL Corporation", "SSL Corp"]),
+    ("entrust.net", &["Entrust, Inc.", "Entrust Limited"]),
+    ("harica.gr", &["Hellenic Academic and Research Institutions CA"]),
+    ("actalis.it", &["Actalis S.p.A."])
+];
+
+const CT_SEARCH_URL: &str = "https://crt.sh/";
+
+#[derive(Serialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum CertificateSource {
+    /// certificate presented by the checked endpoint
+    Endpoint,
+    /// unexpired certificate found in certificate transparency logs
+    CertificateTransparency
+}
+
+#[derive(Serialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Compliance {
+    Allowed,
+    Violation,
+    /// the issuer could not be mapped to a CAA issuer domain name, see `caa_issuers` config
+    UnknownIssuer
+}
+
+#[derive(Serialize, Debug)]
+pub struct IssuerCompliance {
+    pub source: CertificateSource,
+    pub issuer: String,
+    /// CAA issuer domain names the issuer is known by
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub issuer_domains: Vec<String>,
+    pub wildcard: bool,
+    pub compliance: Compliance
+}
+
+/**
+ * Issuer of a certificate and whether it covers wildcard and non-wildcard names
+ */
+type CertificateIssuer = (CertificateSource, String, bool, bool);
+
+#[derive(Serialize, Debug)]
+pub struct CAAComplianceReport {
+    pub domain: String,
+    /// domain name the relevant CAA records have been found at (the domain itself or a parent), absent if there are none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub caa_domain: Option<String>,
+    pub dnssec_validated: bool,
+    /// issuer domain names of `issue` properties, an empty list allows no CA at all
+    pub allowed_issuers: Vec<String>,
+    /// issuer domain names of `issuewild` properties, if present
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_wildcard_issuers: Option<Vec<String>>,
+    /// unknown properties flagged as critical, which forbid any issuance (RFC 8659, section 4.5)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unknown_critical_properties: Vec<String>,
+    pub certificates: Vec<IssuerCompliance>,
+    pub violation: bool
+}
+
+/// entry of the crt.sh JSON API
+#[derive(Deserialize, Debug)]
+struct CTLogEntry {
+    issuer_name: String,
+    name_value: String
+}
+
+/**
+ * Relevant CAA record set as defined in RFC 8659, section 3
+ */
+struct CAARecordSet {
+    domain: String,
+    records: Vec<CAA>,
+    authenticated: bool
+}
+
+impl CAARecordSet {
+
+    /**
+     * Climbs up the domain tree until a non-empty CAA record set is found
+     */
+    fn lookup(resolver: &Resolver, domain: &str) -> Result<Option<Self>, dns::Error> {
+        let mut name = domain.trim_end_matches('.').to_lowercase();
+        loop {
+            let answer = resolver.query(&format!("{}.", name), RecordType::CAA)?;
+            let records = answer.records.into_iter()
+                .filter_map(|rdata| match rdata {
+                    RData::CAA(caa) => Some(caa),
+                    _ => None
+                })
+                .collect::<Vec<_>>();
+            if !records.is_empty() {
+                return Ok(Some(Self { domain: name, records, authenticated: answer.authenticated }));
+            }
+            match name.split_once('.') {
+                Some((_, parent)) if !parent.is_empty() => name = parent.to_string(),
+                _ => return Ok(None)
+            }
+        }
+    }
+
+    fn issuers(&self, property: &Property) -> Option<Vec<String>> {
+        let values = self.records.iter()
+            .filter(|caa| caa.tag() == property)
+            .collect::<Vec<_>>();
+        if values.is_empty() {
+            return None;
+        }
+        Some(values.into_iter()
+            .filter_map(|caa| match caa.value() {
+                // an empty issuer (e.g. `0 issue ";"`) allows no CA
+                Value::Issuer(Some(name), _) => Some(name.to_ascii().trim_end_matches('.').to_lowercase()),
+                _ => None
+            })
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect())
+    }
+
+    fn unknown_critical_properties(&self) -> Vec<String> {
+        self.records.iter()
+            .filter(|caa| caa.issuer_critical() && caa.tag().is_unknown())
+            .map(|caa| caa.tag().as_str().to_lowercase())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+}
+
+/**
+ * Whether the names contain wildcard and non-wildcard ones, certificates without names are considered non-wildcard ones
+ */
+fn name_kinds<'a>(names: impl IntoIterator<Item = &'a str>) -> (bool, bool) {
+    let (mut wildcard, mut non_wildcard) = (false, false);
+    for name in names {
+        if name.starts_with("*.") {
+            wildcard = true;
+        } else {
+            non_wildcard = true;
+        }
+    }
+    (wildcard, non_wildcard || !wildcard)
+}
+
+impl CAAComplianceReport {
+
+    pub fn create(domain: &str, resolver: &Resolver, certificates: &[CertificateInfo], custom_issuers: &HashMap<String, Vec<String>>, ct_search: bool) -> Result<Self, dns::Error> {
+        let record_set = CAARecordSet::lookup(resolver, domain)?;
+        let issuer_map = Self::build_issuer_map(custom_issuers);
+
+        let mut issuers = certificates.iter()
+            .map(|cert| {
+                let (wildcard, non_wildcard) = name_kinds(cert.subject_alt_names.iter().map(String::as_str));
+                (
+                    CertificateSource::Endpoint,
+                    cert.issuer.organization_name.clone().or(cert.issuer.common_name.clone()).unwrap_or_default(),
+                    wildcard,
+                    non_wildcard
+                )
+            })
+            .collect::<Vec<_>>();
+        if ct_search {
+            match Self::search_ct_logs(domain) {
+                Ok(entries) => issuers.extend(entries.into_iter()
+                    .map(|(issuer, wildcard, non_wildcard)| (CertificateSource::CertificateTransparency, issuer, wildcard, non_wildcard))),
+                Err(err) => error!("failed to search CT logs for {}: {}", domain, err)
+            }
+        }
+
+        Ok(Self::evaluate(domain, record_set.as_ref(), &issuer_map, issuers))
+    }
+
+    fn evaluate(domain: &str, record_set: Option<&CAARecordSet>, issuer_map: &HashMap<String, Vec<String>>, issuers: Vec<CertificateIssuer>) -> Self {
+        let allowed_issuers = record_set.and_then(|rs| rs.issuers(&Property::Issue));
+        let allowed_wildcard_issuers = record_set.and_then(|rs| rs.issuers(&Property::IssueWild));
+        let unknown_critical_properties = record_set.map(|rs| rs.unknown_critical_properties()).unwrap_or_default();
+
+        let certificates = issuers.into_iter().map(|(source, issuer, wildcard, non_wildcard)| {
+            let issuer_domains = issuer_map.iter()
+                .filter(|(_, names)| names.iter().any(|name| name.eq_ignore_ascii_case(&issuer)))
+                .map(|(issuer_domain, _)| issuer_domain.clone())
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect::<Vec<_>>();
+            let check = |allowed: Option<&Vec<String>>| match allowed {
+                // no restrictions for this kind of name
+                None => Compliance::Allowed,
+                Some(_) if issuer_domains.is_empty() => Compliance::UnknownIssuer,
+                Some(allowed) if issuer_domains.iter().any(|d| allowed.contains(d)) => Compliance::Allowed,
+                Some(_) => Compliance::Violation
+            };
+            // issuewild takes precedence for wildcard names (RFC 8659, section 4.3), other names of the certificate have to satisfy issue
+            let mut results = vec![];
+            if wildcard {
+                results.push(check(allowed_wildcard_issuers.as_ref().or(allowed_issuers.as_ref())));
+            }
+            if non_wildcard {
+                results.push(check(allowed_issuers.as_ref()));
+            }
+            let compliance = if !unknown_critical_properties.is_empty() || results.contains(&Compliance::Violation) {
+                Compliance::Violation
+            } else if results.contains(&Compliance::UnknownIssuer) {
+                Compliance::UnknownIssuer
+            } else {
+                Compliance::Allowed
+            };
+            IssuerCompliance { source, issuer, issuer_domains, wildcard, compliance }
+        }).collect::<Vec<_>>();
+
+        Self {
+            domain: domain.to_string(),
+            caa_domain: record_set.map(|rs| rs.domain.clone()),
+            dnssec_validated: record_set.is_some_and(|rs| rs.authenticated),
+            allowed_issuers: allowed_issuers.unwrap_or_default(),
+            allowed_wildcard_issuers,
+            violation: !unknown_critical_properties.is_empty() || certificates.iter().any(|c| c.compliance == Compliance::Violation),
+            unknown_critical_properties,
+            certificates
+        }
+    }
+
+    fn build_issuer_map(custom_issuers: &HashMap<String, Vec<String>>) -> HashMap<String, Vec<String>> {
+        let mut issuer_map = DEFAULT_CAA_ISSUERS.iter()
+            .map(|(issuer_domain, names)| (issuer_domain.to_string(), names.iter().map(|n| n.to_string()).collect::<Vec<_>>()))
+            .collect::<HashMap<_, _>>();
+        for (issuer_domain, names) in custom_issuers {
+            issuer_map.entry(issuer_domain.to_lowercase()).or_default().extend(names.iter().cloned());
+        }
+        issuer_map
+    }
+
+    /**
+     * Returns the issuer organizations of unexpired certificates logged for the domain and whether they cover wildcard and non-wildcard names
+     */
+    fn search_ct_logs(domain: &str) -> Result<BTreeSet<(String, bool, bool)>, reqwest::Error> {
+        let url = Url::parse_with_params(CT_SEARCH_URL, &[("q", domain), ("output", "json"), ("exclude", "expired")])
+            .expect("CT search URL is valid");
+        let response = reqwest::blocking::get(url)?
+            .error_for_status()?;
+        let body = response.text()?;
+        let entries = match serde_json::from_str::<Vec<CTLogEntry>>(&body) {
+            Ok(entries) => entries,
+            Err(err) => {
+                trace!("unexpected CT search response: {}", body);
+                error!("failed to parse CT search response: {}", err);
+                return Ok(BTreeSet::new());
+            }
+        };
+        Ok(entries.into_iter()
+            .map(|entry| {
+                let issuer = parse_distinguished_name(&entry.issuer_name);
+                let (wildcard, non_wildcard) = name_kinds(entry.name_value.lines());
+                (
+                    issuer.get("O").or(issuer.get("CN")).cloned().unwrap_or(entry.issuer_name.clone()),
+                    wildcard,
+                    non_wildcard
+                )
+            })
+            .collect())
+    }
+}
+
+/**
+ * Parses a distinguished name like `C=US, O="Example, Inc.", CN=Example CA` into its attributes
+ */
+fn parse_distinguished_name(dn: &str) -> HashMap<String, String> {
+    let mut attributes = HashMap::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in dn.chars().chain([',']) {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                if let Some((key, value)) = current.split_once('=') {
+                    attributes.insert(key.trim().to_string(), value.trim().to_string());
+                }
+                current.clear();
+            },
+            _ => current.push(c)
+        }
+    }
+    attributes
+}
+
+#[cfg(test)]
+mod tests {
+    use hickory_proto::rr::Name;
+
+    use super::*;
+
+    fn record_set(records: Vec<CAA>) -> CAARecordSet {
+        CAARecordSet { domain: "example.com".to_string(), records, authenticated: true }
+    }
+
+    #[test]
+    fn parse_dn() {
+        let attributes = parse_distinguished_name("C=US, O=\"DigiCert, Inc.\", CN=DigiCert Global G2 TLS RSA SHA256 2020 CA1");
+        assert_eq!(attributes.get("C").unwrap(), "US");
+        assert_eq!(attributes.get("O").unwrap(), "DigiCert, Inc.");
+        assert_eq!(attributes.get("CN").unwrap(), "DigiCert Global G2 TLS RSA SHA256 2020 CA1");
+    }
+
+    #[test]
+    fn detect_violation() {
+        let rs = record_set(vec![CAA::new_issue(false, Some(Name::from_ascii("letsencrypt.org").unwrap()), vec![])]);
+        let issuer_map = CAAComplianceReport::build_issuer_map(&HashMap::new());
+        let rpt = CAAComplianceReport::evaluate("example.com", Some(&rs), &issuer_map, vec![
+            (CertificateSource::Endpoint, "Let's Encrypt".to_string(), false, true),
+            (CertificateSource::CertificateTransparency, "DigiCert Inc".to_string(), false, true),
+            (CertificateSource::CertificateTransparency, "Some Private CA".to_string(), false, true)
+        ]);
+        assert_eq!(rpt.allowed_issuers, vec!["letsencrypt.org"]);
+        assert_eq!(rpt.certificates[0].compliance, Compliance::Allowed);
+        assert_eq!(rpt.certificates[1].compliance, Compliance::Violation);
+        assert_eq!(rpt.certificates[2].compliance, Compliance::UnknownIssuer);
+        assert!(rpt.violation);
+    }
+
+    #[test]
+    fn issuewild_precedence() {
+        let rs = record_set(vec![
+            CAA::new_issue(false, Some(Name::from_ascii("letsencrypt.org").unwrap()), vec![]),
+            CAA::new_issuewild(false, None, vec![])
+        ]);
+        let mut custom_issuers = HashMap::new();
+        custom_issuers.insert("ca.example.net".to_string(), vec!["Example CA".to_string()]);
+        let issuer_map = CAAComplianceReport::build_issuer_map(&custom_issuers);
+        let rpt = CAAComplianceReport::evaluate("example.com", Some(&rs), &issuer_map, vec![
+            (CertificateSource::Endpoint, "Let's Encrypt".to_string(), true, false),
+            (CertificateSource::Endpoint, "Example CA".to_string(), false, true)
+        ]);
+        assert_eq!(rpt.allowed_wildcard_issuers, Some(vec![]));
+        assert_eq!(rpt.certificates[0].compliance, Compliance::Violation);
+        assert_eq!(rpt.certificates[1].issuer_domains, vec!["ca.example.net"]);
+        assert_eq!(rpt.certificates[1].compliance, Compliance::Violation);
+    }
+
+    #[test]
+    fn no_caa_records() {
+        let issuer_map = CAAComplianceReport::build_issuer_map(&HashMap::new());
+        let rpt = CAAComplianceReport::evaluate("example.com", None, &issuer_map, vec![
+            (CertificateSource::Endpoint, "Some Private CA".to_string(), false, true)
+        ]);
+        assert_eq!(rpt.certificates[0].compliance, Compliance::Allowed);
+        assert!(!rpt.violation);
+    }
+
+    #[test]
+    fn wildcard_and_domain_names() {
+        assert_eq!(name_kinds(["*.example.com", "example.com"]), (true, true));
+        assert_eq!(name_kinds(["*.example.com"]), (true, false));
+        assert_eq!(name_kinds([]), (false, true));
+
+        let rs = record_set(vec![
+            CAA::new_issue(false, Some(Name::from_ascii("digicert.com").unwrap()), vec![]),
+            CAA::new_issuewild(false, Some(Name::from_ascii("letsencrypt.org").unwrap()), vec![])
+        ]);
+        let issuer_map = CAAComplianceReport::build_issuer_map(&HashMap::new());
+        let rpt = CAAComplianceReport::evaluate("example.com", Some(&rs), &issuer_map, vec![
+            // *.example.com and example.com
+            (CertificateSource::Endpoint, "Let's Encrypt".to_string(), true, true),
+            (CertificateSource::Endpoint, "Let's Encrypt".to_string(), true, false)
+        ]);
+        assert_eq!(rpt.certificates[0].compliance, Compliance::Violation);
+        assert_eq!(rpt.certificates[1].compliance, Compliance::Allowed);
+        assert!(rpt.violation);
+    }
+
+    #[test]
+    fn unknown_critical_property() {
+        let rs = record_set(vec![
+            CAA::new_issue(false, Some(Name::from_ascii("letsencrypt.org").unwrap()), vec![]),
+            CAA { issuer_critical: false, tag: Property::Unknown("future".to_string()), value: Value::Unknown(vec![]) }
+        ]);
+        let issuer_map = CAAComplianceReport::build_issuer_map(&HashMap::new());
+        let certificates = || vec![(CertificateSource::Endpoint, "Let's Encrypt".to_string(), false, true)];
+        let rpt = CAAComplianceReport::evaluate("example.com", Some(&rs), &issuer_map, certificates());
+        assert!(rpt.unknown_critical_properties.is_empty());
+        assert!(!rpt.violation);
+
+        let rs = record_set(vec![
+            CAA::new_issue(false, Some(Name::from_ascii("letsencrypt.org").unwrap()), vec![]),
+            CAA { issuer_critical: true, tag: Property::Unknown("Future".to_string()), value: Value::Unknown(vec![]) }
+        ]);
+        let rpt = CAAComplianceReport::evaluate("example.com", Some(&rs), &issuer_map, certificates());
+        assert_eq!(rpt.unknown_critical_properties, vec!["future"]);
+        assert_eq!(rpt.certificates[0].compliance, Compliance::Violation);
+        assert!(rpt.violation);
+    }
+}
@@ -16,14 +16,14 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use actix_web::{http::header, web::{Data, Payload}, HttpMessage, HttpRequest, HttpResponse, Responder};
+use actix_web::{web::{Data, Payload}, HttpMessage, HttpRequest, HttpResponse, Responder};
 use log::{error, warn};
 use serde::{Deserialize, Serialize};
 
 use crate::{
     get_body_as_string, 
-    processing::filter::Filter, 
-    reports::{self, handle_report, reporting_api::{handle_reporting_api_report, ReportingApiReport}, ReportType}, 
+    processing::{Processor, RequestInfo}, 
+    reports::{self, handle_report, reporting_api::{handle_reporting_api_report, ReportingApiReport}, too_many_requests, ReportType}, 
     WebState
 };
 
@@ -86,22 +86,26 @@ pub struct CSPHash {
     destination: String
 }
 
-async fn handle_csp_lvl3_report(payload: &str, user_agent: Option<&str>, filter: &Filter) -> Result<(), reports::Error> {
+async fn handle_csp_lvl3_report(payload: &str, request: &RequestInfo, processor: &Processor) -> Result<(), reports::Error> {
     let report_parse_res = serde_json::from_str::<ReportingApiReport>(payload);
     match report_parse_res {
-        Ok(reports) => handle_reporting_api_report(&reports, user_agent, filter).await,
+        Ok(reports) => handle_reporting_api_report(&reports, request, processor).await,
         Err(err) => Err(reports::Error::Parse(err))
     }
 }
 
 pub async fn report_csp(state: Data<WebState>, req: HttpRequest, body: Payload) -> impl Responder {
-    let ua = req.headers().get(header::USER_AGENT).map(|h| h.to_str().unwrap());
+    let request = RequestInfo::from_request(&req, &state.processor);
+    if let Err(retry_after) = state.processor.rate_limiter.check_request(&request) {
+        return too_many_requests(retry_after);
+    }
     match req.content_type() {
         "application/reports+json" => {
             match get_body_as_string(body).await {
                 Ok(str) => {
-                    match handle_csp_lvl3_report(&str, ua, &state.filter).await {
+                    match handle_csp_lvl3_report(&str, &request, &state.processor).await {
                         Ok(_) => HttpResponse::Ok(),
+                        Err(reports::Error::RateLimited(retry_after)) => too_many_requests(retry_after),
                         Err(err) => {
                             error!("{} in {}", err, str);
                             HttpResponse::BadRequest()
@@ -120,9 +124,10 @@ pub async fn report_csp(state: Data<WebState>, req: HttpRequest, body: Payload)
                     let parse_res = serde_json::from_str::<CSPReport>(&str);
                     match parse_res {
                         Ok(report) => {
-                            let res = handle_report(&ReportType::CSPLvl2(&report), ua, Some(&state.filter));
+                            let res = handle_report(&ReportType::CSPLvl2(&report), Some(&request), Some(&state.processor));
                             match res {
                                 Ok(_) => HttpResponse::Ok(),
+                                Err(reports::Error::RateLimited(retry_after)) => too_many_requests(retry_after),
                                 Err(err) => {
                                     error!("{} in {:?}", err, report);
                                     HttpResponse::BadRequest()
@@ -131,11 +136,12 @@ pub async fn report_csp(state: Data<WebState>, req: HttpRequest, body: Payload)
                         },
                         Err(err_csp2) => {
                             // attempt to parse as CSP level 3 report
-                            match handle_csp_lvl3_report(&str, ua, &state.filter).await {
+                            match handle_csp_lvl3_report(&str, &request, &state.processor).await {
                                 Ok(_) => {
-                                    warn!("got CSP level 3 report with CSP level 2 content type from user agent: {}", ua.unwrap_or("unknown"));
+                                    warn!("got CSP level 3 report with CSP level 2 content type from user agent: {}", request.user_agent.as_deref().unwrap_or("unknown"));
                                     HttpResponse::Ok()
                                 },
+                                Err(reports::Error::RateLimited(retry_after)) => too_many_requests(retry_after),
                                 Err(err_csp3) => {
                                     error!("\"{}\" while trying to parse as CSP level 2 and \"{}\" while trying to parse as CSP level 3 in {}", 
                                         err_csp2, err_csp3, str);
@@ -152,7 +158,7 @@ pub async fn report_csp(state: Data<WebState>, req: HttpRequest, body: Payload)
             }
         },
         ct => {
-            error!("unexpected content type: {} (UA: {})", ct, ua.unwrap_or("unknown"));
+            error!("unexpected content type: {} (UA: {})", ct, request.user_agent.as_deref().unwrap_or("unknown"));
             HttpResponse::BadRequest()
         }
     }
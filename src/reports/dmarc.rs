@@ -26,6 +26,8 @@ use quick_xml::DeError;
 use serde::{Deserialize, Serialize};
 use zip::{result::ZipError, ZipArchive};
 
+use crate::structured_field::{split_top_level, unquote};
+
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct DateRange {
     begin: u64,
@@ -209,7 +211,10 @@ pub struct DMARCReport {
     report_metadata: ReportMetadata,
     policy_published: PolicyPublished,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
-    record: Vec<Record>
+    record: Vec<Record>,
+    /// Authentication-Results of the e-mail the report was attached to
+    #[serde(skip)]
+    authentication: Vec<MessageAuthentication>
 }
 
 impl DMARCReport {
@@ -220,6 +225,84 @@ impl DMARCReport {
     pub fn get_sender_organisation(&self) -> &String {
         &self.report_metadata.org_name
     }
+
+    pub fn get_sender_email(&self) -> &String {
+        &self.report_metadata.email
+    }
+
+    pub fn get_authentication_results(&self) -> &[MessageAuthentication] {
+        &self.authentication
+    }
+
+    pub fn get_source_ips(&self) -> Vec<&str> {
+        self.record.iter().map(|record| record.row.source_ip.as_str()).collect()
+    }
+}
+
+/**
+ * Result of one method (e.g. DKIM) of an Authentication-Results header (RFC 8601)
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageAuthentication {
+    pub authserv_id: String,
+    pub method: String,
+    pub result: String,
+    /// authenticated domain i.e. `header.d` of DKIM, the domain of `smtp.mailfrom` of SPF and `header.from` of DMARC
+    pub domain: Option<String>
+}
+
+impl MessageAuthentication {
+    pub fn parse(header: &str) -> Vec<Self> {
+        let header = strip_comments(header).replace(['\r', '\n', '\t'], " ");
+        let mut parts = split_top_level(&header, ';').into_iter();
+        let authserv_id = match parts.next().and_then(|id| id.split_whitespace().next()) {
+            Some(id) => id.to_lowercase(),
+            None => return vec![]
+        };
+        parts
+            .filter_map(|resinfo| {
+                let mut tokens = split_top_level(resinfo, ' ').into_iter();
+                let (method, result) = tokens.next()?.split_once('=')?;
+                let method = method.split('/').next().unwrap_or_default().trim().to_lowercase();
+                let properties: Vec<(String, String)> = tokens
+                    .filter_map(|token| token.split_once('=').map(|(key, value)| (key.to_lowercase(), unquote(value))))
+                    .collect();
+                let property = |key: &str| properties.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+                let domain = match method.as_str() {
+                    "dkim" => property("header.d").or(property("header.i").and_then(|i| i.rsplit_once('@').map(|(_, d)| d))),
+                    "spf" => property("smtp.mailfrom").map(|from| from.rsplit_once('@').map_or(from, |(_, d)| d)).or(property("smtp.helo")),
+                    "dmarc" => property("header.from"),
+                    _ => None
+                };
+                Some(MessageAuthentication {
+                    authserv_id: authserv_id.clone(),
+                    method,
+                    result: unquote(result).to_lowercase(),
+                    domain: domain.map(|d| d.to_lowercase())
+                })
+            })
+            .collect()
+    }
+}
+
+/**
+ * Removes comments (in parentheses) of e-mail headers
+ */
+fn strip_comments(header: &str) -> String {
+    let (mut res, mut depth, mut quoted) = (String::with_capacity(header.len()), 0, false);
+    for c in header.chars() {
+        match c {
+            '"' if depth == 0 => {
+                quoted = !quoted;
+                res.push(c);
+            },
+            '(' if !quoted => depth += 1,
+            ')' if !quoted && depth > 0 => depth -= 1,
+            c if depth == 0 => res.push(c),
+            _ => {}
+        }
+    }
+    res
 }
 
 #[allow(dead_code)]
@@ -331,7 +414,12 @@ impl DMARCReader {
                 debug!("unexpected content type: {:?}", attachment.content_type());
                 return Ok(None);
             }
-            return self.parse_report(&xml).map(|res| Some(res));
+            let mut report = self.parse_report(&xml)?;
+            report.authentication = msg.headers_raw()
+                .filter(|(name, _)| name.eq_ignore_ascii_case("Authentication-Results"))
+                .flat_map(|(_, value)| MessageAuthentication::parse(value))
+                .collect();
+            return Ok(Some(report));
         } else {
             debug!("no attachment found");
             return Ok(None);
@@ -445,7 +533,20 @@ mod tests {
                         result: SPFResult::Pass
                     }]
                 }
-            }]
+            }],
+            authentication: vec![]
         })
     }
+
+    #[test]
+    fn parse_authentication_results() {
+        let results = MessageAuthentication::parse("mx.example.com 1;\r\n\tdkim=pass (2048-bit key) header.d=Google.com header.s=20230601 header.b=\"abc;def\";\r\n\tspf=pass (domain of noreply-dmarc-support@google.com designates 209.85.220.73 as permitted sender) smtp.mailfrom=noreply-dmarc-support@google.com;\r\n\tdmarc=fail header.from=example.org; arc=none");
+        assert_eq!(results, vec![
+            MessageAuthentication { authserv_id: "mx.example.com".to_string(), method: "dkim".to_string(), result: "pass".to_string(), domain: Some("google.com".to_string()) },
+            MessageAuthentication { authserv_id: "mx.example.com".to_string(), method: "spf".to_string(), result: "pass".to_string(), domain: Some("google.com".to_string()) },
+            MessageAuthentication { authserv_id: "mx.example.com".to_string(), method: "dmarc".to_string(), result: "fail".to_string(), domain: Some("example.org".to_string()) },
+            MessageAuthentication { authserv_id: "mx.example.com".to_string(), method: "arc".to_string(), result: "none".to_string(), domain: None }
+        ]);
+        assert_eq!(MessageAuthentication::parse("mx.example.com; none"), vec![]);
+    }
 }
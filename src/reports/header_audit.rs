@@ -0,0 +1,401 @@
+/**
+ * network-journal - collect network reports and print them to file
+ * Copyright (C) 2025 nerou GmbH
+ * 
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{collections::HashSet, fmt::Display, time::Duration};
+
+use reqwest::{blocking::Client, header::HeaderMap};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{config::{AuditedHeader, HeaderAuditConfig, HeaderAuditTargetConfig}, structured_field::{parse_dictionary, parse_inner_list, parse_item, unquote}};
+
+#[derive(Serialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Problem {
+    /// the header is expected, but not sent
+    Missing,
+    /// the header cannot be parsed
+    Invalid,
+    /// the policy is set, but does not send any reports
+    NoReporting,
+    /// reports are sent to an endpoint name neither defined in `Reporting-Endpoints` nor `Report-To`
+    UnknownEndpoint,
+    /// reports are sent to another host than this instance or via plain HTTP
+    ForeignEndpoint,
+    /// the policy is effectively disabled e.g. by `max_age: 0`
+    Disabled
+}
+
+#[derive(Serialize, Debug)]
+pub struct Finding {
+    pub header: &'static str,
+    pub problem: Problem,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>
+}
+
+#[derive(Serialize, Debug)]
+pub struct HeaderAuditReport {
+    pub url: String,
+    /// final URL, if the request has been redirected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redirected_to: Option<String>,
+    pub status: u16,
+    /// no findings at all
+    pub passed: bool,
+    pub findings: Vec<Finding>
+}
+
+#[derive(Deserialize, Debug)]
+struct ReportToGroup {
+    #[serde(default = "default_report_to_group")]
+    group: String,
+    max_age: u64,
+    endpoints: Vec<ReportToEndpoint>
+}
+
+#[derive(Deserialize, Debug)]
+struct ReportToEndpoint {
+    url: String
+}
+
+fn default_report_to_group() -> String {
+    "default".to_string()
+}
+
+#[derive(Deserialize, Debug)]
+struct NelPolicy {
+    report_to: Option<String>,
+    max_age: u64
+}
+
+/**
+ * Names of endpoints (`Reporting-Endpoints`) and groups (`Report-To`) defined by the response
+ */
+struct Endpoints<'a> {
+    names: HashSet<String>,
+    /// NEL is only delivered to `Report-To` groups
+    groups: HashSet<String>,
+    base_url: &'a Url,
+    endpoint: Option<Url>
+}
+
+impl Endpoints<'_> {
+
+    fn check_name(&self, header: &'static str, name: &str, findings: &mut Vec<Finding>) {
+        if !self.names.contains(name) {
+            findings.push(Finding { header, problem: Problem::UnknownEndpoint, detail: Some(name.to_string()) });
+        }
+    }
+
+    fn check_group(&self, header: &'static str, name: &str, findings: &mut Vec<Finding>) {
+        if !self.groups.contains(name) {
+            findings.push(Finding { header, problem: Problem::UnknownEndpoint, detail: Some(name.to_string()) });
+        }
+    }
+
+    /**
+     * Endpoints have to use HTTPS and, if known, point to this instance
+     */
+    fn check_url(&self, header: &'static str, url: &str, findings: &mut Vec<Finding>) {
+        match self.base_url.join(url) {
+            Ok(url) => {
+                let foreign = url.scheme() != "https" 
+                    || self.endpoint.as_ref().is_some_and(|endpoint| !is_below(&url, endpoint));
+                if foreign {
+                    findings.push(Finding { header, problem: Problem::ForeignEndpoint, detail: Some(url.to_string()) });
+                }
+            },
+            Err(err) => findings.push(Finding { header, problem: Problem::Invalid, detail: Some(format!("{}: {}", url, err)) })
+        }
+    }
+}
+
+/**
+ * Whether the URL has the same origin as the endpoint and its path is the one of the endpoint or below
+ */
+fn is_below(url: &Url, endpoint: &Url) -> bool {
+    let path = endpoint.path().trim_end_matches('/');
+    url.scheme() == endpoint.scheme()
+        && url.host() == endpoint.host()
+        && url.port_or_known_default() == endpoint.port_or_known_default()
+        && url.path().strip_prefix(path).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+impl HeaderAuditReport {
+
+    pub fn create(target: &HeaderAuditTargetConfig, config: &HeaderAuditConfig) -> Result<Self, Error> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            .build()?;
+        let response = client.get(&target.url).send()?;
+        let final_url = response.url().clone();
+        let expected = target.headers.as_ref().unwrap_or(&config.headers);
+        let findings = audit(response.headers(), &final_url, expected, config.endpoint.as_deref());
+        Ok(Self {
+            redirected_to: if final_url.as_str() != target.url { Some(final_url.to_string()) } else { None },
+            url: target.url.clone(),
+            status: response.status().as_u16(),
+            passed: findings.is_empty(),
+            findings
+        })
+    }
+}
+
+fn audit(headers: &HeaderMap, url: &Url, expected: &[AuditedHeader], endpoint: Option<&str>) -> Vec<Finding> {
+    let mut findings = vec![];
+    let mut endpoints = Endpoints { names: HashSet::new(), groups: HashSet::new(), base_url: url, endpoint: endpoint.and_then(|endpoint| Url::parse(endpoint).ok()) };
+
+    // Reporting-Endpoints and Report-To first, since all other headers refer to them
+    let reporting_endpoints = header_value(headers, "reporting-endpoints");
+    if let Some(value) = &reporting_endpoints {
+        for (name, value) in parse_dictionary(value) {
+            endpoints.check_url("Reporting-Endpoints", &unquote(&value), &mut findings);
+            endpoints.names.insert(name);
+        }
+    }
+    let report_to = header_value(headers, "report-to");
+    if let Some(value) = &report_to {
+        match serde_json::from_str::<Vec<ReportToGroup>>(&format!("[{}]", value)) {
+            Ok(groups) => for group in groups {
+                if group.max_age == 0 {
+                    findings.push(Finding { header: "Report-To", problem: Problem::Disabled, detail: Some(group.group.clone()) });
+                }
+                for ep in &group.endpoints {
+                    endpoints.check_url("Report-To", &ep.url, &mut findings);
+                }
+                endpoints.names.insert(group.group.clone());
+                endpoints.groups.insert(group.group);
+            },
+            Err(err) => findings.push(Finding { header: "Report-To", problem: Problem::Invalid, detail: Some(err.to_string()) })
+        }
+    }
+    if reporting_endpoints.is_none() && report_to.is_none() && expected.contains(&AuditedHeader::ReportingEndpoints) {
+        findings.push(Finding { header: "Reporting-Endpoints", problem: Problem::Missing, detail: None });
+    }
+
+    match header_value(headers, "nel") {
+        Some(value) => match serde_json::from_str::<NelPolicy>(&value) {
+            Ok(policy) => {
+                if policy.max_age == 0 {
+                    findings.push(Finding { header: "NEL", problem: Problem::Disabled, detail: None });
+                }
+                endpoints.check_group("NEL", policy.report_to.as_deref().unwrap_or("default"), &mut findings);
+            },
+            Err(err) => findings.push(Finding { header: "NEL", problem: Problem::Invalid, detail: Some(err.to_string()) })
+        },
+        None => if expected.contains(&AuditedHeader::Nel) {
+            findings.push(Finding { header: "NEL", problem: Problem::Missing, detail: None });
+        }
+    }
+
+    audit_policy(headers, "Content-Security-Policy", AuditedHeader::Csp, expected, &mut findings, |header, value, findings| {
+        let mut reporting = false;
+        for directive in value.split([';', ',']) {
+            let mut tokens = directive.split_whitespace();
+            match tokens.next().map(|t| t.to_lowercase()).as_deref() {
+                Some("report-to") => for name in tokens {
+                    reporting = true;
+                    endpoints.check_name(header, name, findings);
+                },
+                Some("report-uri") => for uri in tokens {
+                    reporting = true;
+                    endpoints.check_url(header, uri, findings);
+                },
+                _ => {}
+            }
+        }
+        reporting
+    });
+
+    for (name, audited_header) in [("Cross-Origin-Opener-Policy", AuditedHeader::Coop), ("Cross-Origin-Embedder-Policy", AuditedHeader::Coep)] {
+        audit_policy(headers, name, audited_header, expected, &mut findings, |header, value, findings| {
+            let (_, params) = parse_item(value);
+            match params.iter().find(|(key, _)| key == "report-to") {
+                Some((_, endpoint)) => {
+                    endpoints.check_name(header, &unquote(endpoint), findings);
+                    true
+                },
+                None => false
+            }
+        });
+    }
+
+    audit_policy(headers, "Integrity-Policy", AuditedHeader::IntegrityPolicy, expected, &mut findings, |header, value, findings| {
+        let names = parse_dictionary(value).into_iter()
+            .filter(|(key, _)| key == "endpoints")
+            .flat_map(|(_, value)| parse_inner_list(&value))
+            .collect::<Vec<_>>();
+        for name in &names {
+            endpoints.check_name(header, name, findings);
+        }
+        !names.is_empty()
+    });
+
+    findings
+}
+
+/**
+ * Audits a policy header and its `-Report-Only` variant. `check` returns whether the policy sends reports at all.
+ */
+fn audit_policy<F>(headers: &HeaderMap, name: &'static str, audited_header: AuditedHeader, expected: &[AuditedHeader], findings: &mut Vec<Finding>, mut check: F)
+where
+    F: FnMut(&'static str, &str, &mut Vec<Finding>) -> bool
+{
+    let report_only_name: &'static str = match audited_header {
+        AuditedHeader::Csp => "Content-Security-Policy-Report-Only",
+        AuditedHeader::Coop => "Cross-Origin-Opener-Policy-Report-Only",
+        AuditedHeader::Coep => "Cross-Origin-Embedder-Policy-Report-Only",
+        _ => "Integrity-Policy-Report-Only"
+    };
+    let mut present = false;
+    for header in [name, report_only_name] {
+        if let Some(value) = header_value(headers, &header.to_lowercase()) {
+            present = true;
+            if !check(header, &value, findings) {
+                findings.push(Finding { header, problem: Problem::NoReporting, detail: None });
+            }
+        }
+    }
+    if !present && expected.contains(&audited_header) {
+        findings.push(Finding { header: name, problem: Problem::Missing, detail: None });
+    }
+}
+
+/**
+ * Combines multiple header lines of the same name like a comma separated list
+ */
+fn header_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    let values = headers.get_all(name).iter()
+        .map(|v| String::from_utf8_lossy(v.as_bytes()).to_string())
+        .collect::<Vec<_>>();
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.join(", "))
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    HttpError(reqwest::Error)
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(value: reqwest::Error) -> Self {
+        Self::HttpError(value)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::HttpError(e) => write!(f, "{}", e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    const ENDPOINT: &str = "https://network-journal.example.com";
+
+    fn audit_headers(headers: &[(&'static str, &'static str)], expected: &[AuditedHeader]) -> Vec<(&'static str, Problem)> {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(*name, HeaderValue::from_static(value));
+        }
+        audit(&map, &Url::parse("https://example.com/").unwrap(), expected, Some(ENDPOINT)).into_iter()
+            .map(|finding| (finding.header, finding.problem))
+            .collect()
+    }
+
+    #[test]
+    fn complete_headers() {
+        let findings = audit_headers(&[
+            ("reporting-endpoints", r#"default="https://network-journal.example.com/reporting-api""#),
+            ("report-to", r#"{"group": "nel", "max_age": 31556952, "endpoints": [{ "url": "https://network-journal.example.com/nel" }]}"#),
+            ("nel", r#"{ "report_to": "nel", "max_age": 31536000, "include_subdomains": true }"#),
+            ("content-security-policy", "default-src 'self'; report-to default; report-uri https://network-journal.example.com/csp"),
+            ("cross-origin-opener-policy", r#"same-origin; report-to="default""#),
+            ("cross-origin-embedder-policy-report-only", r#"require-corp; report-to="default""#),
+            ("integrity-policy", "blocked-destinations=(script), endpoints=(default)")
+        ], &HeaderAuditConfig::default().headers);
+        assert!(findings.is_empty(), "{:?}", findings);
+    }
+
+    #[test]
+    fn misconfigured_headers() {
+        let findings = audit_headers(&[
+            ("reporting-endpoints", r#"csp="https://reports.example.org/csp", legacy="http://network-journal.example.com/reporting-api""#),
+            ("nel", r#"{ "report_to": "nel", "max_age": 0 }"#),
+            ("content-security-policy", "default-src 'self'; report-to csp"),
+            ("cross-origin-opener-policy", "same-origin"),
+            ("integrity-policy", "blocked-destinations=(script), endpoints=(integrity)")
+        ], &HeaderAuditConfig::default().headers);
+        assert_eq!(findings, vec![
+            ("Reporting-Endpoints", Problem::ForeignEndpoint),
+            ("Reporting-Endpoints", Problem::ForeignEndpoint),
+            ("NEL", Problem::Disabled),
+            ("NEL", Problem::UnknownEndpoint),
+            ("Cross-Origin-Opener-Policy", Problem::NoReporting),
+            ("Cross-Origin-Embedder-Policy", Problem::Missing),
+            ("Integrity-Policy", Problem::UnknownEndpoint)
+        ]);
+    }
+
+    #[test]
+    fn nel_without_report_to() {
+        let findings = audit_headers(&[
+            ("reporting-endpoints", r#"default="https://network-journal.example.com/reporting-api""#),
+            ("nel", r#"{ "report_to": "default", "max_age": 31536000 }"#)
+        ], &[AuditedHeader::Nel]);
+        assert_eq!(findings, vec![("NEL", Problem::UnknownEndpoint)]);
+    }
+
+    #[test]
+    fn foreign_endpoints() {
+        let endpoint = Url::parse("https://network-journal.example.com/reports/").unwrap();
+        let below = |url: &str| is_below(&Url::parse(url).unwrap(), &endpoint);
+        assert!(below("https://network-journal.example.com/reports"));
+        assert!(below("https://NETWORK-JOURNAL.example.com:443/reports/csp"));
+        assert!(!below("https://network-journal.example.com.evil.com/reports/csp"));
+        assert!(!below("https://network-journal.example.com@evil.com/reports/csp"));
+        assert!(!below("https://network-journal.example.com:8443/reports/csp"));
+        assert!(!below("http://network-journal.example.com/reports/csp"));
+        assert!(!below("https://network-journal.example.com/reports-evil/csp"));
+        assert!(!below("https://network-journal.example.com/csp"));
+
+        let findings = audit_headers(&[
+            ("reporting-endpoints", r#"default="https://network-journal.example.com.evil.com/reporting-api""#)
+        ], &[AuditedHeader::ReportingEndpoints]);
+        assert_eq!(findings, vec![("Reporting-Endpoints", Problem::ForeignEndpoint)]);
+    }
+
+    #[test]
+    fn missing_headers() {
+        let findings = audit_headers(&[], &[AuditedHeader::ReportingEndpoints, AuditedHeader::Csp]);
+        assert_eq!(findings, vec![
+            ("Reporting-Endpoints", Problem::Missing),
+            ("Content-Security-Policy", Problem::Missing)
+        ]);
+    }
+}
@@ -41,7 +41,7 @@ pub struct NetworkError {
     request_headers: Option<HashMap<String, Vec<String>>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     response_headers: Option<HashMap<String, Vec<String>>>,
-    sampling_fraction: f32,
+    sampling_fraction: f64,
     server_ip: String,
     status_code: u16,
     r#type: String,
@@ -49,6 +49,41 @@ pub struct NetworkError {
     url: Option<String>,
 }
 
+impl NetworkError {
+
+    /**
+     * Network error of a request issued by network-journal itself, i.e. a GET request without referrer or sampling
+     */
+    pub fn synthetic(phase: Phase, r#type: &str, protocol: &str, server_ip: String, status_code: u16, elapsed_time: u64) -> Self {
+        Self {
+            elapsed_time,
+            method: "GET".to_string(),
+            phase,
+            protocol: protocol.to_string(),
+            referrer: None,
+            request_headers: None,
+            response_headers: None,
+            sampling_fraction: 1.0,
+            server_ip,
+            status_code,
+            r#type: r#type.to_string(),
+            url: None
+        }
+    }
+
+    pub fn get_type(&self) -> &String {
+        &self.r#type
+    }
+
+    pub fn get_sampling_fraction(&self) -> f64 {
+        self.sampling_fraction
+    }
+
+    pub fn get_server_ip(&self) -> &String {
+        &self.server_ip
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::reports::reporting_api::{Report, ReportingApiReport, ReportType};
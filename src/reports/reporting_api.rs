@@ -16,11 +16,11 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use actix_web::{http::header, web::{Data, Json}, HttpRequest, HttpResponse, Responder};
+use actix_web::{web::{Data, Json}, HttpRequest, HttpResponse, Responder};
 use log::error;
 use serde::{Deserialize, Serialize};
 
-use crate::{processing::filter::Filter, reports::{
+use crate::{processing::{Processor, RequestInfo}, reports::{
     self, 
     coep::CrossOriginEmbedderPolicyViolation, 
     coop::CrossOriginOpenerPolicyViolation, 
@@ -31,7 +31,8 @@ use crate::{processing::filter::Filter, reports::{
     integrity::IntegrityViolation, 
     intervention::Intervention, 
     nel::NetworkError, 
-    permissions::PermissionsPolicyViolation
+    permissions::PermissionsPolicyViolation, 
+    too_many_requests
 }, WebState};
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
@@ -71,28 +72,45 @@ pub enum ReportingApiReport {
     Multi(Vec<Report>)
 }
 
-pub async fn handle_reporting_api_report(reports: &ReportingApiReport, user_agent: Option<&str>, filter: &Filter) -> Result<(), reports::Error> {
+/**
+ * Reports of a batch exceeding the limit of their host are dropped (and counted in the Rate-Limit-Statistics), while the remaining ones are logged.
+ * As a retry would log the latter again, the batch is only rejected as rate limited if all of its reports have been dropped.
+ */
+pub async fn handle_reporting_api_report(reports: &ReportingApiReport, request: &RequestInfo, processor: &Processor) -> Result<(), reports::Error> {
     match reports {
-        ReportingApiReport::Single(report) => handle_report(&reports::ReportType::ReportingAPI(report), user_agent, Some(filter)),
+        ReportingApiReport::Single(report) => handle_report(&reports::ReportType::ReportingAPI(report), Some(request), Some(processor)),
         ReportingApiReport::Multi(reports) => {
-            let mut res = Ok(());
+            let (mut dropped, mut retry_after) = (0, 0);
             for report in reports {
-                let handle_res = handle_report(&reports::ReportType::ReportingAPI(report), user_agent, Some(filter));
-                if handle_res.is_err() {
-                    res = handle_res;
-                    break;
+                match handle_report(&reports::ReportType::ReportingAPI(report), Some(request), Some(processor)) {
+                    Ok(_) => {},
+                    // handle the remaining reports, which may be of other hosts
+                    Err(reports::Error::RateLimited(seconds)) => {
+                        dropped += 1;
+                        retry_after = retry_after.max(seconds);
+                    },
+                    Err(err) => return Err(err)
                 }
             }
-            res
+            if dropped > 0 && dropped == reports.len() {
+                Err(reports::Error::RateLimited(retry_after))
+            } else {
+                Ok(())
+            }
         }
     }
 }
 
 pub async fn reporting_api(state: Data<WebState>, req: HttpRequest, reports: Json<ReportingApiReport>) -> impl Responder {
+    let request = RequestInfo::from_request(&req, &state.processor);
+    if let Err(retry_after) = state.processor.rate_limiter.check_request(&request) {
+        return too_many_requests(retry_after);
+    }
     let rpts = reports.into_inner();
-    let res = handle_reporting_api_report(&rpts, req.headers().get(header::USER_AGENT).map(|h| h.to_str().unwrap()), &state.filter).await;
+    let res = handle_reporting_api_report(&rpts, &request, &state.processor).await;
     match res {
         Ok(_) => HttpResponse::Ok(),
+        Err(reports::Error::RateLimited(retry_after)) => too_many_requests(retry_after),
         Err(err) => {
             error!("{} in {:?}", err, rpts);
             HttpResponse::BadRequest()
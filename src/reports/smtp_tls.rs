@@ -18,12 +18,12 @@
 
 use std::io::{self, Read};
 
-use actix_web::{http::header, web::{Data, Payload}, HttpMessage, HttpRequest, HttpResponse, Responder};
+use actix_web::{web::{block, Data, Payload}, HttpMessage, HttpRequest, HttpResponse, Responder};
 use flate2::bufread::GzDecoder;
 use log::error;
 use serde::{Deserialize, Serialize};
 
-use crate::{get_body_as_string, reports::{self, handle_report, ReportType}, WebState};
+use crate::{get_body_as_string, processing::RequestInfo, reports::{self, handle_report, too_many_requests, ReportType}, WebState};
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 #[serde(rename_all = "kebab-case")]
@@ -102,6 +102,25 @@ impl SMTPTLSReport {
         }
         domains
     }
+
+    pub fn get_organization_name(&self) -> &String {
+        &self.organization_name
+    }
+
+    pub fn get_contact_info(&self) -> &String {
+        &self.contact_info
+    }
+
+    /**
+     * IP addresses of the failure details with the name of their field
+     */
+    pub fn get_ip_addresses(&self) -> Vec<(&'static str, &str)> {
+        self.policies.iter()
+            .flat_map(|policy| policy.failure_details.iter())
+            .flat_map(|details| [Some(("sending-mta-ip", details.sending_mta_ip.as_str())), details.receiving_ip.as_deref().map(|ip| ("receiving-ip", ip))])
+            .flatten()
+            .collect()
+    }
 }
 
 fn decode_reader(bytes: Vec<u8>) -> io::Result<String> {
@@ -112,7 +131,10 @@ fn decode_reader(bytes: Vec<u8>) -> io::Result<String> {
 }
 
 pub async fn report_smtp_tls(state: Data<WebState>, req: HttpRequest, body: Payload) -> impl Responder {
-    let ua = req.headers().get(header::USER_AGENT).map(|h| h.to_str().unwrap());
+    let request = RequestInfo::from_request(&req, &state.processor);
+    if let Err(retry_after) = state.processor.rate_limiter.check_request(&request) {
+        return too_many_requests(retry_after);
+    }
     let payload = if req.content_type() == "application/tlsrpt+gzip" && req.headers().get("content-encoding").is_none() {
         match body.to_bytes().await {
             Ok(bytes) => {
@@ -142,7 +164,7 @@ pub async fn report_smtp_tls(state: Data<WebState>, req: HttpRequest, body: Payl
             "unexpected content type/encoding: {}/{} (UA: {})",
             req.content_type(),
             req.headers().get("content-encoding").map_or("none", |ce| ce.to_str().unwrap_or("invalid")),
-            ua.unwrap_or("unknown")
+            request.user_agent.as_deref().unwrap_or("unknown")
         );
         return HttpResponse::BadRequest();
     };
@@ -155,16 +177,25 @@ pub async fn report_smtp_tls(state: Data<WebState>, req: HttpRequest, body: Payl
             return HttpResponse::BadRequest();
         }
     };
-    let res = handle_report(
-        &ReportType::SMTPTLSRPT(&report), 
-        ua,
-        Some(&state.filter)
-    );
+    // reverse DNS lookups block, so keep them off the async workers
+    let res = block(move || {
+        let res = handle_report(
+            &ReportType::SMTPTLSRPT(&report), 
+            Some(&request),
+            Some(&state.processor)
+        );
+        (res, report)
+    }).await;
     match res {
-        Ok(_) => HttpResponse::Ok(),
-        Err(err) => {
+        Ok((Ok(_), _)) => HttpResponse::Ok(),
+        Ok((Err(reports::Error::RateLimited(retry_after)), _)) => too_many_requests(retry_after),
+        Ok((Err(err), report)) => {
             error!("{} in {:?}", err, report);
             HttpResponse::BadRequest()
+        },
+        Err(err) => {
+            error!("failed to handle report: {}", err);
+            HttpResponse::InternalServerError()
         }
     }
 }
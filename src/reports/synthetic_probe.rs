@@ -0,0 +1,383 @@
+/**
+ * network-journal - collect network reports and print them to file
+ * Copyright (C) 2025 nerou GmbH
+ * 
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{fmt::Display, io::{self, BufRead, BufReader, Read, Write}, net::{SocketAddr, TcpStream, ToSocketAddrs}, time::{Duration, Instant}};
+
+use clap::{crate_name, crate_version};
+use openssl::ssl::{self, HandshakeError, SslConnector, SslMethod};
+use serde::Serialize;
+use url::Url;
+
+use crate::reports::nel::{NetworkError, Phase};
+
+const PROTOCOL: &str = "http/1.1";
+
+// X509_V_ERR_* codes of OpenSSL
+const X509_V_ERR_UNABLE_TO_GET_ISSUER_CERT: i32 = 2;
+const X509_V_ERR_CERT_NOT_YET_VALID: i32 = 9;
+const X509_V_ERR_CERT_HAS_EXPIRED: i32 = 10;
+const X509_V_ERR_DEPTH_ZERO_SELF_SIGNED_CERT: i32 = 18;
+const X509_V_ERR_SELF_SIGNED_CERT_IN_CHAIN: i32 = 19;
+const X509_V_ERR_UNABLE_TO_GET_ISSUER_CERT_LOCALLY: i32 = 20;
+const X509_V_ERR_UNABLE_TO_VERIFY_LEAF_SIGNATURE: i32 = 21;
+const X509_V_ERR_CERT_REVOKED: i32 = 23;
+const X509_V_ERR_CERT_UNTRUSTED: i32 = 27;
+const X509_V_ERR_HOSTNAME_MISMATCH: i32 = 62;
+
+/// durations of the phases of a request in milliseconds, absent if not reached
+#[derive(Serialize, Default, Debug)]
+pub struct ProbeTimings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<u64>,
+    /// time from sending the request until the first byte of the response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_byte: Option<u64>
+}
+
+/**
+ * Result of a request issued by network-journal itself. Its `type`, `url` and `body` are structured like 
+ * a NEL report received via the Reporting API, so both can be evaluated together.
+ */
+#[derive(Serialize, Debug)]
+pub struct SyntheticProbeReport {
+    pub r#type: &'static str,
+    pub url: String,
+    pub body: NetworkError,
+    pub timings: ProbeTimings
+}
+
+/// failure of a single phase, classified using the NEL error types
+struct Failure {
+    phase: Phase,
+    r#type: &'static str
+}
+
+impl Failure {
+    fn new(phase: Phase, r#type: &'static str) -> Self {
+        Self { phase, r#type }
+    }
+}
+
+impl SyntheticProbeReport {
+
+    /**
+     * Requests the URL from every resolved address, like clients falling back to the other addresses would
+     */
+    pub fn create(url: &str, timeout: Duration) -> Result<Vec<Self>, Error> {
+        let parsed_url = Url::parse(url).map_err(|err| Error::InvalidUrl(format!("{}: {}", url, err)))?;
+        let tls = match parsed_url.scheme() {
+            "https" => true,
+            "http" => false,
+            scheme => return Err(Error::InvalidUrl(format!("{}: unsupported scheme {}", url, scheme)))
+        };
+        let Some(host_header) = host_header(&parsed_url) else {
+            return Err(Error::InvalidUrl(format!("{}: no host", url)));
+        };
+        let host = parsed_url.host_str().unwrap_or_default().trim_start_matches('[').trim_end_matches(']');
+        let port = parsed_url.port_or_known_default().unwrap_or(if tls { 443 } else { 80 });
+        let mut path = parsed_url.path().to_string();
+        if let Some(query) = parsed_url.query() {
+            path = format!("{}?{}", path, query);
+        }
+
+        let started = Instant::now();
+        let addrs = (host, port).to_socket_addrs().map(|addrs| addrs.collect::<Vec<_>>()).unwrap_or_default();
+        let dns = elapsed_millis(started);
+        if addrs.is_empty() {
+            let failure = Failure::new(Phase::DNS, "dns.name_not_resolved");
+            return Ok(vec![Self::new(url, Err(failure), String::new(), dns, ProbeTimings::default())]);
+        }
+
+        Ok(addrs.into_iter()
+            .map(|addr| {
+                let started = Instant::now();
+                let mut timings = ProbeTimings { dns: Some(dns), ..Default::default() };
+                let res = Self::probe(addr, host, &host_header, &path, tls, timeout, &mut timings);
+                Self::new(url, res, addr.ip().to_string(), dns + elapsed_millis(started), timings)
+            })
+            .collect())
+    }
+
+    fn new(url: &str, res: Result<u16, Failure>, server_ip: String, elapsed_time: u64, timings: ProbeTimings) -> Self {
+        let body = match res {
+            Ok(status_code) if status_code >= 400 => NetworkError::synthetic(Phase::Application, "http.error", PROTOCOL, server_ip, status_code, elapsed_time),
+            Ok(status_code) => NetworkError::synthetic(Phase::Application, "ok", PROTOCOL, server_ip, status_code, elapsed_time),
+            Err(failure) => NetworkError::synthetic(failure.phase, failure.r#type, PROTOCOL, server_ip, 0, elapsed_time)
+        };
+        Self {
+            r#type: "network-error",
+            url: url.to_string(),
+            body,
+            timings
+        }
+    }
+
+    /**
+     * Returns the status code of the response
+     */
+    fn probe(addr: SocketAddr, host: &str, host_header: &str, path: &str, tls: bool, timeout: Duration, timings: &mut ProbeTimings) -> Result<u16, Failure> {
+        let phase_started = Instant::now();
+        let stream = connect(addr, timeout)?;
+        timings.connect = Some(elapsed_millis(phase_started));
+
+        if tls {
+            let phase_started = Instant::now();
+            let connector = SslConnector::builder(SslMethod::tls())
+                .map_err(|_| Failure::new(Phase::Connection, "tls.failed"))?
+                .build();
+            let stream = connector.connect(host, stream).map_err(classify_handshake_error)?;
+            timings.tls = Some(elapsed_millis(phase_started));
+            request(stream, host_header, path, timings)
+        } else {
+            request(stream, host_header, path, timings)
+        }
+    }
+}
+
+/**
+ * Host of the URL as sent in the Host header, i.e. including the brackets of IPv6 addresses and a port differing from the default one
+ */
+fn host_header(url: &Url) -> Option<String> {
+    let host = url.host_str()?;
+    Some(match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string()
+    })
+}
+
+fn connect(addr: SocketAddr, timeout: Duration) -> Result<TcpStream, Failure> {
+    let stream = TcpStream::connect_timeout(&addr, timeout).map_err(|err| classify_io_error(&err))?;
+    stream.set_read_timeout(Some(timeout)).and_then(|_| stream.set_write_timeout(Some(timeout)))
+        .map_err(|_| Failure::new(Phase::Connection, "tcp.failed"))?;
+    Ok(stream)
+}
+
+fn request<S: Read + Write>(mut stream: S, host: &str, path: &str, timings: &mut ProbeTimings) -> Result<u16, Failure> {
+    let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: {}/{}\r\nAccept: */*\r\nConnection: close\r\n\r\n", 
+        path, host, crate_name!(), crate_version!());
+    stream.write_all(request.as_bytes()).map_err(|err| classify_response_error(&err))?;
+
+    let phase_started = Instant::now();
+    let mut status_line = String::new();
+    let read = BufReader::new(stream).read_line(&mut status_line).map_err(|err| classify_response_error(&err))?;
+    timings.first_byte = Some(elapsed_millis(phase_started));
+    if read == 0 {
+        return Err(Failure::new(Phase::Application, "http.response.invalid.empty"));
+    }
+    parse_status_line(&status_line).ok_or(Failure::new(Phase::Application, "http.response.invalid"))
+}
+
+fn parse_status_line(line: &str) -> Option<u16> {
+    let mut parts = line.split_whitespace();
+    if !parts.next()?.starts_with("HTTP/") {
+        return None;
+    }
+    parts.next()?.parse().ok()
+}
+
+fn elapsed_millis(started: Instant) -> u64 {
+    started.elapsed().as_millis() as u64
+}
+
+fn classify_io_error(err: &io::Error) -> Failure {
+    let r#type = match err.kind() {
+        io::ErrorKind::ConnectionRefused => "tcp.refused",
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => "tcp.timed_out",
+        io::ErrorKind::ConnectionReset => "tcp.reset",
+        io::ErrorKind::ConnectionAborted => "tcp.aborted",
+        io::ErrorKind::HostUnreachable | io::ErrorKind::NetworkUnreachable | io::ErrorKind::AddrNotAvailable => "tcp.address_unreachable",
+        _ => "tcp.failed"
+    };
+    Failure::new(Phase::Connection, r#type)
+}
+
+/**
+ * Errors once the connection is established, e.g. the server not answering the request in time
+ */
+fn classify_response_error(err: &io::Error) -> Failure {
+    match err.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Failure::new(Phase::Application, "http.response.timeout"),
+        io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted => classify_io_error(err),
+        _ => Failure::new(Phase::Application, "http.failed")
+    }
+}
+
+fn classify_handshake_error(err: HandshakeError<TcpStream>) -> Failure {
+    match err {
+        HandshakeError::SetupFailure(_) => Failure::new(Phase::Connection, "tls.failed"),
+        HandshakeError::Failure(mid) | HandshakeError::WouldBlock(mid) => {
+            let verify_result = mid.ssl().verify_result().as_raw();
+            if verify_result != 0 {
+                return Failure::new(Phase::Connection, classify_verify_result(verify_result));
+            }
+            classify_ssl_error(mid.error())
+        }
+    }
+}
+
+fn classify_verify_result(code: i32) -> &'static str {
+    match code {
+        X509_V_ERR_CERT_NOT_YET_VALID | X509_V_ERR_CERT_HAS_EXPIRED => "tls.cert.date_invalid",
+        X509_V_ERR_HOSTNAME_MISMATCH => "tls.cert.name_invalid",
+        X509_V_ERR_CERT_REVOKED => "tls.cert.revoked",
+        X509_V_ERR_UNABLE_TO_GET_ISSUER_CERT 
+            | X509_V_ERR_DEPTH_ZERO_SELF_SIGNED_CERT 
+            | X509_V_ERR_SELF_SIGNED_CERT_IN_CHAIN 
+            | X509_V_ERR_UNABLE_TO_GET_ISSUER_CERT_LOCALLY 
+            | X509_V_ERR_UNABLE_TO_VERIFY_LEAF_SIGNATURE 
+            | X509_V_ERR_CERT_UNTRUSTED => "tls.cert.authority_invalid",
+        _ => "tls.cert.invalid"
+    }
+}
+
+fn classify_ssl_error(err: &ssl::Error) -> Failure {
+    if let Some(io_err) = err.io_error() {
+        return classify_io_error(io_err);
+    }
+    let reasons = err.ssl_error()
+        .map(|stack| stack.errors().iter().filter_map(|e| e.reason()).collect::<Vec<_>>().join(", "))
+        .unwrap_or_default();
+    let mismatch = ["protocol version", "unsupported protocol", "no protocols available", "handshake failure", "no shared cipher", "no cipher"]
+        .iter()
+        .any(|reason| reasons.contains(reason));
+    if mismatch {
+        Failure::new(Phase::Connection, "tls.version_or_cipher_mismatch")
+    } else if err.ssl_error().is_some() {
+        Failure::new(Phase::Connection, "tls.protocol.error")
+    } else {
+        Failure::new(Phase::Connection, "tls.failed")
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidUrl(String)
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidUrl(e) => write!(f, "invalid probe URL {}", e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, thread};
+
+    use super::*;
+
+    fn body_type(rpt: &SyntheticProbeReport) -> String {
+        serde_json::to_value(&rpt.body).unwrap()["type"].as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn probe_refused() {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let rpt = SyntheticProbeReport::create(&format!("http://127.0.0.1:{}/", port), Duration::from_secs(2)).unwrap().remove(0);
+        assert_eq!(body_type(&rpt), "tcp.refused");
+        assert!(rpt.timings.dns.is_some());
+        assert!(rpt.timings.connect.is_none());
+    }
+
+    #[test]
+    fn probe_not_resolved() {
+        let mut rpts = SyntheticProbeReport::create("https://name.invalid/", Duration::from_secs(2)).unwrap();
+        assert_eq!(rpts.len(), 1);
+        let rpt = rpts.remove(0);
+        assert_eq!(body_type(&rpt), "dns.name_not_resolved");
+        assert!(rpt.timings.dns.is_none());
+    }
+
+    #[test]
+    fn probe_http_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 1024];
+            let len = stream.read(&mut buf).unwrap();
+            stream.write_all(b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n").unwrap();
+            String::from_utf8_lossy(&buf[..len]).to_string()
+        });
+        let rpt = SyntheticProbeReport::create(&format!("http://127.0.0.1:{}/status?x=1", port), Duration::from_secs(2)).unwrap().remove(0);
+        let request = server.join().unwrap();
+        assert!(request.starts_with("GET /status?x=1 HTTP/1.1\r\n"));
+        assert!(request.contains(&format!("\r\nHost: 127.0.0.1:{}\r\n", port)));
+        let body = serde_json::to_value(&rpt.body).unwrap();
+        assert_eq!(body["type"], "http.error");
+        assert_eq!(body["phase"], "application");
+        assert_eq!(body["status_code"], 503);
+        assert_eq!(body["server_ip"], "127.0.0.1");
+        assert!(rpt.timings.first_byte.is_some());
+    }
+
+    #[test]
+    fn probe_response_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            // accept the connection, but never answer
+            let (stream, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_secs(2));
+            drop(stream);
+        });
+        let rpt = SyntheticProbeReport::create(&format!("http://127.0.0.1:{}/", port), Duration::from_secs(1)).unwrap().remove(0);
+        server.join().unwrap();
+        let body = serde_json::to_value(&rpt.body).unwrap();
+        assert_eq!(body["type"], "http.response.timeout");
+        assert_eq!(body["phase"], "application");
+        assert!(rpt.timings.connect.is_some());
+    }
+
+    #[test]
+    fn probe_all_addresses() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let rpts = SyntheticProbeReport::create(&format!("http://localhost:{}/", port), Duration::from_secs(2)).unwrap();
+        let addresses = ("localhost", port).to_socket_addrs().unwrap().count();
+        assert_eq!(rpts.len(), addresses);
+        assert!(rpts.iter().any(|rpt| rpt.body.get_server_ip() == "127.0.0.1"));
+    }
+
+    #[test]
+    fn build_host_header() {
+        let host = |url: &str| host_header(&Url::parse(url).unwrap());
+        assert_eq!(host("https://example.com/"), Some("example.com".to_string()));
+        assert_eq!(host("https://example.com:443/"), Some("example.com".to_string()));
+        assert_eq!(host("https://example.com:8443/"), Some("example.com:8443".to_string()));
+        assert_eq!(host("http://[2001:db8::1]/"), Some("[2001:db8::1]".to_string()));
+        assert_eq!(host("http://[2001:db8::1]:8080/"), Some("[2001:db8::1]:8080".to_string()));
+    }
+
+    #[test]
+    fn classify_errors() {
+        assert_eq!(classify_verify_result(X509_V_ERR_CERT_HAS_EXPIRED), "tls.cert.date_invalid");
+        assert_eq!(classify_verify_result(X509_V_ERR_DEPTH_ZERO_SELF_SIGNED_CERT), "tls.cert.authority_invalid");
+        assert_eq!(classify_io_error(&io::Error::from(io::ErrorKind::TimedOut)).r#type, "tcp.timed_out");
+        assert_eq!(classify_response_error(&io::Error::from(io::ErrorKind::WouldBlock)).r#type, "http.response.timeout");
+        assert_eq!(classify_response_error(&io::Error::from(io::ErrorKind::ConnectionReset)).r#type, "tcp.reset");
+        assert_eq!(parse_status_line("HTTP/1.1 200 OK\r\n"), Some(200));
+        assert_eq!(parse_status_line("SSH-2.0-OpenSSH_9.6\r\n"), None);
+    }
+}
@@ -0,0 +1,266 @@
+/**
+ * network-journal - collect network reports and print them to file
+ * Copyright (C) 2025 nerou GmbH
+ * 
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{collections::HashMap, fmt::Display, fs, io, net::IpAddr, path::{Path, PathBuf}};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::reports::tls_cert_validity::{CertificateIdentifier, CertificateInfo};
+
+const STATE_FILE: &str = "certificate-check-state.json";
+
+/// subset of `CertificateInfo` persisted between checks to detect changes
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct CertificateState {
+    pub serial_number: String,
+    pub fingerprint: String,
+    pub public_key_fingerprint: String,
+    pub issuer: CertificateIdentifier,
+    /// RFC 3339 formatted
+    pub not_after: String
+}
+
+impl From<&CertificateInfo> for CertificateState {
+    fn from(info: &CertificateInfo) -> Self {
+        Self {
+            serial_number: info.serial_number.clone(),
+            fingerprint: info.fingerprint.clone(),
+            public_key_fingerprint: info.public_key_fingerprint.clone(),
+            issuer: info.issuer.clone(),
+            not_after: info.not_after.to_rfc3339()
+        }
+    }
+}
+
+#[derive(Serialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum CertificateChange {
+    /// another certificate than during the previous check is presented
+    Renewal,
+    /// the new certificate has been issued by another CA than the previous one
+    IssuerChange,
+    /// the new certificate uses the same key as the previous one
+    KeyReuse,
+    /// the certificate expires within the configured threshold and has not been replaced yet
+    NotRenewed
+}
+
+#[derive(Serialize, Debug)]
+pub struct TLSCertificateChangeReport {
+    pub domain: String,
+    pub port: u16,
+    pub address: IpAddr,
+    pub change: CertificateChange,
+    /// certificate presented during the previous check
+    pub old: CertificateState,
+    /// certificate presented during the current check
+    pub new: CertificateState,
+    pub expires_in_days: i64
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+struct StateFile {
+    #[serde(default)]
+    certificates: HashMap<String, CertificateState>,
+    /// RFC 3339 formatted time of the last check per target
+    #[serde(default)]
+    last_checks: HashMap<String, String>,
+    /// fingerprint of the certificate per domain, port and address, which has been reported as not renewed
+    #[serde(default)]
+    not_renewed: HashMap<String, String>
+}
+
+/**
+ * Last seen certificate per domain, port and address as well as the time of the last check per target, 
+ * persisted as JSON in the state directory
+ */
+pub struct CertificateStateStore {
+    path: PathBuf,
+    state: StateFile
+}
+
+impl CertificateStateStore {
+
+    pub fn new(state_dir: &Path) -> Self {
+        Self {
+            path: state_dir.join(STATE_FILE),
+            state: StateFile::default()
+        }
+    }
+
+    /**
+     * A missing state file results in an empty store (e.g. on first start)
+     */
+    pub fn load(state_dir: &Path) -> Result<Self, Error> {
+        let mut store = Self::new(state_dir);
+        match fs::read_to_string(&store.path) {
+            Ok(content) => store.state = serde_json::from_str(&content)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {},
+            Err(err) => return Err(err.into())
+        }
+        Ok(store)
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // write to a temporary file first to not corrupt the state on failure
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(&self.state)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    pub fn last_check(&self, target: &str) -> Option<DateTime<Utc>> {
+        self.state.last_checks.get(target)
+            .and_then(|d| DateTime::parse_from_rfc3339(d).ok())
+            .map(|d| d.to_utc())
+    }
+
+    pub fn set_last_check(&mut self, target: &str, time: DateTime<Utc>) {
+        self.state.last_checks.insert(target.to_string(), time.to_rfc3339());
+    }
+
+    /**
+     * Stores the presented certificate and returns the changes compared to the previous check.
+     * Nothing is reported for targets checked for the first time, a certificate is reported as not renewed only once.
+     */
+    pub fn update(&mut self, domain: &str, port: u16, address: IpAddr, info: &CertificateInfo, renewal_threshold_days: i64) -> Vec<TLSCertificateChangeReport> {
+        let key = format!("{}:{}/{}", domain, port, address);
+        let new = CertificateState::from(info);
+        let Some(old) = self.state.certificates.insert(key.clone(), new.clone()) else {
+            return vec![];
+        };
+
+        let expires_in_days = info.get_days_until_expiration();
+        let mut changes = vec![];
+        if old.fingerprint == new.fingerprint {
+            if expires_in_days <= renewal_threshold_days && self.state.not_renewed.get(&key) != Some(&new.fingerprint) {
+                changes.push(CertificateChange::NotRenewed);
+                self.state.not_renewed.insert(key, new.fingerprint.clone());
+            }
+        } else {
+            self.state.not_renewed.remove(&key);
+            changes.push(CertificateChange::Renewal);
+            if old.issuer != new.issuer {
+                changes.push(CertificateChange::IssuerChange);
+            }
+            if old.public_key_fingerprint == new.public_key_fingerprint {
+                changes.push(CertificateChange::KeyReuse);
+            }
+        }
+
+        changes.into_iter().map(|change| TLSCertificateChangeReport {
+            domain: domain.to_string(),
+            port,
+            address,
+            change,
+            old: old.clone(),
+            new: new.clone(),
+            expires_in_days
+        }).collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Json(serde_json::Error)
+}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Json(value)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "failed to access certificate state: {}", e),
+            Self::Json(e) => write!(f, "failed to (de)serialize certificate state: {}", e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+
+    use super::*;
+
+    fn create_info(serial_number: &str, key: &str, issuer: &str, expires_in_days: i64) -> CertificateInfo {
+        let identifier = |cn: &str| CertificateIdentifier {
+            common_name: Some(cn.to_string()),
+            organization_name: None,
+            organizational_unit_name: None,
+            country_name: None,
+            state_or_province_name: None,
+            locality_name: None
+        };
+        CertificateInfo {
+            serial_number: serial_number.to_string(),
+            fingerprint: format!("fingerprint-{}", serial_number),
+            public_key_fingerprint: key.to_string(),
+            issuer: identifier(issuer),
+            subject: identifier("example.com"),
+            subject_alt_names: vec!["example.com".to_string()],
+            not_before: (Utc::now() - Duration::days(1)).fixed_offset(),
+            not_after: (Utc::now() + Duration::days(expires_in_days) + Duration::hours(1)).fixed_offset(),
+            crl_distribution_urls: vec![],
+            ocsp_urls: vec![]
+        }
+    }
+
+    fn changes(store: &mut CertificateStateStore, info: &CertificateInfo) -> Vec<CertificateChange> {
+        store.update("example.com", 443, "192.0.2.1".parse().unwrap(), info, 14).into_iter().map(|rpt| rpt.change).collect()
+    }
+
+    #[test]
+    fn detect_changes() {
+        let mut store = CertificateStateStore::new(Path::new("."));
+        assert!(changes(&mut store, &create_info("1", "key-a", "R10", 60)).is_empty());
+        assert!(changes(&mut store, &create_info("1", "key-a", "R10", 59)).is_empty());
+        assert_eq!(changes(&mut store, &create_info("1", "key-a", "R10", 10)), vec![CertificateChange::NotRenewed]);
+        // reported once
+        assert!(changes(&mut store, &create_info("1", "key-a", "R10", 9)).is_empty());
+        assert_eq!(changes(&mut store, &create_info("2", "key-a", "R11", 90)), vec![
+            CertificateChange::Renewal,
+            CertificateChange::IssuerChange,
+            CertificateChange::KeyReuse
+        ]);
+        assert_eq!(changes(&mut store, &create_info("3", "key-b", "R11", 90)), vec![CertificateChange::Renewal]);
+        assert_eq!(changes(&mut store, &create_info("3", "key-b", "R11", 5)), vec![CertificateChange::NotRenewed]);
+    }
+
+    #[test]
+    fn separate_addresses() {
+        let mut store = CertificateStateStore::new(Path::new("."));
+        store.update("example.com", 443, "192.0.2.1".parse().unwrap(), &create_info("1", "key-a", "R10", 60), 14);
+        let rpts = store.update("example.com", 443, "192.0.2.2".parse().unwrap(), &create_info("2", "key-b", "R10", 60), 14);
+        assert!(rpts.is_empty());
+    }
+}
@@ -16,18 +16,28 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::{fmt::Display, io, net::TcpStream};
+use std::{fmt::Display, fs, io::{self, BufRead, BufReader, Write}, net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs}, path::{Path, PathBuf}, time::Duration};
 
 use chrono::{DateTime, FixedOffset, Utc};
-use log::error;
-use openssl::{asn1::Asn1TimeRef, error::ErrorStack, nid::Nid, ssl::{self, HandshakeError, SslConnector, SslMethod, SslVerifyMode}, x509::{CrlStatus, X509Crl, X509NameRef, X509}};
-use serde::{Serialize, Serializer};
+use log::{error, trace, warn};
+use openssl::{error::ErrorStack, hash::{hash, MessageDigest}, nid::Nid, ssl::{self, HandshakeError, SslConnector, SslConnectorBuilder, SslMethod, SslStream, SslVerifyMode}, x509::{X509NameRef, X509VerifyResult, X509}};
+use serde::{Deserialize, Serialize, Serializer};
 
-const CRL_MIME_TYPES: &[&'static str] = &["application/pkix-crl", "application/x-pkcs7-crl"];
+use crate::{config::{default_certificate_check_timeout, default_critical_days, default_warning_days, CertificateCheckConfig, CertificateChecksConfig}, dns::Resolver, reports::tls_cert_validity::{dane::{DaneResult, DaneStatus, TLSARecords}, revocation::{RevocationCache, RevocationStatus}}};
 
-#[derive(Serialize, Debug)]
+pub mod dane;
+pub mod revocation;
+#[cfg(test)]
+pub(crate) mod test_certs;
+
+/// file extensions of certificates picked up from configured directories
+const CERTIFICATE_FILE_EXTENSIONS: &[&str] = &["pem", "crt", "cer", "der"];
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct CertificateIdentifier {
-    pub common_name: String,
+    /// absent e.g. for certificates only identified by their subject alternative names
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub common_name: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub organization_name: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -59,7 +69,7 @@ impl From<&X509NameRef> for CertificateIdentifier {
     
     fn from(props: &X509NameRef) -> Self {
         Self {
-            common_name: Self::extract_single_entry(props, Nid::COMMONNAME).unwrap(),
+            common_name: Self::extract_single_entry(props, Nid::COMMONNAME),
             organization_name: Self::extract_single_entry(props, Nid::ORGANIZATIONNAME),
             organizational_unit_name: Self::extract_single_entry(props, Nid::ORGANIZATIONALUNITNAME),
             country_name: Self::extract_single_entry(props, Nid::COUNTRYNAME),
@@ -72,6 +82,10 @@ impl From<&X509NameRef> for CertificateIdentifier {
 #[derive(Serialize, Debug)]
 pub struct CertificateInfo {
     pub serial_number: String,
+    /// SHA-256 hash of the DER encoded certificate
+    pub fingerprint: String,
+    /// SHA-256 hash of the DER encoded public key (SubjectPublicKeyInfo)
+    pub public_key_fingerprint: String,
     pub issuer: CertificateIdentifier,
     pub subject: CertificateIdentifier,
     pub subject_alt_names: Vec<String>,
@@ -80,7 +94,10 @@ pub struct CertificateInfo {
     #[serde(serialize_with = "serialize_datetime")]
     pub not_after: DateTime<FixedOffset>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
-    pub crl_distribution_urls: Vec<String>
+    pub crl_distribution_urls: Vec<String>,
+    /// OCSP responders of the Authority Information Access extension
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ocsp_urls: Vec<String>
 }
 
 pub fn serialize_datetime<S>(
@@ -106,19 +123,35 @@ impl CertificateInfo {
         return (self.not_after.to_utc() - now).num_days();
     }
 
-    fn asn1_date_to_chrono(asn1_time: &Asn1TimeRef) -> Result<DateTime<FixedOffset>, Error> {
+    /**
+     * Common name of the subject, the first subject alternative name if missing or else the serial number
+     */
+    pub fn get_name(&self) -> &str {
+        self.subject.common_name.as_deref()
+            .or(self.subject_alt_names.first().map(String::as_str))
+            .unwrap_or(&self.serial_number)
+    }
+
+    fn asn1_date_to_chrono(asn1_time: &impl Display) -> Result<DateTime<FixedOffset>, Error> {
         Ok(DateTime::parse_from_str(&asn1_time.to_string().replace("GMT", "+00:00"), "%b %d %T %Y %:z")?)
     }
 
     fn extract_info_from_cert(cert: &X509) -> Result<Self, Error> {
         let mut info = Self {
             serial_number: cert.serial_number().to_bn()?.to_hex_str()?.to_string(),
+            fingerprint: to_hex(&cert.digest(MessageDigest::sha256())?),
+            public_key_fingerprint: to_hex(&hash(MessageDigest::sha256(), &cert.public_key()?.public_key_to_der()?)?),
             issuer: cert.issuer_name().into(),
             subject: cert.subject_name().into(),
-            subject_alt_names: cert.subject_alt_names().unwrap().into_iter().map(|x| x.dnsname().unwrap().to_string()).collect(),
+            subject_alt_names: cert.subject_alt_names()
+                .map(|names| names.into_iter().filter_map(|x| x.dnsname().map(|n| n.to_string())).collect())
+                .unwrap_or_default(),
             not_before: Self::asn1_date_to_chrono(&cert.not_before())?,
             not_after: Self::asn1_date_to_chrono(&cert.not_after())?,
-            crl_distribution_urls: vec![]
+            crl_distribution_urls: vec![],
+            ocsp_urls: cert.ocsp_responders()
+                .map(|urls| urls.iter().map(|url| url.to_string()).collect())
+                .unwrap_or_default()
         };
 
         if let Some(crl_distribution_points) = cert.crl_distribution_points() {
@@ -138,14 +171,61 @@ impl CertificateInfo {
     }
 }
 
+/// result of the check of a single address of a target
+pub type AddressCheckResult = (IpAddr, Result<Option<TLSCertificateValidityReport>, Error>);
+/// result of the check of a single certificate file
+pub type FileCheckResult = (PathBuf, Result<Option<TLSCertificateValidityReport>, Error>);
+
+#[derive(Serialize, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Ok,
+    /// e.g. expiring within `warning_days`, differing certificates between addresses or upcoming DANE rollover issues
+    Warning,
+    /// e.g. expired, revoked, expiring within `critical_days` or not matching the TLSA records
+    Critical
+}
+
 #[derive(Serialize, Debug)]
 pub struct TLSCertificateValidityReport {
+    pub severity: Severity,
+    /// IP address the certificate has been retrieved from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<IpAddr>,
+    /// file the certificate has been read from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
     pub certificate: CertificateInfo,
     pub expired: bool,
     pub expires_in_days: i64,
     pub revoked: bool,
     #[serde(skip_serializing_if = "Option::is_none", serialize_with = "serialize_datetime_opt")]
-    pub revoked_since: Option<DateTime<FixedOffset>>
+    pub revoked_since: Option<DateTime<FixedOffset>>,
+    /// none if the certificate has neither CRL distribution points nor OCSP responders
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revocation_status: Option<RevocationStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dane: Option<DaneStatus>,
+    /// other addresses of the same domain presenting a different certificate
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub differing_addresses: Vec<IpAddr>
+}
+
+impl Severity {
+
+    fn evaluate(rpt: &TLSCertificateValidityReport, warning_days: i64, critical_days: i64) -> Self {
+        let dane_result = rpt.dane.as_ref().map(|dane| &dane.result);
+        if rpt.expired || rpt.revoked || rpt.expires_in_days <= critical_days || dane_result == Some(&DaneResult::Mismatch) {
+            Self::Critical
+        } else if rpt.expires_in_days <= warning_days 
+            || !rpt.differing_addresses.is_empty() 
+            || dane_result == Some(&DaneResult::Insecure) 
+            || rpt.dane.as_ref().is_some_and(|dane| !dane.rollover_warnings.is_empty()) {
+            Self::Warning
+        } else {
+            Self::Ok
+        }
+    }
 }
 
 pub fn serialize_datetime_opt<S>(
@@ -165,12 +245,59 @@ where
 
 impl TLSCertificateValidityReport {
     
-    pub fn create(host: &str, port: u16) -> Result<Option<TLSCertificateValidityReport>, Error> {
-        let mut connector = SslConnector::builder(SslMethod::tls())?;
-        connector.set_verify(SslVerifyMode::NONE);
+    /**
+     * Checks every resolved address of the target separately, failures of single addresses do not affect the others
+     */
+    pub fn create(target: &CertificateCheckConfig, resolver: &Resolver, revocation_cache: &RevocationCache) -> Result<Vec<AddressCheckResult>, Error> {
+        let addresses = (target.domain.as_str(), target.port).to_socket_addrs()?.collect::<Vec<_>>();
+
+        let tlsa_records = if target.dane {
+            match TLSARecords::lookup(resolver, &target.domain, target.port) {
+                Ok(records) => Some(records),
+                Err(err) => {
+                    error!("DANE check for {}:{} failed: {}", target.domain, target.port, err);
+                    None
+                }
+            }
+        } else {
+            None
+        };
 
-        let stream = TcpStream::connect(format!("{}:{}", host, port))?;
-        let mut stream = connector.build().connect(host, stream)?;
+        let mut results = addresses.iter()
+            .map(|addr| (addr.ip(), Self::create_for_address(target, *addr, tlsa_records.as_ref(), revocation_cache)))
+            .collect::<Vec<_>>();
+        Self::compare_addresses(target, &mut results);
+        Ok(results)
+    }
+
+    /**
+     * Lists the addresses presenting a different certificate in each report and evaluates its severity
+     */
+    fn compare_addresses(target: &CertificateCheckConfig, results: &mut [AddressCheckResult]) {
+        let fingerprints = results.iter()
+            .filter_map(|(ip, res)| match res {
+                Ok(Some(rpt)) => Some((*ip, rpt.certificate.fingerprint.clone())),
+                _ => None
+            })
+            .collect::<Vec<_>>();
+        for (_, res) in results.iter_mut() {
+            if let Ok(Some(rpt)) = res {
+                rpt.differing_addresses = fingerprints.iter()
+                    .filter(|(_, fingerprint)| *fingerprint != rpt.certificate.fingerprint)
+                    .map(|(ip, _)| *ip)
+                    .collect();
+                if !rpt.differing_addresses.is_empty() {
+                    warn!("certificate of {}:{} at {:?} differs from the one at {:?}", target.domain, target.port, rpt.address, rpt.differing_addresses);
+                }
+                rpt.severity = Severity::evaluate(rpt, 
+                    target.warning_days.unwrap_or_else(default_warning_days), 
+                    target.critical_days.unwrap_or_else(default_critical_days));
+            }
+        }
+    }
+
+    fn create_for_address(target: &CertificateCheckConfig, addr: SocketAddr, tlsa_records: Option<&TLSARecords>, revocation_cache: &RevocationCache) -> Result<Option<TLSCertificateValidityReport>, Error> {
+        let mut stream = connect(target, addr, |_| Ok(()))?;
 
         let mut res = None;
         if let Some(cert) = stream.ssl().peer_certificate() {
@@ -178,35 +305,164 @@ impl TLSCertificateValidityReport {
 
             let info = CertificateInfo::extract_info_from_cert(&cert)?;
 
-            let mut revoked = None;
-            for dist_point in &info.crl_distribution_urls {
-                let response = reqwest::blocking::get(dist_point)?;
-                let mime_type = response.headers().get("Content-Type").unwrap().to_str().unwrap();
-                if response.status().is_success() && CRL_MIME_TYPES.contains(&mime_type){
-                    let crl_res = X509Crl::from_der(&response.bytes()?);
-                    if let Ok(crl) = crl_res {
-                        let status = crl.get_by_cert(&cert);
-                        if let CrlStatus::Revoked(rev) = status {
-                            revoked = Some(CertificateInfo::asn1_date_to_chrono(rev.revocation_date())?);
-                        }
-                    }
-                } else {
-                    error!("CRL check failed, got status code {} and MIME type \"{}\"", response.status(), mime_type);
+            let issuer = stream.ssl().peer_cert_chain()
+                .and_then(|chain| chain.iter().find(|c| c.issued(&cert) == X509VerifyResult::OK).map(|c| c.to_owned()));
+            let revocation = revocation_cache.check(&cert, &info, issuer.as_ref());
+
+            let dane = tlsa_records.map(|records| {
+                let mut chain = vec![cert.clone()];
+                if let Some(peer_chain) = stream.ssl().peer_cert_chain() {
+                    // the peer's chain usually, but not necessarily, starts with the end entity certificate
+                    chain.extend(peer_chain.iter()
+                        .filter(|c| c.to_der().ok() != cert.to_der().ok())
+                        .map(|c| c.to_owned()));
                 }
-            }
+                records.evaluate(&chain)
+            });
 
             res = Some(Self { 
+                // evaluated once all addresses have been checked
+                severity: Severity::Ok,
+                address: Some(addr.ip()),
+                path: None,
                 expired: info.is_expired(), 
                 expires_in_days: info.get_days_until_expiration(),
                 certificate: info,
-                revoked: revoked.is_some(), 
-                revoked_since: revoked
+                revoked: revocation.as_ref().is_some_and(|r| r.status == RevocationStatus::Revoked),
+                revoked_since: revocation.as_ref().and_then(|r| r.revoked_since),
+                revocation_status: revocation.map(|r| r.status),
+                dane,
+                differing_addresses: vec![]
             });
         }
 
         stream.shutdown()?;
         Ok(res)
     }
+
+    /**
+     * Checks the configured certificate files and all certificate files (see `CERTIFICATE_FILE_EXTENSIONS`) in the configured directories.
+     * Only the first certificate of a file is checked, any further ones are considered to be its chain.
+     */
+    pub fn create_for_files(config: &CertificateChecksConfig, server_cert: Option<&Path>, revocation_cache: &RevocationCache) -> Vec<FileCheckResult> {
+        let mut files = vec![];
+        for path in config.files.iter().map(|p| p.as_path()).chain(server_cert) {
+            if path.is_dir() {
+                match fs::read_dir(path) {
+                    Ok(entries) => {
+                        let mut dir_files = entries
+                            .filter_map(|entry| entry.ok().map(|e| e.path()))
+                            .filter(|p| p.is_file() && p.extension().is_some_and(|ext| CERTIFICATE_FILE_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str())))
+                            .map(|p| (p, true))
+                            .collect::<Vec<_>>();
+                        dir_files.sort();
+                        files.extend(dir_files);
+                    },
+                    Err(err) => error!("failed to read certificate directory {}: {}", path.display(), err)
+                }
+            } else if !files.iter().any(|(p, _)| p == path) {
+                files.push((path.to_path_buf(), false));
+            }
+        }
+
+        files.into_iter()
+            .filter_map(|(path, from_dir)| match Self::create_for_file(&path, revocation_cache, config.warning_days, config.critical_days) {
+                // directories may contain other PEM files like private keys
+                Ok(None) if from_dir => {
+                    trace!("no certificate found in {}", path.display());
+                    None
+                },
+                res => Some((path, res))
+            })
+            .collect()
+    }
+
+    fn create_for_file(path: &Path, revocation_cache: &RevocationCache, warning_days: i64, critical_days: i64) -> Result<Option<TLSCertificateValidityReport>, Error> {
+        let content = fs::read(path)?;
+        let certs = if content.windows(10).any(|w| w == b"-----BEGIN") {
+            // PEM files without any certificate result in an error
+            X509::stack_from_pem(&content).unwrap_or_default()
+        } else {
+            vec![X509::from_der(&content)?]
+        };
+        let Some(cert) = certs.first() else {
+            return Ok(None);
+        };
+
+        let info = CertificateInfo::extract_info_from_cert(cert)?;
+        let issuer = certs.iter().skip(1).find(|c| c.issued(cert) == X509VerifyResult::OK);
+        let revocation = revocation_cache.check(cert, &info, issuer);
+        let mut rpt = Self {
+            severity: Severity::Ok,
+            address: None,
+            path: Some(path.to_path_buf()),
+            expired: info.is_expired(),
+            expires_in_days: info.get_days_until_expiration(),
+            certificate: info,
+            revoked: revocation.as_ref().is_some_and(|r| r.status == RevocationStatus::Revoked),
+            revoked_since: revocation.as_ref().and_then(|r| r.revoked_since),
+            revocation_status: revocation.map(|r| r.status),
+            dane: None,
+            differing_addresses: vec![]
+        };
+        rpt.severity = Severity::evaluate(&rpt, warning_days, critical_days);
+        Ok(Some(rpt))
+    }
+}
+
+/**
+ * Connects to the given address using the target's domain for SNI. Certificate verification is disabled, 
+ * `configure` may restrict the connector e.g. to specific protocol versions.
+ */
+pub(crate) fn connect<F>(target: &CertificateCheckConfig, addr: SocketAddr, configure: F) -> Result<SslStream<TcpStream>, Error>
+where
+    F: FnOnce(&mut SslConnectorBuilder) -> Result<(), ErrorStack>
+{
+    let mut connector = SslConnector::builder(SslMethod::tls())?;
+    connector.set_verify(SslVerifyMode::NONE);
+    configure(&mut connector)?;
+
+    let stream = TcpStream::connect_timeout(&addr, Duration::from_secs(target.connect_timeout.unwrap_or_else(default_certificate_check_timeout)))?;
+    let handshake_timeout = Duration::from_secs(target.handshake_timeout.unwrap_or_else(default_certificate_check_timeout));
+    stream.set_read_timeout(Some(handshake_timeout))?;
+    stream.set_write_timeout(Some(handshake_timeout))?;
+    if target.starttls {
+        smtp_starttls(&stream)?;
+    }
+    Ok(connector.build().connect(&target.domain, stream)?)
+}
+
+pub(crate) fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/**
+ * Upgrades a plain SMTP connection as described in RFC 3207
+ */
+fn smtp_starttls(stream: &TcpStream) -> Result<(), Error> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    read_smtp_response(&mut reader, "220")?;
+    writer.write_all(b"EHLO network-journal\r\n")?;
+    read_smtp_response(&mut reader, "250")?;
+    writer.write_all(b"STARTTLS\r\n")?;
+    read_smtp_response(&mut reader, "220")
+}
+
+fn read_smtp_response(reader: &mut impl BufRead, expected_code: &str) -> Result<(), Error> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(Error::StartTlsError("connection closed by server".to_string()));
+        }
+        if !line.starts_with(expected_code) {
+            return Err(Error::StartTlsError(format!("unexpected response \"{}\"", line.trim_end())));
+        }
+        // multiline responses use a hyphen instead of a space after the status code
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -217,6 +473,9 @@ pub enum Error {
     ShutdownError(ssl::Error),
     ParseError(chrono::ParseError),
     HttpError(reqwest::Error),
+    StartTlsError(String),
+    CrlError(String),
+    OcspError(String),
 }
 
 impl From<ErrorStack> for Error {
@@ -264,17 +523,46 @@ impl Display for Error {
             Self::ShutdownError(e) => write!(f, "{}", e),
             Self::ParseError(e) => write!(f, "{}", e),
             Self::HttpError(e) => write!(f, "{}", e),
+            Self::StartTlsError(e) => write!(f, "STARTTLS failed: {}", e),
+            Self::CrlError(e) => write!(f, "CRL check failed: {}", e),
+            Self::OcspError(e) => write!(f, "OCSP check failed: {}", e),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use openssl::{asn1::Asn1Time, bn::BigNum, pkey::PKey, rsa::Rsa, x509::{extension::SubjectAlternativeName, X509Builder}};
+
+    use crate::{config::DnsConfig, reports::tls_cert_validity::test_certs::create_cert};
+
     use super::*;
 
+    fn target(domain: &str) -> CertificateCheckConfig {
+        CertificateCheckConfig {
+            domain: domain.to_string(),
+            port: 443,
+            starttls: false,
+            dane: false,
+            tls_configuration: false,
+            caa: false,
+            interval: None,
+            schedule: None,
+            connect_timeout: None,
+            handshake_timeout: None,
+            warning_days: None,
+            critical_days: None
+        }
+    }
+
+    fn create(domain: &str) -> Result<Option<TLSCertificateValidityReport>, Error> {
+        let mut results = TLSCertificateValidityReport::create(&target(domain), &Resolver::new(&DnsConfig::default()).unwrap(), &RevocationCache::new(&std::env::temp_dir().join("network-journal-test")))?;
+        results.remove(0).1
+    }
+
     #[test]
     fn check_expired() {
-        let rpt_res = TLSCertificateValidityReport::create("expired.badssl.com", 443);
+        let rpt_res = create("expired.badssl.com");
         assert!(rpt_res.is_ok(), "{:?}", rpt_res.err());
 
         let rpt_opt = rpt_res.unwrap();
@@ -284,11 +572,12 @@ mod tests {
         assert!(rpt.expired);
         assert!(rpt.expires_in_days < 0);
         assert!(!rpt.revoked);
+        assert_eq!(rpt.severity, Severity::Critical);
     }
 
     #[test]
     fn check_revoked() {
-        let rpt_res = TLSCertificateValidityReport::create("revoked.badssl.com", 443);
+        let rpt_res = create("revoked.badssl.com");
         assert!(rpt_res.is_ok(), "{:?}", rpt_res.err());
 
         let rpt_opt = rpt_res.unwrap();
@@ -304,7 +593,7 @@ mod tests {
 
     #[test]
     fn check_self_signed() {
-        let rpt_res = TLSCertificateValidityReport::create("self-signed.badssl.com", 443);
+        let rpt_res = create("self-signed.badssl.com");
         assert!(rpt_res.is_ok(), "{:?}", rpt_res.err());
 
         let rpt_opt = rpt_res.unwrap();
@@ -315,4 +604,106 @@ mod tests {
         assert!(rpt.expires_in_days > 0);
         assert!(!rpt.revoked);
     }
+
+    #[test]
+    fn check_files() {
+        let (cert, key) = create_cert("client.example.com", 1, None);
+
+        let dir = std::env::temp_dir().join(format!("network-journal-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("client.pem"), cert.to_pem().unwrap()).unwrap();
+        fs::write(dir.join("client.der"), cert.to_der().unwrap()).unwrap();
+        fs::write(dir.join("client.key.pem"), key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+
+        let config = CertificateChecksConfig {
+            files: vec![dir.clone()],
+            ..Default::default()
+        };
+        let results = TLSCertificateValidityReport::create_for_files(&config, None, &RevocationCache::new(&dir));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(results.len(), 2);
+        for (path, res) in results {
+            let rpt = res.unwrap().unwrap();
+            assert_eq!(rpt.path, Some(path));
+            assert_eq!(rpt.certificate.subject.common_name.as_deref(), Some("client.example.com"));
+            assert!(!rpt.expired);
+            assert!(!rpt.revoked);
+            // expires within the default warning_days of 30
+            assert_eq!(rpt.severity, Severity::Warning);
+        }
+    }
+
+    #[test]
+    fn certificate_without_common_name() {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_serial_number(&BigNum::from_u32(7).unwrap().to_asn1_integer().unwrap()).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(90).unwrap()).unwrap();
+        let san = SubjectAlternativeName::new().dns("san-only.example.com").build(&builder.x509v3_context(None, None)).unwrap();
+        builder.append_extension(san).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+
+        let info = CertificateInfo::extract_info_from_cert(&builder.build()).unwrap();
+        assert_eq!(info.subject.common_name, None);
+        assert_eq!(info.issuer.common_name, None);
+        assert_eq!(info.get_name(), "san-only.example.com");
+        assert!(serde_json::to_value(&info.subject).unwrap().get("common_name").is_none());
+    }
+
+    #[test]
+    fn compare_addresses() {
+        let report = |ip: &str, cert: &X509| {
+            let info = CertificateInfo::extract_info_from_cert(cert).unwrap();
+            TLSCertificateValidityReport {
+                severity: Severity::Ok,
+                address: ip.parse().ok(),
+                path: None,
+                expired: info.is_expired(),
+                expires_in_days: 90,
+                certificate: info,
+                revoked: false,
+                revoked_since: None,
+                revocation_status: None,
+                dane: None,
+                differing_addresses: vec![]
+            }
+        };
+        let (cert_a, _) = create_cert("www.example.com", 1, None);
+        let (cert_b, _) = create_cert("www.example.com", 2, None);
+        let ips: Vec<IpAddr> = ["192.0.2.1", "192.0.2.2", "2001:db8::1", "2001:db8::2"].iter().map(|ip| ip.parse().unwrap()).collect();
+
+        let mut results: Vec<AddressCheckResult> = vec![
+            (ips[0], Ok(Some(report("192.0.2.1", &cert_a)))),
+            (ips[1], Ok(Some(report("192.0.2.2", &cert_a)))),
+            (ips[2], Ok(Some(report("2001:db8::1", &cert_b)))),
+            // failed addresses are not compared
+            (ips[3], Err(Error::StartTlsError("connection closed".to_string())))
+        ];
+        TLSCertificateValidityReport::compare_addresses(&target("www.example.com"), &mut results);
+
+        let differing: Vec<_> = results.iter()
+            .map(|(_, res)| res.as_ref().ok().and_then(Option::as_ref).map(|rpt| (rpt.differing_addresses.clone(), rpt.severity)))
+            .collect();
+        assert_eq!(differing, vec![
+            Some((vec![ips[2]], Severity::Warning)),
+            Some((vec![ips[2]], Severity::Warning)),
+            Some((vec![ips[0], ips[1]], Severity::Warning)),
+            None
+        ]);
+
+        let mut results: Vec<AddressCheckResult> = vec![
+            (ips[0], Ok(Some(report("192.0.2.1", &cert_a)))),
+            (ips[1], Ok(Some(report("192.0.2.2", &cert_a))))
+        ];
+        TLSCertificateValidityReport::compare_addresses(&target("www.example.com"), &mut results);
+        for (_, res) in results {
+            let rpt = res.unwrap().unwrap();
+            assert!(rpt.differing_addresses.is_empty());
+            assert_eq!(rpt.severity, Severity::Ok);
+        }
+    }
 }
@@ -0,0 +1,231 @@
+/**
+ * network-journal - collect network reports and print them to file
+ * Copyright (C) 2025 nerou GmbH
+ * 
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use hickory_proto::rr::{rdata::{tlsa::{CertUsage, Matching, Selector}, TLSA}, RData, RecordType};
+use openssl::{hash::{hash, MessageDigest}, x509::{X509Ref, X509}};
+use serde::Serialize;
+
+use crate::{dns::{self, Resolver}, reports::tls_cert_validity::to_hex};
+
+#[derive(Serialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DaneResult {
+    /// at least one TLSA record matches the presented chain
+    Valid,
+    /// TLSA records are published, but none of them matches the presented chain
+    Mismatch,
+    /// TLSA records are published, but the resolver did not validate them using DNSSEC, so clients will ignore them
+    Insecure,
+    NoRecords
+}
+
+#[derive(Serialize, Debug)]
+pub struct TLSARecordStatus {
+    pub usage: u8,
+    pub selector: u8,
+    pub matching_type: u8,
+    pub association_data: String,
+    pub matched: bool,
+    /// position of the matching certificate in the presented chain, 0 is the end entity certificate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chain_depth: Option<usize>
+}
+
+#[derive(Serialize, Debug)]
+pub struct DaneStatus {
+    pub result: DaneResult,
+    pub dnssec_validated: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub records: Vec<TLSARecordStatus>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rollover_warnings: Vec<String>
+}
+
+/**
+ * TLSA records of one service as returned by the resolver
+ */
+#[derive(Debug)]
+pub struct TLSARecords {
+    records: Vec<TLSA>,
+    authenticated: bool
+}
+
+impl TLSARecords {
+
+    pub fn lookup(resolver: &Resolver, host: &str, port: u16) -> Result<Self, dns::Error> {
+        let answer = resolver.query(&format!("_{}._tcp.{}.", port, host.trim_end_matches('.')), RecordType::TLSA)?;
+        Ok(Self {
+            records: answer.records.into_iter()
+                .filter_map(|rdata| match rdata {
+                    RData::TLSA(tlsa) => Some(tlsa),
+                    _ => None
+                })
+                .collect(),
+            authenticated: answer.authenticated
+        })
+    }
+
+    /**
+     * `chain` is expected to start with the end entity certificate
+     */
+    pub fn evaluate(&self, chain: &[X509]) -> DaneStatus {
+        DaneStatus::evaluate(&self.records, self.authenticated, chain)
+    }
+}
+
+impl DaneStatus {
+
+    fn evaluate(records: &[TLSA], authenticated: bool, chain: &[X509]) -> Self {
+        if records.is_empty() {
+            return Self {
+                result: DaneResult::NoRecords,
+                dnssec_validated: authenticated,
+                records: vec![],
+                rollover_warnings: vec![]
+            };
+        }
+
+        let statuses = records.iter().map(|record| {
+            let chain_depth = chain.iter().enumerate()
+                .filter(|(depth, _)| match record.cert_usage() {
+                    CertUsage::CA | CertUsage::TrustAnchor => *depth > 0,
+                    CertUsage::Service | CertUsage::DomainIssued => *depth == 0,
+                    _ => false
+                })
+                .find(|(_, cert)| Self::matches(record, cert))
+                .map(|(depth, _)| depth);
+            TLSARecordStatus {
+                usage: record.cert_usage().into(),
+                selector: record.selector().into(),
+                matching_type: record.matching().into(),
+                association_data: to_hex(record.cert_data()),
+                matched: chain_depth.is_some(),
+                chain_depth
+            }
+        }).collect::<Vec<_>>();
+
+        let result = if !authenticated {
+            DaneResult::Insecure
+        } else if statuses.iter().any(|s| s.matched) {
+            DaneResult::Valid
+        } else {
+            DaneResult::Mismatch
+        };
+        let rollover_warnings = Self::check_rollover(records, &statuses);
+
+        Self {
+            result,
+            dnssec_validated: authenticated,
+            records: statuses,
+            rollover_warnings
+        }
+    }
+
+    fn matches(record: &TLSA, cert: &X509Ref) -> bool {
+        let data = match record.selector() {
+            Selector::Full => cert.to_der(),
+            Selector::Spki => cert.public_key().and_then(|key| key.public_key_to_der()),
+            _ => return false
+        };
+        let Ok(data) = data else {
+            return false;
+        };
+        match record.matching() {
+            Matching::Raw => data == record.cert_data(),
+            Matching::Sha256 => hash(MessageDigest::sha256(), &data).is_ok_and(|digest| *digest == *record.cert_data()),
+            Matching::Sha512 => hash(MessageDigest::sha512(), &data).is_ok_and(|digest| *digest == *record.cert_data()),
+            _ => false
+        }
+    }
+
+    /**
+     * Detects setups that only work for the currently presented chain (see RFC 7671, section 8.1)
+     */
+    fn check_rollover(records: &[TLSA], statuses: &[TLSARecordStatus]) -> Vec<String> {
+        let is_end_entity = |record: &TLSA| matches!(record.cert_usage(), CertUsage::Service | CertUsage::DomainIssued);
+        let matched = records.iter().zip(statuses).filter(|(_, s)| s.matched).map(|(r, _)| r).collect::<Vec<_>>();
+        if matched.is_empty() || matched.iter().any(|r| !is_end_entity(r)) {
+            // nothing to roll over or the trust anchor survives end entity certificate renewals
+            return vec![];
+        }
+
+        let mut warnings = vec![];
+        if matched.iter().all(|r| r.selector() == Selector::Full) {
+            warnings.push("all matching TLSA records pin the full end entity certificate, which changes with every renewal even if the key is reused".to_string());
+        }
+        let prepublished = records.iter().zip(statuses).any(|(r, s)| !s.matched && is_end_entity(r));
+        if !prepublished {
+            warnings.push("no TLSA record for the next end entity key or certificate is published, a rollover will break DANE until the records are updated".to_string());
+        }
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::hash::MessageDigest;
+
+    use crate::reports::tls_cert_validity::test_certs::create_cert;
+
+    use super::*;
+
+    fn spki_sha256(cert: &X509) -> Vec<u8> {
+        hash(MessageDigest::sha256(), &cert.public_key().unwrap().public_key_to_der().unwrap()).unwrap().to_vec()
+    }
+
+    #[test]
+    fn match_dane_ee_spki() {
+        let cert = create_cert("mx.example.com", 1, None).0;
+        let next = create_cert("mx.example.com", 1, None).0;
+        let records = vec![
+            TLSA::new(CertUsage::DomainIssued, Selector::Spki, Matching::Sha256, spki_sha256(&cert)),
+            TLSA::new(CertUsage::DomainIssued, Selector::Spki, Matching::Sha256, spki_sha256(&next))
+        ];
+        let status = DaneStatus::evaluate(&records, true, &[cert]);
+        assert_eq!(status.result, DaneResult::Valid);
+        assert!(status.records[0].matched);
+        assert_eq!(status.records[0].chain_depth, Some(0));
+        assert!(!status.records[1].matched);
+        assert!(status.rollover_warnings.is_empty());
+    }
+
+    #[test]
+    fn mismatch_and_insecure() {
+        let cert = create_cert("mx.example.com", 1, None).0;
+        let other = create_cert("mx.example.com", 1, None).0;
+        let records = vec![TLSA::new(CertUsage::DomainIssued, Selector::Spki, Matching::Sha256, spki_sha256(&other))];
+        assert_eq!(DaneStatus::evaluate(&records, true, std::slice::from_ref(&cert)).result, DaneResult::Mismatch);
+        assert_eq!(DaneStatus::evaluate(&records, false, &[cert]).result, DaneResult::Insecure);
+    }
+
+    #[test]
+    fn warn_about_full_certificate_pin() {
+        let cert = create_cert("mx.example.com", 1, None).0;
+        let digest = hash(MessageDigest::sha256(), &cert.to_der().unwrap()).unwrap().to_vec();
+        let records = vec![TLSA::new(CertUsage::DomainIssued, Selector::Full, Matching::Sha256, digest)];
+        let status = DaneStatus::evaluate(&records, true, &[cert]);
+        assert_eq!(status.result, DaneResult::Valid);
+        assert_eq!(status.rollover_warnings.len(), 2);
+    }
+
+    #[test]
+    fn no_records() {
+        let status = DaneStatus::evaluate(&[], true, &[create_cert("mx.example.com", 1, None).0]);
+        assert_eq!(status.result, DaneResult::NoRecords);
+    }
+}
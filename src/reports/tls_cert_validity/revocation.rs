@@ -0,0 +1,543 @@
+/**
+ * network-journal - collect network reports and print them to file
+ * Copyright (C) 2025 nerou GmbH
+ * 
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{fs, io, path::{Path, PathBuf}, process, sync::atomic::{AtomicUsize, Ordering}};
+
+use chrono::{DateTime, FixedOffset, Utc};
+use log::{error, trace, warn};
+use openssl::{hash::{hash, MessageDigest}, ocsp::{OcspCertId, OcspCertStatus, OcspFlag, OcspRequest, OcspResponse, OcspResponseStatus}, stack::Stack, x509::{store::X509StoreBuilder, verify::X509VerifyFlags, CrlStatus, X509Crl, X509}};
+use reqwest::{blocking::Client, header::{CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED}, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::reports::tls_cert_validity::{to_hex, CertificateInfo, Error};
+
+const CRL_CACHE_DIR: &str = "crl-cache";
+const OCSP_CACHE_DIR: &str = "ocsp-cache";
+/// tolerated clock skew regarding thisUpdate and nextUpdate of OCSP responses
+const OCSP_MAX_SKEW_SECS: u32 = 300;
+
+/// distinguishes the temporary files of concurrent writers
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/**
+ * Revocation status of a certificate, ordered by the information it carries.
+ * The most informative status of all CRLs and OCSP responders of a certificate is reported.
+ */
+#[derive(Serialize, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RevocationStatus {
+    /// none of the CRLs or OCSP responders could be queried
+    Unknown,
+    /// the issuer certificate is unknown, so the signature of CRLs and OCSP responses cannot be verified
+    Unverified,
+    /// neither listed in a verified CRL nor reported as revoked in a verified OCSP response
+    Good,
+    Revoked
+}
+
+#[derive(PartialEq, Debug)]
+pub struct Revocation {
+    pub status: RevocationStatus,
+    pub revoked_since: Option<DateTime<FixedOffset>>
+}
+
+impl From<RevocationStatus> for Revocation {
+    fn from(status: RevocationStatus) -> Self {
+        Self {
+            status,
+            revoked_since: None
+        }
+    }
+}
+
+/// metadata of a cached CRL, the CRL itself is stored DER encoded next to it
+#[derive(Serialize, Deserialize, Default, Debug)]
+struct CacheEntry {
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_modified: Option<String>,
+    /// RFC 3339 formatted nextUpdate of the CRL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_update: Option<String>
+}
+
+/**
+ * On-disk cache of CRLs keyed by their distribution point URL and of OCSP responses keyed by the certificate ID.
+ * A cached CRL is used without any request until its nextUpdate, afterwards it is revalidated using ETag and Last-Modified.
+ * OCSP responses are used until their nextUpdate, responses without nextUpdate are not cached.
+ */
+pub struct RevocationCache {
+    dir: PathBuf
+}
+
+impl RevocationCache {
+
+    pub fn new(state_dir: &Path) -> Self {
+        Self {
+            dir: state_dir.to_path_buf()
+        }
+    }
+
+    /**
+     * Queries the CRL distribution points and OCSP responders of the certificate, none if it has neither of them.
+     * CRLs and OCSP responses are only considered if they have been signed by the given issuer (or a responder delegated by it).
+     * Without issuer certificate, the signature cannot be verified, so they are not retrieved at all.
+     * Failures are logged and result in `RevocationStatus::Unknown`, so that they do not prevent the remaining checks.
+     */
+    pub fn check(&self, cert: &X509, info: &CertificateInfo, issuer: Option<&X509>) -> Option<Revocation> {
+        if info.crl_distribution_urls.is_empty() && info.ocsp_urls.is_empty() {
+            return None;
+        }
+        let Some(issuer) = issuer else {
+            warn!("revocation status of {} could not be verified, its issuer certificate is unknown", info.get_name());
+            return Some(RevocationStatus::Unverified.into());
+        };
+
+        let crl_results = info.crl_distribution_urls.iter().map(|url| (url, self.check_crl(url, cert, issuer)));
+        let ocsp_results = info.ocsp_urls.iter().map(|url| (url, self.check_ocsp(url, cert, issuer)));
+        let mut revocation = Revocation::from(RevocationStatus::Unknown);
+        for (url, result) in crl_results.chain(ocsp_results) {
+            match result {
+                Ok(res) if res.status > revocation.status => revocation = res,
+                Ok(_) => (),
+                Err(err) => error!("revocation check of {} using {} failed: {}", info.get_name(), url, err)
+            }
+            if revocation.status == RevocationStatus::Revoked {
+                break;
+            }
+        }
+        Some(revocation)
+    }
+
+    fn check_crl(&self, url: &str, cert: &X509, issuer: &X509) -> Result<Revocation, Error> {
+        let crl = self.get_crl(url)?;
+        if crl.issuer_name().to_der()? != cert.issuer_name().to_der()? {
+            return Err(Error::CrlError(format!("{} has not been issued by the issuer of the certificate", url)));
+        }
+        if !crl.verify(issuer.public_key()?.as_ref())? {
+            return Err(Error::CrlError(format!("{} has an invalid signature", url)));
+        }
+        Ok(match crl.get_by_cert(cert) {
+            CrlStatus::Revoked(rev) => Revocation {
+                status: RevocationStatus::Revoked,
+                revoked_since: Some(CertificateInfo::asn1_date_to_chrono(rev.revocation_date())?)
+            },
+            _ => RevocationStatus::Good.into()
+        })
+    }
+
+    fn get_crl(&self, url: &str) -> Result<X509Crl, Error> {
+        let dir = self.dir.join(CRL_CACHE_DIR);
+        let key = to_hex(&hash(MessageDigest::sha256(), url.as_bytes())?);
+        let crl_path = dir.join(format!("{}.crl", key));
+        let entry_path = dir.join(format!("{}.json", key));
+
+        let entry = fs::read_to_string(&entry_path).ok()
+            .and_then(|content| serde_json::from_str::<CacheEntry>(&content).ok())
+            .filter(|entry| entry.url == url);
+        let cached = entry.as_ref()
+            .and_then(|_| fs::read(&crl_path).ok())
+            .and_then(|der| X509Crl::from_der(&der).ok());
+
+        let next_update = entry.as_ref()
+            .and_then(|e| e.next_update.as_ref())
+            .and_then(|d| DateTime::parse_from_rfc3339(d).ok());
+        if next_update.is_some_and(|d| d > Utc::now()) {
+            if let Some(crl) = cached {
+                trace!("using cached CRL {}", url);
+                return Ok(crl);
+            }
+        }
+
+        // a blocking client must not be created within an async context, so it is not created upfront
+        let mut request = Client::new().get(url);
+        if cached.is_some() {
+            if let Some(etag) = entry.as_ref().and_then(|e| e.etag.as_ref()) {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = entry.as_ref().and_then(|e| e.last_modified.as_ref()) {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+        let response = match (request.send(), cached) {
+            (Ok(response), Some(crl)) if response.status() == StatusCode::NOT_MODIFIED => {
+                trace!("cached CRL {} not modified", url);
+                return Ok(crl);
+            },
+            (Ok(response), _) if response.status().is_success() => response,
+            (Ok(response), Some(crl)) => {
+                warn!("failed to update CRL {}, got status code {}, using cached one", url, response.status());
+                return Ok(crl);
+            },
+            (Ok(response), None) => return Err(Error::CrlError(format!("got status code {} for {}", response.status(), url))),
+            (Err(err), Some(crl)) => {
+                warn!("failed to update CRL {}: {}, using cached one", url, err);
+                return Ok(crl);
+            },
+            (Err(err), None) => return Err(err.into())
+        };
+
+        let header = |name| response.headers().get(name).and_then(|v| v.to_str().ok()).map(|v| v.to_string());
+        let mut entry = CacheEntry {
+            url: url.to_string(),
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            next_update: None
+        };
+        let crl = parse_crl(&response.bytes()?)?;
+        if let Some(next_update) = crl.next_update() {
+            entry.next_update = Some(CertificateInfo::asn1_date_to_chrono(next_update)?.to_rfc3339());
+        }
+        let stored = store(&crl_path, &crl.to_der()?)
+            .and_then(|_| store(&entry_path, serde_json::to_string_pretty(&entry)?.as_bytes()));
+        if let Err(err) = stored {
+            error!("failed to cache CRL {}: {}", url, err);
+        }
+        Ok(crl)
+    }
+
+    fn check_ocsp(&self, url: &str, cert: &X509, issuer: &X509) -> Result<Revocation, Error> {
+        let path = self.dir.join(OCSP_CACHE_DIR).join(format!("{}.der", ocsp_cache_key(cert, issuer)?));
+        let cached = fs::read(&path).ok()
+            .and_then(|der| evaluate_ocsp_response(&der, cert, issuer).ok())
+            .filter(|(_, next_update)| next_update.is_some_and(|d| d > Utc::now()));
+        if let Some((revocation, _)) = cached {
+            trace!("using cached OCSP response of {} for serial {}", url, cert.serial_number().to_bn()?.to_hex_str()?);
+            return Ok(revocation);
+        }
+
+        let mut request = OcspRequest::new()?;
+        request.add_id(OcspCertId::from_cert(MessageDigest::sha1(), cert, issuer)?)?;
+        let response = Client::new().post(url)
+            .header(CONTENT_TYPE, "application/ocsp-request")
+            .body(request.to_der()?)
+            .send()?;
+        if !response.status().is_success() {
+            return Err(Error::OcspError(format!("got status code {} for {}", response.status(), url)));
+        }
+        let der = response.bytes()?;
+        let (revocation, next_update) = evaluate_ocsp_response(&der, cert, issuer)?;
+        if next_update.is_some() {
+            if let Err(err) = store(&path, &der) {
+                error!("failed to cache OCSP response of {}: {}", url, err);
+            }
+        }
+        Ok(revocation)
+    }
+}
+
+/**
+ * Writes to a temporary file unique to the writer first, so that neither a failure nor a concurrent writer leaves a corrupt cache entry
+ */
+fn store(path: &Path, data: &[u8]) -> Result<(), io::Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(format!(".{}-{}.tmp", process::id(), TMP_COUNTER.fetch_add(1, Ordering::Relaxed)));
+    let tmp_path = path.with_file_name(tmp_name);
+    fs::write(&tmp_path, data)?;
+    if let Err(err) = fs::rename(&tmp_path, path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(err);
+    }
+    Ok(())
+}
+
+/**
+ * CRLs are served PEM or DER encoded with all kinds of MIME types, so the content decides
+ */
+fn parse_crl(data: &[u8]) -> Result<X509Crl, Error> {
+    if data.windows(10).any(|w| w == b"-----BEGIN") {
+        Ok(X509Crl::from_pem(data)?)
+    } else {
+        Ok(X509Crl::from_der(data)?)
+    }
+}
+
+/**
+ * Equivalent to the OCSP certificate ID: hashes of the issuer's name and key as well as the serial number
+ */
+fn ocsp_cache_key(cert: &X509, issuer: &X509) -> Result<String, Error> {
+    let mut data = issuer.subject_name().to_der()?;
+    data.extend(issuer.public_key()?.public_key_to_der()?);
+    data.extend(cert.serial_number().to_bn()?.to_vec());
+    Ok(to_hex(&hash(MessageDigest::sha256(), &data)?))
+}
+
+/**
+ * Verifies that the response has been signed by the issuer or a responder delegated by it and returns the status of the certificate.
+ * The nextUpdate of the response is returned as well, to decide how long it may be cached.
+ */
+fn evaluate_ocsp_response(der: &[u8], cert: &X509, issuer: &X509) -> Result<(Revocation, Option<DateTime<FixedOffset>>), Error> {
+    let response = OcspResponse::from_der(der)?;
+    if response.status() != OcspResponseStatus::SUCCESSFUL {
+        return Err(Error::OcspError(format!("unsuccessful response status {}", response.status().as_raw())));
+    }
+    let basic = response.basic()?;
+
+    // a delegated responder's certificate is verified up to the issuer, which is trusted even if it is an intermediate
+    let mut certs = Stack::new()?;
+    certs.push(issuer.clone())?;
+    let mut store = X509StoreBuilder::new()?;
+    store.add_cert(issuer.clone())?;
+    store.set_flags(X509VerifyFlags::PARTIAL_CHAIN)?;
+    basic.verify(&certs, &store.build(), OcspFlag::TRUST_OTHER)?;
+
+    let cert_id = OcspCertId::from_cert(MessageDigest::sha1(), cert, issuer)?;
+    let Some(status) = basic.find_status(&cert_id) else {
+        return Err(Error::OcspError("response does not contain the certificate".to_string()));
+    };
+    status.check_validity(OCSP_MAX_SKEW_SECS, None)?;
+    let next_update = status.next_update().map(CertificateInfo::asn1_date_to_chrono).transpose()?;
+    let revocation = match status.status {
+        OcspCertStatus::GOOD => RevocationStatus::Good.into(),
+        OcspCertStatus::REVOKED => Revocation {
+            status: RevocationStatus::Revoked,
+            revoked_since: status.revocation_time.map(CertificateInfo::asn1_date_to_chrono).transpose()?
+        },
+        _ => RevocationStatus::Unknown.into()
+    };
+    Ok((revocation, next_update))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::{BufRead, BufReader, Read, Write}, net::TcpListener, sync::Arc, thread};
+
+    use chrono::Duration;
+    use openssl::{asn1::Asn1Time, bn::BigNum, pkey::{PKey, Private}, sign::Signer, x509::{extension::AuthorityKeyIdentifier, CrlNumber, X509Builder, X509CrlBuilder, X509RevokedBuilder}};
+
+    use crate::reports::tls_cert_validity::test_certs::create_cert;
+
+    use super::*;
+
+    const CRL_URL: &str = "http://crl.example.invalid/ca.crl";
+
+    fn create_crl(ca: &X509, ca_key: &PKey<Private>, revoked_serial: u32) -> X509Crl {
+        let mut revoked = X509RevokedBuilder::new().unwrap();
+        revoked.set_serial_number(&BigNum::from_u32(revoked_serial).unwrap().to_asn1_integer().unwrap()).unwrap();
+        revoked.set_revocation_date(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+
+        let mut builder = X509CrlBuilder::new().unwrap();
+        builder.set_issuer_name(ca.subject_name()).unwrap();
+        builder.set_last_update(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_next_update(&Asn1Time::days_from_now(7).unwrap()).unwrap();
+        builder.add_revoked(revoked.build()).unwrap();
+        let dummy = X509Builder::new().unwrap();
+        builder.append_extension(AuthorityKeyIdentifier::new().issuer(true).build(&dummy.x509v3_context(Some(ca), None)).unwrap()).unwrap();
+        builder.append_extension(CrlNumber::new(BigNum::from_u32(1).unwrap()).unwrap().build().unwrap()).unwrap();
+        builder.sign(ca_key, MessageDigest::sha256()).unwrap();
+        builder.build().unwrap()
+    }
+
+    fn create_cache(name: &str) -> RevocationCache {
+        RevocationCache::new(&std::env::temp_dir().join(format!("network-journal-{}-{}", name, process::id())))
+    }
+
+    /**
+     * Places the CRL in the cache, so that no request is necessary until its nextUpdate
+     */
+    fn cache_crl(cache: &RevocationCache, crl: &X509Crl) {
+        let key = to_hex(&hash(MessageDigest::sha256(), CRL_URL.as_bytes()).unwrap());
+        let entry = CacheEntry {
+            url: CRL_URL.to_string(),
+            next_update: Some(CertificateInfo::asn1_date_to_chrono(crl.next_update().unwrap()).unwrap().to_rfc3339()),
+            ..Default::default()
+        };
+        let dir = cache.dir.join(CRL_CACHE_DIR);
+        store(&dir.join(format!("{}.crl", key)), &crl.to_der().unwrap()).unwrap();
+        store(&dir.join(format!("{}.json", key)), serde_json::to_string(&entry).unwrap().as_bytes()).unwrap();
+    }
+
+    fn info(cert: &X509, crl_url: Option<&str>, ocsp_url: Option<&str>) -> CertificateInfo {
+        let mut info = CertificateInfo::extract_info_from_cert(cert).unwrap();
+        info.crl_distribution_urls.extend(crl_url.map(|url| url.to_string()));
+        info.ocsp_urls.extend(ocsp_url.map(|url| url.to_string()));
+        info
+    }
+
+    fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag];
+        match content.len() {
+            len if len < 0x80 => out.push(len as u8),
+            len if len < 0x100 => out.extend([0x81, len as u8]),
+            len => out.extend([0x82, (len >> 8) as u8, len as u8])
+        }
+        out.extend_from_slice(content);
+        out
+    }
+
+    fn generalized_time(time: DateTime<Utc>) -> Vec<u8> {
+        der(0x18, time.format("%Y%m%d%H%M%SZ").to_string().as_bytes())
+    }
+
+    /**
+     * DER encoded OCSP response about the certificate issued by `ca`, signed by `signer_key` on behalf of `ca`
+     */
+    fn create_ocsp_response(cert: &X509, ca: &X509, signer_key: &PKey<Private>, revoked_at: Option<DateTime<Utc>>) -> Vec<u8> {
+        let sha1 = |data: &[u8]| hash(MessageDigest::sha1(), data).unwrap().to_vec();
+        let cert_id = der(0x30, &[
+            der(0x30, &[der(0x06, &[0x2b, 0x0e, 0x03, 0x02, 0x1a]), vec![0x05, 0x00]].concat()),
+            der(0x04, &sha1(&ca.subject_name().to_der().unwrap())),
+            der(0x04, &sha1(&ca.public_key().unwrap().rsa().unwrap().public_key_to_der_pkcs1().unwrap())),
+            der(0x02, &cert.serial_number().to_bn().unwrap().to_vec())
+        ].concat());
+        let cert_status = revoked_at.map_or(vec![0x80, 0x00], |time| der(0xa1, &generalized_time(time)));
+        let single_response = der(0x30, &[
+            cert_id,
+            cert_status,
+            generalized_time(Utc::now() - Duration::hours(1)),
+            der(0xa0, &generalized_time(Utc::now() + Duration::days(7)))
+        ].concat());
+        let tbs = der(0x30, &[
+            der(0xa1, &ca.subject_name().to_der().unwrap()),
+            generalized_time(Utc::now()),
+            der(0x30, &single_response)
+        ].concat());
+
+        let mut signer = Signer::new(MessageDigest::sha256(), signer_key).unwrap();
+        signer.update(&tbs).unwrap();
+        let signature = [vec![0x00], signer.sign_to_vec().unwrap()].concat();
+        let basic = der(0x30, &[
+            tbs,
+            der(0x30, &[der(0x06, &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b]), vec![0x05, 0x00]].concat()),
+            der(0x03, &signature)
+        ].concat());
+        der(0x30, &[
+            der(0x0a, &[0x00]),
+            der(0xa0, &der(0x30, &[
+                der(0x06, &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01]),
+                der(0x04, &basic)
+            ].concat()))
+        ].concat())
+    }
+
+    /**
+     * Answers every request with the given body until the test ends, counting the requests
+     */
+    fn spawn_ocsp_responder(body: Vec<u8>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/ocsp", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().map_while(Result::ok) {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut content_length = 0;
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().unwrap();
+                        }
+                    }
+                    line.clear();
+                }
+                reader.read_exact(&mut vec![0; content_length]).unwrap();
+                write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/ocsp-response\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len()).unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    #[test]
+    fn parse_pem_and_der() {
+        let (ca, ca_key) = create_cert("Example CA", 1, None);
+        let crl = create_crl(&ca, &ca_key, 2);
+        assert_eq!(parse_crl(&crl.to_pem().unwrap()).unwrap().to_der().unwrap(), crl.to_der().unwrap());
+        assert_eq!(parse_crl(&crl.to_der().unwrap()).unwrap().to_der().unwrap(), crl.to_der().unwrap());
+        assert!(parse_crl(b"<html></html>").is_err());
+    }
+
+    #[test]
+    fn check_revoked_from_cache() {
+        let (ca, ca_key) = create_cert("Example CA", 1, None);
+        let (cert, _) = create_cert("example.com", 2, Some((&ca, &ca_key)));
+        let (valid_cert, _) = create_cert("example.org", 3, Some((&ca, &ca_key)));
+        let cache = create_cache("crl-revoked");
+        cache_crl(&cache, &create_crl(&ca, &ca_key, 2));
+
+        let revocation = cache.check(&cert, &info(&cert, Some(CRL_URL), None), Some(&ca)).unwrap();
+        assert_eq!(revocation.status, RevocationStatus::Revoked);
+        assert!(revocation.revoked_since.is_some());
+        assert_eq!(cache.check(&valid_cert, &info(&valid_cert, Some(CRL_URL), None), Some(&ca)), Some(RevocationStatus::Good.into()));
+
+        // an unverifiable CRL does not decide anything
+        assert_eq!(cache.check(&cert, &info(&cert, Some(CRL_URL), None), None), Some(RevocationStatus::Unverified.into()));
+        assert_eq!(cache.check(&cert, &info(&cert, None, None), Some(&ca)), None);
+        fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[test]
+    fn ignore_forged_crl() {
+        let (ca, ca_key) = create_cert("Example CA", 1, None);
+        // same name, but another key
+        let (forged_ca, forged_key) = create_cert("Example CA", 1, None);
+        let (cert, _) = create_cert("example.com", 2, Some((&ca, &ca_key)));
+        let cache = create_cache("crl-forged");
+        cache_crl(&cache, &create_crl(&forged_ca, &forged_key, 2));
+
+        assert_eq!(cache.check(&cert, &info(&cert, Some(CRL_URL), None), Some(&ca)), Some(RevocationStatus::Unknown.into()));
+        fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[test]
+    fn unreachable_distribution_point() {
+        let (ca, ca_key) = create_cert("Example CA", 1, None);
+        let (cert, _) = create_cert("example.com", 2, Some((&ca, &ca_key)));
+        let cache = create_cache("crl-unreachable");
+
+        let info = info(&cert, Some("http://127.0.0.1:1/ca.crl"), Some("http://127.0.0.1:1/ocsp"));
+        assert_eq!(cache.check(&cert, &info, Some(&ca)), Some(RevocationStatus::Unknown.into()));
+    }
+
+    #[test]
+    fn check_ocsp() {
+        let (ca, ca_key) = create_cert("Example CA", 1, None);
+        let (cert, _) = create_cert("example.com", 2, Some((&ca, &ca_key)));
+        let (valid_cert, _) = create_cert("example.org", 3, Some((&ca, &ca_key)));
+        let (_, forged_key) = create_cert("Example CA", 1, None);
+        let cache = create_cache("ocsp");
+
+        let revoked_at = Utc::now() - Duration::days(1);
+        let (url, requests) = spawn_ocsp_responder(create_ocsp_response(&cert, &ca, &ca_key, Some(revoked_at)));
+        let revocation = cache.check(&cert, &info(&cert, None, Some(&url)), Some(&ca)).unwrap();
+        assert_eq!(revocation.status, RevocationStatus::Revoked);
+        assert_eq!(revocation.revoked_since.unwrap().timestamp(), revoked_at.timestamp());
+        // cached until nextUpdate
+        assert_eq!(cache.check(&cert, &info(&cert, None, Some(&url)), Some(&ca)).unwrap().status, RevocationStatus::Revoked);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        let (url, _) = spawn_ocsp_responder(create_ocsp_response(&valid_cert, &ca, &ca_key, None));
+        assert_eq!(cache.check(&valid_cert, &info(&valid_cert, None, Some(&url)), Some(&ca)), Some(RevocationStatus::Good.into()));
+        fs::remove_dir_all(&cache.dir).unwrap();
+
+        // the response is about another certificate
+        let other_cache = create_cache("ocsp-other");
+        assert_eq!(other_cache.check(&cert, &info(&cert, None, Some(&url)), Some(&ca)), Some(RevocationStatus::Unknown.into()));
+
+        let (url, _) = spawn_ocsp_responder(create_ocsp_response(&valid_cert, &ca, &forged_key, Some(revoked_at)));
+        assert_eq!(other_cache.check(&valid_cert, &info(&valid_cert, None, Some(&url)), Some(&ca)), Some(RevocationStatus::Unknown.into()));
+        assert!(!other_cache.dir.exists());
+    }
+}
@@ -0,0 +1,281 @@
+/**
+ * network-journal - collect network reports and print them to file
+ * Copyright (C) 2025 nerou GmbH
+ * 
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+
+use log::debug;
+use openssl::{nid::Nid, pkey::Id, ssl::{SslConnectorBuilder, SslVersion}};
+use serde::Serialize;
+
+use crate::{config::CertificateCheckConfig, reports::tls_cert_validity::{connect, Error}};
+
+/// probed protocol versions and their names
+const PROTOCOLS: &[(SslVersion, &str)] = &[
+    (SslVersion::TLS1, "TLSv1"),
+    (SslVersion::TLS1_1, "TLSv1.1"),
+    (SslVersion::TLS1_2, "TLSv1.2"),
+    (SslVersion::TLS1_3, "TLSv1.3")
+];
+
+/// OpenSSL names of probed cipher suites up to TLS 1.2, ciphers not supported by the local OpenSSL are skipped
+const CIPHERS: &[&str] = &[
+    "ECDHE-ECDSA-AES256-GCM-SHA384", "ECDHE-RSA-AES256-GCM-SHA384",
+    "ECDHE-ECDSA-CHACHA20-POLY1305", "ECDHE-RSA-CHACHA20-POLY1305",
+    "ECDHE-ECDSA-AES128-GCM-SHA256", "ECDHE-RSA-AES128-GCM-SHA256",
+    "ECDHE-ECDSA-AES256-SHA384", "ECDHE-RSA-AES256-SHA384",
+    "ECDHE-ECDSA-AES128-SHA256", "ECDHE-RSA-AES128-SHA256",
+    "ECDHE-ECDSA-AES256-SHA", "ECDHE-RSA-AES256-SHA",
+    "ECDHE-ECDSA-AES128-SHA", "ECDHE-RSA-AES128-SHA",
+    "DHE-RSA-AES256-GCM-SHA384", "DHE-RSA-AES128-GCM-SHA256", "DHE-RSA-CHACHA20-POLY1305",
+    "DHE-RSA-AES256-SHA256", "DHE-RSA-AES128-SHA256", "DHE-RSA-AES256-SHA", "DHE-RSA-AES128-SHA",
+    "AES256-GCM-SHA384", "AES128-GCM-SHA256", "AES256-SHA256", "AES128-SHA256", "AES256-SHA", "AES128-SHA",
+    "ECDHE-RSA-DES-CBC3-SHA", "DES-CBC3-SHA", "ECDHE-RSA-RC4-SHA", "RC4-SHA", "RC4-MD5",
+    "ADH-AES256-GCM-SHA384", "AECDH-AES256-SHA", "NULL-SHA256", "NULL-SHA"
+];
+
+/// names of probed TLS 1.3 cipher suites
+const CIPHERSUITES: &[&str] = &[
+    "TLS_AES_256_GCM_SHA384", "TLS_CHACHA20_POLY1305_SHA256", "TLS_AES_128_GCM_SHA256",
+    "TLS_AES_128_CCM_SHA256", "TLS_AES_128_CCM_8_SHA256"
+];
+
+/// allows probing of legacy protocol versions and ciphers disabled by default in recent OpenSSL versions
+const LEGACY_CIPHER_LIST: &str = "ALL:COMPLEMENTOFALL:@SECLEVEL=0";
+
+#[derive(Serialize, Debug)]
+pub struct NegotiatedParameters {
+    pub protocol: String,
+    pub cipher: String,
+    pub cipher_bits: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_exchange: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_exchange_bits: Option<u32>
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProtocolSupport {
+    pub protocol: String,
+    pub supported: bool
+}
+
+#[derive(Serialize, Debug)]
+pub struct PublicKeyInfo {
+    pub algorithm: String,
+    pub bits: u32
+}
+
+/// result of the probe of a single address of a target
+pub type AddressProbeResult = (IpAddr, Result<TLSConfigurationReport, Error>);
+
+#[derive(Serialize, Debug)]
+pub struct TLSConfigurationReport {
+    pub domain: String,
+    pub port: u16,
+    pub address: IpAddr,
+    /// parameters negotiated using the default settings of OpenSSL
+    pub negotiated: NegotiatedParameters,
+    pub protocols: Vec<ProtocolSupport>,
+    /// accepted cipher suites (OpenSSL names for TLS 1.2 and below)
+    pub cipher_suites: Vec<String>,
+    pub public_key: PublicKeyInfo,
+    pub signature_algorithm: String,
+    pub weaknesses: Vec<String>
+}
+
+impl TLSConfigurationReport {
+
+    /**
+     * Probes every resolved address of the target separately
+     */
+    pub fn create(target: &CertificateCheckConfig) -> Result<Vec<AddressProbeResult>, Error> {
+        let addresses = (target.domain.as_str(), target.port).to_socket_addrs()?;
+        Ok(addresses.map(|addr| (addr.ip(), Self::create_for_address(target, addr))).collect())
+    }
+
+    fn create_for_address(target: &CertificateCheckConfig, addr: SocketAddr) -> Result<Self, Error> {
+        let mut stream = connect(target, addr, |_| Ok(()))?;
+        let ssl = stream.ssl();
+        let tmp_key = ssl.peer_tmp_key().ok();
+        let negotiated = NegotiatedParameters {
+            protocol: ssl.version_str().to_string(),
+            cipher: ssl.current_cipher().map_or("none".to_string(), |c| c.name().to_string()),
+            cipher_bits: ssl.current_cipher().map_or(0, |c| c.bits().secret),
+            key_exchange: tmp_key.as_ref().map(|key| key_algorithm_name(key.id())),
+            key_exchange_bits: tmp_key.as_ref().map(|key| key.bits())
+        };
+        let (public_key, signature_algorithm) = match ssl.peer_certificate() {
+            Some(cert) => {
+                let key = cert.public_key()?;
+                (
+                    PublicKeyInfo { algorithm: key_algorithm_name(key.id()), bits: key.bits() },
+                    cert.signature_algorithm().object().to_string()
+                )
+            },
+            None => (PublicKeyInfo { algorithm: "none".to_string(), bits: 0 }, "none".to_string())
+        };
+        // some servers handle one connection at a time only
+        let _ = stream.shutdown();
+        drop(stream);
+
+        let protocols = PROTOCOLS.iter().map(|(version, name)| ProtocolSupport {
+            protocol: name.to_string(),
+            supported: Self::probe(target, addr, |builder| {
+                builder.set_min_proto_version(Some(*version))?;
+                builder.set_max_proto_version(Some(*version))?;
+                builder.set_cipher_list(LEGACY_CIPHER_LIST)
+            })
+        }).collect::<Vec<_>>();
+
+        let mut cipher_suites = vec![];
+        for cipher in CIPHERS {
+            let mut supported_locally = true;
+            let accepted = Self::probe(target, addr, |builder| {
+                builder.set_min_proto_version(Some(SslVersion::TLS1))?;
+                builder.set_max_proto_version(Some(SslVersion::TLS1_2))?;
+                builder.set_cipher_list(&format!("{}:@SECLEVEL=0", cipher)).inspect_err(|_| supported_locally = false)
+            });
+            if !supported_locally {
+                debug!("cipher {} is not supported by the local OpenSSL, skipped", cipher);
+            } else if accepted {
+                cipher_suites.push(cipher.to_string());
+            }
+        }
+        for ciphersuite in CIPHERSUITES {
+            let accepted = Self::probe(target, addr, |builder| {
+                builder.set_min_proto_version(Some(SslVersion::TLS1_3))?;
+                builder.set_ciphersuites(ciphersuite)
+            });
+            if accepted {
+                cipher_suites.push(ciphersuite.to_string());
+            }
+        }
+
+        let weaknesses = Self::find_weaknesses(&protocols, &cipher_suites, &public_key, &signature_algorithm);
+        Ok(Self {
+            domain: target.domain.clone(),
+            port: target.port,
+            address: addr.ip(),
+            negotiated,
+            protocols,
+            cipher_suites,
+            public_key,
+            signature_algorithm,
+            weaknesses
+        })
+    }
+
+    fn probe<F>(target: &CertificateCheckConfig, addr: SocketAddr, configure: F) -> bool
+    where
+        F: FnOnce(&mut SslConnectorBuilder) -> Result<(), openssl::error::ErrorStack>
+    {
+        match connect(target, addr, configure) {
+            Ok(mut stream) => {
+                let _ = stream.shutdown();
+                true
+            },
+            Err(_) => false
+        }
+    }
+
+    fn find_weaknesses(protocols: &[ProtocolSupport], cipher_suites: &[String], public_key: &PublicKeyInfo, signature_algorithm: &str) -> Vec<String> {
+        let mut weaknesses = vec![];
+        for protocol in protocols {
+            match (protocol.protocol.as_str(), protocol.supported) {
+                ("TLSv1", true) | ("TLSv1.1", true) => weaknesses.push(format!("deprecated protocol {} supported", protocol.protocol)),
+                ("TLSv1.3", false) => weaknesses.push("TLSv1.3 not supported".to_string()),
+                _ => {}
+            }
+        }
+
+        for cipher in cipher_suites.iter().filter(|c| !c.starts_with("TLS_")) {
+            if cipher.contains("NULL") || cipher.starts_with("ADH") || cipher.starts_with("AECDH") {
+                weaknesses.push(format!("insecure cipher {} (no encryption or authentication)", cipher));
+            } else if cipher.contains("RC4") || cipher.contains("DES-CBC3") || cipher.contains("MD5") {
+                weaknesses.push(format!("weak cipher {}", cipher));
+            }
+            if !cipher.starts_with("ECDHE") && !cipher.starts_with("DHE") {
+                weaknesses.push(format!("cipher {} without forward secrecy", cipher));
+            }
+        }
+
+        // EdDSA keys have a fixed size (OpenSSL reports 253 bits for Ed25519), "none" without certificate
+        let min_bits = match public_key.algorithm.as_str() {
+            "rsaEncryption" | "RSA" | "RSASSA-PSS" | "DSA" => Some(2048),
+            "id-ecPublicKey" | "EC" => Some(256),
+            _ => None
+        };
+        if min_bits.is_some_and(|min_bits| public_key.bits < min_bits) {
+            weaknesses.push(format!("{} key with {} bits is too small", public_key.algorithm, public_key.bits));
+        }
+        let signature_algorithm = signature_algorithm.to_lowercase();
+        if signature_algorithm.contains("sha1") || signature_algorithm.contains("md5") {
+            weaknesses.push(format!("weak signature algorithm {}", signature_algorithm));
+        }
+        weaknesses
+    }
+}
+
+fn key_algorithm_name(id: Id) -> String {
+    Nid::from_raw(id.as_raw()).short_name().unwrap_or("unknown").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_weaknesses() {
+        let protocols = vec![
+            ProtocolSupport { protocol: "TLSv1".to_string(), supported: true },
+            ProtocolSupport { protocol: "TLSv1.2".to_string(), supported: true },
+            ProtocolSupport { protocol: "TLSv1.3".to_string(), supported: false }
+        ];
+        let cipher_suites = vec!["ECDHE-RSA-AES128-GCM-SHA256".to_string(), "AES128-SHA".to_string(), "DES-CBC3-SHA".to_string()];
+        let public_key = PublicKeyInfo { algorithm: "rsaEncryption".to_string(), bits: 1024 };
+        let weaknesses = TLSConfigurationReport::find_weaknesses(&protocols, &cipher_suites, &public_key, "sha1WithRSAEncryption");
+        assert_eq!(weaknesses, vec![
+            "deprecated protocol TLSv1 supported",
+            "TLSv1.3 not supported",
+            "cipher AES128-SHA without forward secrecy",
+            "weak cipher DES-CBC3-SHA",
+            "cipher DES-CBC3-SHA without forward secrecy",
+            "rsaEncryption key with 1024 bits is too small",
+            "weak signature algorithm sha1withrsaencryption"
+        ]);
+
+        let public_key = PublicKeyInfo { algorithm: key_algorithm_name(Id::RSA_PSS), bits: 1024 };
+        assert_eq!(TLSConfigurationReport::find_weaknesses(&[], &[], &public_key, "rsassaPss"), vec!["RSASSA-PSS key with 1024 bits is too small"]);
+    }
+
+    #[test]
+    fn no_weaknesses() {
+        let protocols = vec![
+            ProtocolSupport { protocol: "TLSv1".to_string(), supported: false },
+            ProtocolSupport { protocol: "TLSv1.3".to_string(), supported: true }
+        ];
+        let cipher_suites = vec!["ECDHE-ECDSA-AES256-GCM-SHA384".to_string(), "TLS_AES_256_GCM_SHA384".to_string()];
+        let public_key = PublicKeyInfo { algorithm: "id-ecPublicKey".to_string(), bits: 256 };
+        assert!(TLSConfigurationReport::find_weaknesses(&protocols, &cipher_suites, &public_key, "ecdsa-with-SHA256").is_empty());
+        let public_key = PublicKeyInfo { algorithm: key_algorithm_name(Id::ED25519), bits: 253 };
+        assert!(TLSConfigurationReport::find_weaknesses(&protocols, &cipher_suites, &public_key, "ED25519").is_empty());
+        // no certificate presented
+        let public_key = PublicKeyInfo { algorithm: "none".to_string(), bits: 0 };
+        assert!(TLSConfigurationReport::find_weaknesses(&protocols, &cipher_suites, &public_key, "none").is_empty());
+    }
+}
@@ -0,0 +1,178 @@
+/**
+ * network-journal - collect network reports and print them to file
+ * Copyright (C) 2025 nerou GmbH
+ * 
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::{str::FromStr, thread::sleep, time::Duration};
+
+use chrono::{DateTime, Utc};
+
+/**
+ * When to run a recurring task, either in a fixed interval or at the times of a cron expression
+ */
+#[derive(Clone, Debug)]
+pub enum Schedule {
+    Interval(Duration),
+    Cron(Box<cron::Schedule>)
+}
+
+impl Schedule {
+
+    /**
+     * Cron expressions use the traditional five fields (minute, hour, day of month, month, day of week), 
+     * but may have additional leading seconds and trailing year fields.
+     * Like in crontab, days of week are numbered from 0 to 7, 0 and 7 being Sunday.
+     */
+    pub fn new(interval: Option<u64>, cron_expression: Option<&str>) -> Result<Self, cron::error::Error> {
+        match cron_expression {
+            Some(expression) => {
+                let mut fields: Vec<String> = expression.split_whitespace().map(|f| f.to_string()).collect();
+                if fields.len() == 5 {
+                    fields.insert(0, "0".to_string());
+                }
+                if let Some(day_of_week) = fields.get_mut(5) {
+                    *day_of_week = translate_day_of_week(day_of_week);
+                }
+                Ok(Self::Cron(Box::new(cron::Schedule::from_str(&fields.join(" "))?)))
+            },
+            None => Ok(Self::Interval(Duration::from_secs(interval.unwrap_or(86400))))
+        }
+    }
+
+    /**
+     * Returns the time of the next run, which is now, if it has never run before or a run has been missed (e.g. during downtime)
+     */
+    pub fn next_run(&self, last_run: Option<DateTime<Utc>>, now: DateTime<Utc>) -> DateTime<Utc> {
+        let Some(last_run) = last_run else {
+            return now;
+        };
+        let next_run = match self {
+            Self::Interval(interval) => last_run + *interval,
+            Self::Cron(schedule) => match schedule.after(&last_run).next() {
+                Some(next_run) => next_run,
+                // the expression has no future dates (e.g. because of a year field)
+                None => DateTime::<Utc>::MAX_UTC
+            }
+        };
+        next_run.max(now)
+    }
+
+    /**
+     * Runs the task according to this schedule forever, starting right away
+     */
+    pub fn run<F: FnMut()>(&self, mut task: F) {
+        let mut last_run = None;
+        loop {
+            let now = Utc::now();
+            if let Ok(wait) = (self.next_run(last_run, now) - now).to_std() {
+                sleep(wait);
+            }
+            last_run = Some(Utc::now());
+            task();
+        }
+    }
+}
+
+/**
+ * The cron crate numbers days of week from 1 (Sunday) to 7 (Saturday), so numeric days are translated, names are kept
+ */
+fn translate_day_of_week(field: &str) -> String {
+    let mut items: Vec<String> = vec![];
+    for item in field.split(',') {
+        let translated = match translate_days(item) {
+            Some(days) => days.iter().map(|day| day.to_string()).collect(),
+            None => vec![item.to_string()]
+        };
+        for item in translated {
+            if !items.contains(&item) {
+                items.push(item);
+            }
+        }
+    }
+    items.join(",")
+}
+
+/**
+ * Numeric days (e.g. 5, 1-5 or 1-5/2) and steps of all days as days of the cron crate, none for anything else
+ */
+fn translate_days(item: &str) -> Option<Vec<u8>> {
+    let (range, step) = match item.split_once('/') {
+        Some((range, step)) => (range, Some(step.parse::<usize>().ok().filter(|s| *s > 0)?)),
+        None => (item, None)
+    };
+    let (start, end): (u8, u8) = match (range, range.split_once('-'), step) {
+        ("*", _, Some(_)) => (0, 6),
+        (_, Some((start, end)), _) => (start.parse().ok()?, end.parse().ok()?),
+        // from the given day until Saturday
+        (_, None, Some(_)) => (range.parse().ok()?, 6),
+        (_, None, None) => (range.parse().ok()?, range.parse().ok()?)
+    };
+    if start > end || end > 7 {
+        return None;
+    }
+    let mut days: Vec<u8> = (start..=end).step_by(step.unwrap_or(1)).map(|day| day % 7 + 1).collect();
+    days.sort();
+    days.dedup();
+    Some(days)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn interval() {
+        let schedule = Schedule::new(Some(3600), None).unwrap();
+        let now = Utc.with_ymd_and_hms(2026, 1, 1, 12, 0, 0).unwrap();
+        assert_eq!(schedule.next_run(None, now), now);
+        assert_eq!(schedule.next_run(Some(now - Duration::from_secs(600)), now), now + Duration::from_secs(3000));
+        // missed while not running
+        assert_eq!(schedule.next_run(Some(now - Duration::from_secs(7200)), now), now);
+    }
+
+    #[test]
+    fn cron() {
+        let schedule = Schedule::new(Some(3600), Some("30 6 * * *")).unwrap();
+        let now = Utc.with_ymd_and_hms(2026, 1, 1, 12, 0, 0).unwrap();
+        assert_eq!(schedule.next_run(Some(Utc.with_ymd_and_hms(2026, 1, 1, 6, 30, 0).unwrap()), now), Utc.with_ymd_and_hms(2026, 1, 2, 6, 30, 0).unwrap());
+        assert_eq!(schedule.next_run(Some(Utc.with_ymd_and_hms(2025, 12, 31, 6, 30, 0).unwrap()), now), now);
+        assert!(Schedule::new(None, Some("every day")).is_err());
+    }
+
+    #[test]
+    fn cron_day_of_week() {
+        // Thursday
+        let now = Utc.with_ymd_and_hms(2026, 1, 1, 12, 0, 0).unwrap();
+        let next_run = |expression: &str| Schedule::new(None, Some(expression)).unwrap().next_run(Some(now), now);
+        let monday = Utc.with_ymd_and_hms(2026, 1, 5, 6, 0, 0).unwrap();
+        let sunday = Utc.with_ymd_and_hms(2026, 1, 4, 6, 0, 0).unwrap();
+        assert_eq!(next_run("0 6 * * 1"), monday);
+        assert_eq!(next_run("0 6 * * MON"), monday);
+        assert_eq!(next_run("0 6 * * 0"), sunday);
+        assert_eq!(next_run("0 6 * * 7"), sunday);
+        assert_eq!(next_run("0 6 * * 1-3"), monday);
+        assert_eq!(next_run("0 6 * * 5-7"), Utc.with_ymd_and_hms(2026, 1, 2, 6, 0, 0).unwrap());
+        assert_eq!(next_run("0 0 6 * * 1,3 2026"), monday);
+
+        assert_eq!(translate_day_of_week("*"), "*");
+        assert_eq!(translate_day_of_week("*/2"), "1,3,5,7");
+        assert_eq!(translate_day_of_week("1-5/2,SUN"), "2,4,6,SUN");
+        assert_eq!(translate_day_of_week("0,7"), "1");
+        assert_eq!(translate_day_of_week("1/2"), "2,4,6");
+    }
+}
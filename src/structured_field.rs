@@ -0,0 +1,124 @@
+/**
+ * network-journal - collect network reports and print them to file
+ * Copyright (C) 2025 nerou GmbH
+ * 
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+/**
+ * Splits at the separator unless it is part of a quoted string or inner list
+ */
+pub fn split_top_level(value: &str, separator: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let (mut quoted, mut depth, mut start) = (false, 0, 0);
+    for (i, c) in value.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth -= 1,
+            c if c == separator && !quoted && depth == 0 => {
+                parts.push(value[start..i].trim());
+                start = i + 1;
+            },
+            _ => {}
+        }
+    }
+    parts.push(value[start..].trim());
+    parts.into_iter().filter(|p| !p.is_empty()).collect()
+}
+
+/**
+ * Simplified parser of structured field dictionaries (RFC 8941), parameters of members are ignored
+ */
+pub fn parse_dictionary(value: &str) -> Vec<(String, String)> {
+    split_top_level(value, ',').into_iter()
+        .map(|member| {
+            let member = split_top_level(member, ';').into_iter().next().unwrap_or_default();
+            match member.split_once('=') {
+                Some((key, value)) => (key.trim().to_string(), value.trim().to_string()),
+                None => (member.to_string(), "?1".to_string())
+            }
+        })
+        .collect()
+}
+
+/**
+ * Splits a structured field item into its bare item and parameters
+ */
+pub fn parse_item(value: &str) -> (String, Vec<(String, String)>) {
+    let mut parts = split_top_level(value, ';').into_iter();
+    let item = parts.next().unwrap_or_default().to_string();
+    let params = parts
+        .map(|param| match param.split_once('=') {
+            Some((key, value)) => (key.trim().to_string(), value.trim().to_string()),
+            None => (param.to_string(), "?1".to_string())
+        })
+        .collect();
+    (item, params)
+}
+
+pub fn parse_inner_list(value: &str) -> Vec<String> {
+    value.trim().trim_start_matches('(').trim_end_matches(')')
+        .split_whitespace()
+        .map(unquote)
+        .collect()
+}
+
+pub fn unquote(value: &str) -> String {
+    value.trim().trim_matches('"').to_string()
+}
+
+/**
+ * Parses a structured field list (RFC 8941) of items with parameters
+ */
+pub fn parse_list(value: &str) -> Vec<(String, Vec<(String, String)>)> {
+    split_top_level(value, ',').into_iter()
+        .map(parse_item)
+        .collect()
+}
+
+/**
+ * Parses a structured field boolean (`?1` or `?0`)
+ */
+pub fn parse_boolean(value: &str) -> Option<bool> {
+    match value.trim() {
+        "?1" => Some(true),
+        "?0" => Some(false),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_structured_fields() {
+        assert_eq!(parse_dictionary(r#"csp-endpoint="https://a.example/r", default="https://b.example/r,x""#), vec![
+            ("csp-endpoint".to_string(), "\"https://a.example/r\"".to_string()),
+            ("default".to_string(), "\"https://b.example/r,x\"".to_string())
+        ]);
+        assert_eq!(parse_dictionary("blocked-destinations=(script style), endpoints=(integrity-endpoint)")[1].1, "(integrity-endpoint)");
+        assert_eq!(parse_inner_list("(script style)"), vec!["script", "style"]);
+        let (item, params) = parse_item(r#"same-origin; report-to="coop""#);
+        assert_eq!(item, "same-origin");
+        assert_eq!(params, vec![("report-to".to_string(), "\"coop\"".to_string())]);
+        assert_eq!(parse_list(r#""Chromium";v="124", "Not-A.Brand";v="99""#), vec![
+            ("\"Chromium\"".to_string(), vec![("v".to_string(), "\"124\"".to_string())]),
+            ("\"Not-A.Brand\"".to_string(), vec![("v".to_string(), "\"99\"".to_string())])
+        ]);
+        assert_eq!(parse_boolean("?1"), Some(true));
+        assert_eq!(parse_boolean("1"), None);
+    }
+}